@@ -0,0 +1,30 @@
+#!/bin/sh
+# Generate CA, server and client certificates for testing TLS, in the
+# same way as `utils/gen-test-certs.sh` of Redis. Files are written to
+# `tests/tls` (or the directory given as 1st argument) :
+#
+#   ca.crt / ca.key           self-signed CA which signs the others
+#   server.crt / server.key   for `--tls-cert-file` / `--tls-key-file`
+#   client.crt / client.key   for clients when `tls-auth-clients` is enabled
+set -e
+dir=${1:-tests/tls}
+mkdir -p "$dir"
+
+openssl genrsa -out "$dir/ca.key" 2048
+openssl req -x509 -new -nodes -sha256 -key "$dir/ca.key" -days 365 \
+    -subj "/O=mini-redis/CN=Certificate Authority" -out "$dir/ca.crt"
+
+gen_cert() {
+    name=$1
+    usage=$2
+    openssl genrsa -out "$dir/$name.key" 2048
+    openssl req -new -sha256 -key "$dir/$name.key" \
+        -subj "/O=mini-redis/CN=localhost" -out "$dir/$name.csr"
+    printf "keyUsage = digitalSignature, keyEncipherment\nextendedKeyUsage = %s\nsubjectAltName = DNS:localhost, IP:127.0.0.1\n" \
+        "$usage" > "$dir/$name.ext"
+    openssl x509 -req -sha256 -in "$dir/$name.csr" -CA "$dir/ca.crt" -CAkey "$dir/ca.key" \
+        -CAcreateserial -days 365 -extfile "$dir/$name.ext" -out "$dir/$name.crt"
+    rm "$dir/$name.csr" "$dir/$name.ext"
+}
+gen_cert server serverAuth
+gen_cert client clientAuth
@@ -0,0 +1,369 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as FmtWrite;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::glob_match;
+
+pub const DEFAULT_USER:&str = "default";
+
+// commands a client can run before it is authenticated
+const NOAUTH_COMMANDS:[&str; 3] = ["auth", "quit", "reset"];
+
+// categories of each command, named after the ACL categories of Redis.
+// Commands missing here are only allowed by `+@all` or by name.
+const CATEGORIES:[(&str, &[&str]); 40] = [
+    ("get", &["read", "keyspace"]),
+    ("set", &["write", "keyspace"]),
+    ("del", &["write", "keyspace"]),
+    ("publish", &["pubsub"]),
+    ("spublish", &["pubsub"]),
+    ("subscribe", &["pubsub"]),
+    ("ssubscribe", &["pubsub"]),
+    ("unsubscribe", &["pubsub"]),
+    ("sunsubscribe", &["pubsub"]),
+    ("psubscribe", &["pubsub"]),
+    ("punsubscribe", &["pubsub"]),
+    ("ping", &["connection"]),
+    ("quit", &["connection"]),
+    ("reset", &["connection"]),
+    ("auth", &["connection"]),
+    ("asking", &["connection"]),
+    ("save", &["admin", "dangerous"]),
+    ("bgsave", &["admin", "dangerous"]),
+    ("bgrewriteaof", &["admin", "dangerous"]),
+    ("replicaof", &["admin", "dangerous"]),
+    ("role", &["admin", "dangerous"]),
+    ("psync", &["admin", "dangerous"]),
+    ("replconf", &["admin", "dangerous"]),
+    ("cluster", &["admin", "dangerous"]),
+    ("config", &["admin", "dangerous"]),
+    ("shutdown", &["admin", "dangerous"]),
+    ("acl", &["admin", "dangerous"]),
+    ("info", &["dangerous"]),
+    ("slowlog", &["admin", "dangerous"]),
+    ("latency", &["admin", "dangerous"]),
+    ("monitor", &["admin", "dangerous"]),
+    ("client", &["admin", "dangerous"]),
+    ("object", &["read", "keyspace"]),
+    ("memory", &["read"]),
+    ("select", &["connection"]),
+    ("dbsize", &["read", "keyspace"]),
+    ("move", &["write", "keyspace"]),
+    ("swapdb", &["write", "keyspace", "dangerous"]),
+    ("flushdb", &["write", "keyspace", "dangerous"]),
+    ("flushall", &["write", "keyspace", "dangerous"]),
+];
+
+fn invalid(detail:String) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, detail)
+}
+
+// passwords are never kept in plain text, only the hex-encoded SHA-256
+fn hash_password(password:&str) -> String {
+    Sha256::digest(password.as_bytes()).iter()
+        .fold(String::with_capacity(64), |mut out, b| {
+            let _ = write!(out, "{:02x}", b);
+            out
+        })
+}
+
+// A user is built from rules, same syntax as `ACL SETUSER` of Redis :
+// - `on` / `off` enables or disables the user
+// - `>password` / `<password` adds or removes a password, `#hash` / `!hash`
+//   does the same with already hashed password, `nopass` accepts any
+//   password, `resetpass` forgets all of them
+// - `+cmd` / `-cmd` / `+@category` / `-@category` allows or denies
+//   commands, `allcommands` and `nocommands` are aliases of `+@all` and `-@all`
+// - `~pattern` adds glob-style pattern of keys the user can access,
+//   `allkeys` is alias of `~*`, `resetkeys` forgets all patterns
+// - `reset` brings the user back to the state of a new user
+#[derive(Debug, Clone, Default)]
+pub struct User {
+    enabled: bool,
+    nopass: bool,
+    passwords: BTreeSet<String>,
+    // if set, all commands except the denied ones, otherwise only the
+    // allowed ones
+    all_commands: bool,
+    allowed: BTreeSet<String>,
+    denied: BTreeSet<String>,
+    key_patterns: Vec<String>,
+}
+
+impl User {
+    pub fn apply_rule(&mut self, rule:&str) -> IoResult<()> {
+        let lower = rule.to_lowercase();
+        match lower.as_str() {
+            "on" => { self.enabled = true; },
+            "off" => { self.enabled = false; },
+            "nopass" => { self.nopass = true; self.passwords.clear(); },
+            "resetpass" => { self.nopass = false; self.passwords.clear(); },
+            "allcommands" | "+@all" => self.set_all_commands(true),
+            "nocommands" | "-@all" => self.set_all_commands(false),
+            "allkeys" => { self.key_patterns = vec!["*".to_string()]; },
+            "resetkeys" => { self.key_patterns.clear(); },
+            "reset" => { *self = Self::default(); },
+            _others => match rule.split_at(rule.chars().next().map_or(0, char::len_utf8)) {
+                (">", pass) => {
+                    self.passwords.insert(hash_password(pass));
+                    self.nopass = false;
+                },
+                ("<", pass) => { self.passwords.remove(&hash_password(pass)); },
+                ("#", hash) => {
+                    let hash = hash.to_lowercase();
+                    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(invalid("The password hash must be exactly 64 characters \
+                                            and contain only lowercase hexadecimal characters".to_string()));
+                    }
+                    self.passwords.insert(hash);
+                    self.nopass = false;
+                },
+                ("!", hash) => { self.passwords.remove(&hash.to_lowercase()); },
+                ("~", pattern) => {
+                    if !self.key_patterns.iter().any(|p| p == "*") {
+                        self.key_patterns.push(pattern.to_string());
+                    }
+                },
+                ("+", name) => self.update_commands(&name.to_lowercase(), true)?,
+                ("-", name) => self.update_commands(&name.to_lowercase(), false)?,
+                _others => return Err(invalid(format!("Syntax error in ACL rule '{}'", rule))),
+            },
+        }
+        Ok(())
+    } // end of apply_rule
+
+    fn set_all_commands(&mut self, allow:bool) {
+        self.all_commands = allow;
+        self.allowed.clear();
+        self.denied.clear();
+    }
+
+    fn update_commands(&mut self, name:&str, allow:bool) -> IoResult<()> {
+        let names:Vec<&str> = match name.strip_prefix('@') {
+            Some(category) => {
+                let names:Vec<&str> = CATEGORIES.iter()
+                    .filter(|(_, cats)| cats.contains(&category))
+                    .map(|(cmd, _)| *cmd).collect();
+                if names.is_empty() {
+                    return Err(invalid(format!("Unknown command category '{}'", category)));
+                }
+                names
+            },
+            None => vec![name],
+        };
+        for n in names {
+            match (self.all_commands, allow) {
+                (true, true) => { self.denied.remove(n); },
+                (true, false) => { self.denied.insert(n.to_string()); },
+                (false, true) => { self.allowed.insert(n.to_string()); },
+                (false, false) => { self.allowed.remove(n); },
+            }
+        }
+        Ok(())
+    }
+
+    fn check_password(&self, password:&str) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&hash_password(password)))
+    }
+
+    fn can_run(&self, cmd:&str) -> bool {
+        if self.all_commands {
+            !self.denied.contains(cmd)
+        } else {
+            self.allowed.contains(cmd)
+        }
+    }
+
+    fn can_access(&self, key:&str) -> bool {
+        self.key_patterns.iter().any(|p| glob_match(p.as_bytes(), key.as_bytes()))
+    }
+
+    // rules which rebuild the same user, this is what `ACL LIST` shows
+    // and what the ACL file stores
+    pub fn describe(&self) -> String {
+        let mut rules = vec![(if self.enabled {"on"} else {"off"}).to_string()];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|h| format!("#{}", h)));
+        if self.key_patterns.is_empty() {
+            rules.push("resetkeys".to_string());
+        }
+        rules.extend(self.key_patterns.iter().map(|p| format!("~{}", p)));
+        if self.all_commands {
+            rules.push("+@all".to_string());
+            rules.extend(self.denied.iter().map(|c| format!("-{}", c)));
+        } else {
+            rules.push("-@all".to_string());
+            rules.extend(self.allowed.iter().map(|c| format!("+{}", c)));
+        }
+        rules.join(" ")
+    }
+} // end of User
+
+// the user every connection starts with, allowed to do everything until
+// `requirepass` is set
+fn default_user() -> User {
+    let mut user = User::default();
+    for rule in ["on", "nopass", "allkeys", "allcommands"] {
+        let _ = user.apply_rule(rule);
+    }
+    user
+}
+
+// shared by all connections through `FakeDatabase`
+pub struct Acl {
+    users: Mutex<BTreeMap<String, User>>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        let users = BTreeMap::from([(DEFAULT_USER.to_string(), default_user())]);
+        Self{ users: Mutex::new(users) }
+    }
+}
+
+impl Acl {
+    fn lock(&self) -> IoResult<MutexGuard<'_, BTreeMap<String, User>>> {
+        self.users.lock().map_err(|_| {
+            IoError::new(ErrorKind::ResourceBusy, "failed to acquire acl lock")
+        })
+    }
+
+    // user of a new connection, `None` means the client has to send
+    // `AUTH` first
+    pub fn initial_user(&self) -> Option<String> {
+        let users = self.lock().ok()?;
+        users.get(DEFAULT_USER).filter(|u| u.enabled && u.nopass)
+            .map(|_| DEFAULT_USER.to_string())
+    }
+
+    // whether `AUTH password` without user name makes sense
+    pub fn default_user_has_password(&self) -> bool {
+        self.lock().ok().and_then(|users| users.get(DEFAULT_USER).map(|u| !u.nopass))
+            .unwrap_or(false)
+    }
+
+    pub fn authenticate(&self, name:&str, password:&str) -> bool {
+        self.lock().ok().and_then(|users| users.get(name).map(|u| u.check_password(password)))
+            .unwrap_or(false)
+    }
+
+    // `requirepass` is a shortcut for the password of the default user
+    pub fn set_default_password(&self, password:Option<&str>) -> IoResult<()> {
+        let mut users = self.lock()?;
+        let user = users.entry(DEFAULT_USER.to_string()).or_insert_with(default_user);
+        user.apply_rule("resetpass")?;
+        match password {
+            Some(p) => user.apply_rule(&format!(">{}", p)),
+            None => user.apply_rule("nopass"),
+        }
+    }
+
+    // create the user if it does not exist, nothing is changed if any of
+    // the rules is invalid
+    pub fn set_user(&self, name:&str, rules:&[String]) -> IoResult<()> {
+        let mut users = self.lock()?;
+        let mut user = users.get(name).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply_rule(rule)?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    // returns number of users deleted
+    pub fn del_users(&self, names:&[String]) -> IoResult<usize> {
+        if names.iter().any(|n| n == DEFAULT_USER) {
+            return Err(invalid("The 'default' user cannot be removed".to_string()));
+        }
+        let mut users = self.lock()?;
+        Ok(names.iter().filter(|n| users.remove(n.as_str()).is_some()).count())
+    }
+
+    // `user <name> <rules>` for each user
+    pub fn list(&self) -> IoResult<Vec<String>> {
+        let users = self.lock()?;
+        Ok(users.iter().map(|(name, u)| format!("user {} {}", name, u.describe())).collect())
+    }
+
+    pub fn users(&self) -> IoResult<Vec<String>> {
+        Ok(self.lock()?.keys().cloned().collect())
+    }
+
+    // error message sent back to the client if the user cannot run the
+    // command, or access any of the keys
+    pub fn check(&self, user:Option<&str>, cmd:&str, keys:&[&str]) -> Option<String> {
+        let name = match user {
+            Some(n) => n,
+            None if NOAUTH_COMMANDS.contains(&cmd) => return None,
+            None => return Some("NOAUTH Authentication required.".to_string()),
+        };
+        let users = match self.lock() {
+            Ok(u) => u,
+            Err(e) => return Some(format!("ERR {}", e)),
+        };
+        let user = match users.get(name) {
+            Some(u) if u.enabled => u,
+            // deleted or disabled after the client authenticated
+            _others if NOAUTH_COMMANDS.contains(&cmd) => return None,
+            _others => return Some("NOAUTH Authentication required.".to_string()),
+        };
+        if !user.can_run(cmd) {
+            return Some(format!(
+                "NOPERM User {} has no permissions to run the '{}' command", name, cmd));
+        }
+        if keys.iter().any(|k| !user.can_access(k)) {
+            return Some("NOPERM No permissions to access a key".to_string());
+        }
+        None
+    }
+
+    // replace all users with the ones in the file, one `user <name>
+    // <rules>` per line. The default user is kept if the file does not
+    // mention it. Nothing is changed if any line is invalid.
+    pub fn load_file(&self, path:impl AsRef<Path>) -> IoResult<usize> {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        let mut loaded = BTreeMap::new();
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let name = match (words.next(), words.next()) {
+                (Some("user"), Some(name)) => name,
+                _others => return Err(invalid(format!(
+                    "{} line {}, should start with user keyword", path.as_ref().display(), lineno + 1))),
+            };
+            let mut user = User::default();
+            for rule in words {
+                user.apply_rule(rule).map_err(|e| invalid(format!(
+                    "{} line {}, {}", path.as_ref().display(), lineno + 1, e)))?;
+            }
+            loaded.insert(name.to_string(), user);
+        }
+        let num_loaded = loaded.len();
+        let mut users = self.lock()?;
+        if !loaded.contains_key(DEFAULT_USER) {
+            let dflt = users.remove(DEFAULT_USER).unwrap_or_else(default_user);
+            loaded.insert(DEFAULT_USER.to_string(), dflt);
+        }
+        *users = loaded;
+        Ok(num_loaded)
+    }
+
+    // write to temporary file then rename, the file is never left half-written
+    pub fn save_file(&self, path:impl AsRef<Path>) -> IoResult<()> {
+        let mut content = self.list()?.join("\n");
+        content.push('\n');
+        let mut tmp_path = path.as_ref().as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, path.as_ref())
+    }
+} // end of Acl
@@ -5,105 +5,42 @@ use std::collections::{HashMap};
 // network transferring
 use bytes::Bytes;
 use tokio::time::sleep;
-use tokio::sync::{mpsc, oneshot};
 use tokio_stream::StreamExt as TokioStreamExt;
 
 use mini_redis_demo::{DEFAULT_PORT, AsyncResult};
-use mini_redis_demo::clients::{Message as SubsMessage, Client};
-
-type Responder<T> = oneshot::Sender<AsyncResult<T>>;
-
-#[derive(Debug)]
-enum KeyValCommand {
-    GET {resp:Responder<Option<Bytes>>, key:String, },
-    SET {resp:Responder<()>, key:String, val:Bytes},
-}
-async fn kv_exp_sender(tx:&mpsc::Sender<KeyValCommand>, k:&str, unchk_val:Option<&str>)
-    -> Option<String>
-{
-    let k = k.to_string();
-    if let Some(v) = unchk_val {
-        let (resp_tx, _resp_rx) = oneshot::channel();
-        let cmd = KeyValCommand::SET {
-            key:k, resp:resp_tx, val:Bytes::from(v.to_string()), // v.into()
-        };
-        let _ = tx.send(cmd).await;
-        Some(String::from("ok"))
-    } else { // the `resp_tx` has different sizes for GET/SET command
-             // they have to be declared in distinct scopes to let compiler
-             // understand that it is safe to go
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let cmd = KeyValCommand::GET {key:k, resp:resp_tx};
-        let _ = tx.send(cmd).await;
-        // receiver of oneshot channel doesn't have `recv()`, simply call
-        // `await` to get result
-        if let Ok(result) = resp_rx.await {
-            let result:Option<Bytes> = result.unwrap();
-            if let Some(rawbytes) = result {
-                // in this example I only insert printable bytes, all bytes read
-                // from the client connection can be safely converted to ASCII
-                // characters
-                Some(rawbytes.escape_ascii().to_string())
-            } else {
-                None
-            }
-        } else {
-            println!("error from oneshot receiver");
-            None
-        }
-    }
-} // end of kv_exp_sender
-
-async fn kv_exp_receiver(mut rx:mpsc::Receiver<KeyValCommand>)
-{
-    let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
-    let mut myc = Client::connect(url).await.unwrap();
-    while let Some(cmd) = rx.recv().await {
-        match cmd {
-            KeyValCommand::GET{key,resp} => {
-                let result = myc.get(&key).await;
-                let _ = resp.send(result);
-            },
-            KeyValCommand::SET{val,key,resp} => {
-                let result = myc.set(&key, val).await;
-                let _ = resp.send(result);
-            },
-            // 1. let command senders handle their errors if it exists in the result
-            // 2. sender of oneshot channel doesn't need `await` cuz it fails or
-            //    succeeds immediately after the call to `send(...)`
-        };
-    } // end of loop
-} // end of kv_exp_receiver
+use mini_redis_demo::clients::{Message as SubsMessage, SubscriberEvent, Client, Pool, PoolConfig};
+use mini_redis_demo::logging::{self, LogFormat};
 
 // macro below expands the function below to synchronous main
 // function calling asynchronous main function.
 #[tokio::main]
 async fn main() ->  AsyncResult<()> {
-    let (tx, rx) = mpsc::channel(29);
-    let tx2 = tx.clone();
-    let manager = tokio::spawn(async move {
-        kv_exp_receiver(rx).await;
-    });
+    // events of the client library, e.g. `RUST_LOG=mini_redis_demo=debug`
+    logging::init("warning", LogFormat::Text)?;
+    // tasks below share connections of the pool, each command checks out
+    // a connection then returns it right after the reply
+    let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
+    let cfg = PoolConfig{ min_size: 1, max_size: 2, ..Default::default() };
+    let pool = Pool::new(&url, cfg).await?;
+    let pool2 = pool.clone();
     let tsk1 = tokio::spawn(async move {
-        let _tx = tx;
-        let _ = kv_exp_sender(&_tx, "halo", Some("code ocean")).await;
-        let result = kv_exp_sender(&_tx, "halo", None).await;
-        assert_eq!(result.unwrap().as_str(), "code ocean");
-        let result = kv_exp_sender(&_tx, "not-exists", None).await;
+        pool.set("halo", "code ocean".into()).await.unwrap();
+        let result = pool.get("halo").await.unwrap();
+        assert_eq!(result, Some(Bytes::from("code ocean")));
+        let result = pool.get("not-exists").await.unwrap();
         assert_eq!(result, None);
     });
     let tsk2 = tokio::spawn(async move {
-        let _tx = tx2;
-        kv_exp_sender(&_tx, "mineral", Some("electricity")).await;
-        kv_exp_sender(&_tx, "museum", Some("gallery")).await;
-        let result = kv_exp_sender(&_tx, "mineral", None).await;
-        assert_eq!(result.unwrap().as_str(), "electricity");
-        kv_exp_sender(&_tx, "halo", Some("in-depth knowledge")).await;
-        let result = kv_exp_sender(&_tx, "halo", None).await;
-        assert_eq!(result.unwrap().as_str(), "in-depth knowledge");
+        let pool = pool2;
+        pool.set("mineral", "electricity".into()).await.unwrap();
+        pool.set("museum", "gallery".into()).await.unwrap();
+        let result = pool.get("mineral").await.unwrap();
+        assert_eq!(result, Some(Bytes::from("electricity")));
+        pool.set("halo", "in-depth knowledge".into()).await.unwrap();
+        let result = pool.get("halo").await.unwrap();
+        assert_eq!(result, Some(Bytes::from("in-depth knowledge")));
     });
-    manager.await.unwrap(); // check the result by trying to unwrap the `result`
-    tsk1.await.unwrap();
+    tsk1.await.unwrap(); // check the result by trying to unwrap the `result`
     tsk2.await.unwrap();
     { // reconnect
         let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
@@ -177,7 +114,19 @@ async fn main() ->  AsyncResult<()> {
         expect_recv_data.insert("halo".to_string(), data);
         let _ = _run_subscription(&expect_recv_data, &mut myc).await;
         let data = ["halo".to_string()];
-        let _ = myc.unsubscribe(&data).await;
+        // the client is still in subscriber mode, no more channel to add
+        let mut onesubs = myc.subscribe(vec![]).await.unwrap();
+        let result = onesubs.ping(Some("in stream".into())).await.unwrap();
+        assert_eq!(result, Bytes::from("in stream"));
+        myc.unsubscribe(&data).await.unwrap();
+        assert_eq!(myc.get_subscribed(), &["food".to_string()]);
+        // leave subscriber mode, the connection accepts other commands again
+        myc.unsubscribe(&[]).await.unwrap();
+        assert!(myc.get_subscribed().is_empty());
+        let result = myc.get("halo").await.unwrap();
+        assert_eq!(result, Some(Bytes::from("blooming")));
+        let result = myc.ping(None).await.unwrap();
+        assert_eq!(result, Bytes::from("PONG"));
     });
     let publisher3 = tokio::spawn(async {
         let pairs = vec![("food", "Kofta"), ("halo", "Ichi"), ("food", "Lepet")];
@@ -188,6 +137,65 @@ async fn main() ->  AsyncResult<()> {
     });
     subscriber3.await.unwrap();
     publisher3.await.unwrap();
+    let shard_subscriber = tokio::spawn(async {
+        let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
+        let mut myc = Client::connect(url).await .unwrap();
+        // shard channels are in separate namespace from `food` above
+        let mut onesubs = myc.ssubscribe(vec!["food".to_string()]).await.unwrap();
+        let mut actual_recv_data = vec![];
+        while actual_recv_data.len() < 2 {
+            match onesubs.next_message().await.unwrap() {
+                Some(SubscriberEvent::ShardMessage(m)) => actual_recv_data.push(m.content),
+                Some(_others) => {},
+                None => break,
+            }
+        }
+        assert_eq!(actual_recv_data, ["bagel", "naan"]);
+        onesubs.sunsubscribe(&[]).await.unwrap();
+        assert!(myc.get_shard_subscribed().is_empty());
+    });
+    let shard_publisher = tokio::spawn(async {
+        sleep(Duration::new(1, 0)).await;
+        let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
+        let mut myc = Client::connect(url).await .unwrap();
+        for msg in ["bagel", "naan"] {
+            let num_subs = myc.spublish("food", Bytes::from(msg)).await.unwrap();
+            assert_eq!(num_subs, 1);
+        }
+    });
+    shard_subscriber.await.unwrap();
+    shard_publisher.await.unwrap();
+    let pattern_subscriber = tokio::spawn(async {
+        let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
+        let mut myc = Client::connect(url).await .unwrap();
+        let mut onesubs = myc.psubscribe(vec!["dish.*".to_string()]).await.unwrap();
+        let mut actual_recv_data = vec![];
+        while actual_recv_data.len() < 2 {
+            match onesubs.next_message().await.unwrap() {
+                Some(SubscriberEvent::PatternMessage{pattern, message}) => {
+                    assert_eq!(pattern, "dish.*");
+                    actual_recv_data.push((message.channel, message.content));
+                },
+                Some(_others) => {},
+                None => break,
+            }
+        }
+        assert_eq!(actual_recv_data, [("dish.main".to_string(), "biryani".to_string()),
+                                      ("dish.side".to_string(), "raita".to_string())]);
+        onesubs.punsubscribe(&[]).await.unwrap();
+        assert!(myc.get_pattern_subscribed().is_empty());
+    });
+    let pattern_publisher = tokio::spawn(async {
+        sleep(Duration::new(1, 0)).await;
+        let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
+        let mut myc = Client::connect(url).await .unwrap();
+        for (chn, msg) in [("dish.main", "biryani"), ("dish.side", "raita")] {
+            let num_subs = myc.publish(chn, Bytes::from(msg)).await.unwrap();
+            assert_eq!(num_subs, 1);
+        }
+    });
+    pattern_subscriber.await.unwrap();
+    pattern_publisher.await.unwrap();
     let subscriber_hang = tokio::spawn(async {
         let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
         let mut myc = Client::connect(url).await .unwrap();
@@ -251,13 +259,18 @@ async fn _run_subscription_receive_streaming_message (
     // the stream object below is implemented using `TokioStreamExt` trait
     let _stream  = onesubs.into_stream();
     // the syntax `impl Trait` is allowed ONLY in function parameters, not closure
-    let discard_empty = |obj:&AsyncResult<SubsMessage>|-> bool {
+    let discard_empty = |obj:AsyncResult<SubscriberEvent>| -> Option<AsyncResult<SubsMessage>> {
         match obj {
-            Ok(m)  => (m.content.len() > 0) && (m.channel.len() > 0),
-            _others => false,
+            Ok(SubscriberEvent::Message(m)) if !m.content.is_empty()
+                && !m.channel.is_empty() => Some(Ok(m)),
+            Ok(SubscriberEvent::Lagged{channel, num_dropped}) => {
+                println!("subscriber lagged, channel:{}, dropped:{}", channel, num_dropped);
+                None
+            },
+            _others => None,
         }
     };
-    let processpipe = _stream.filter(discard_empty).take(expect_num_recv_msgs);
+    let processpipe = _stream.filter_map(discard_empty).take(expect_num_recv_msgs);
     tokio::pin!(processpipe);
     while let Some(msg) = processpipe.next().await {
         let msg = msg.unwrap();
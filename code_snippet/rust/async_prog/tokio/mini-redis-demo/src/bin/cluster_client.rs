@@ -0,0 +1,26 @@
+use bytes::Bytes;
+
+use mini_redis_demo::AsyncResult;
+use mini_redis_demo::clients::ClusterClient;
+use mini_redis_demo::logging::{self, LogFormat};
+
+// Start the nodes listed in the cluster config first, e.g.
+// `server --port 7001 --cluster-config cluster.conf`, then run this with
+// address of any node, `127.0.0.1:7001` by default.
+#[tokio::main]
+async fn main() -> AsyncResult<()>
+{
+    logging::init("warning", LogFormat::Text)?;
+    let seed = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:7001".to_string());
+    let mut client = ClusterClient::connect(&seed).await?;
+    let keys = ["apple", "banana", "cherry", "{fruit}.lemon", "{fruit}.mango"];
+    for k in keys.iter() {
+        client.set(k, Bytes::from(format!("value of {}", k))).await?;
+        println!("[cluster-client] set {} on node {:?}", k, client.node_of(k));
+    }
+    for k in keys.iter() {
+        let v = client.get(k).await?;
+        println!("[cluster-client] get {} : {:?}", k, v);
+    }
+    Ok(())
+}
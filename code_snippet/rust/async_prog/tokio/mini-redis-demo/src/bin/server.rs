@@ -1,90 +1,577 @@
+use std::future::pending;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
 use std::mem::drop;
-use std::sync::Arc;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{Semaphore, broadcast};
-use tokio::time::sleep;
-use tokio::signal;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, error, info, warn, debug_span, info_span, field, Instrument, Span};
 
 // why compiler does not allow to use `crate` for import ?
-use mini_redis_demo::{DEFAULT_PORT, MAX_CONNECTIONS,
-    Connection, cmd, SingleRequestShutdown};
-use mini_redis_demo::db::FakeDatabase;
+use mini_redis_demo::{Connection, BoxedStream, Frame, cmd, SingleRequestShutdown,
+    ShutdownCoordinator, SaveMode};
+use mini_redis_demo::config::ServerConfig;
+use mini_redis_demo::tls::{self, TlsAcceptor};
+use mini_redis_demo::logging;
+use mini_redis_demo::info::render_metrics;
+use mini_redis_demo::latency::EVENT_COMMAND;
+use mini_redis_demo::db::{FakeDatabase, ACTIVE_EXPIRE_INTERVAL};
+use mini_redis_demo::persist::AppendOnlyFile;
+use mini_redis_demo::tracking::make_invalidation_message;
+
+const MIN_ACCEPT_BACKOFF:Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF:Duration = Duration::from_secs(1);
+// clients which never complete TLS handshake shouldn't hold their slots
+const TLS_HANDSHAKE_TIMEOUT:Duration = Duration::from_secs(10);
+// scrapers which never send complete request
+const METRICS_REQUEST_TIMEOUT:Duration = Duration::from_secs(5);
+
+// sockets the server accepts clients from, any of them can be disabled
+struct Listeners {
+    tcp: Option<TcpListener>,
+    tls: Option<(TcpListener, TlsAcceptor)>,
+    #[cfg(unix)]
+    unix: Option<UnixListener>,
+}
+
+// accepted socket, the TLS handshake is done later in the task of the
+// connection, so it never blocks accepting other clients
+enum Incoming {
+    Tcp(TcpStream),
+    Tls(TcpStream, TlsAcceptor),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listeners {
+    async fn bind(cfg:&ServerConfig) -> IoResult<Self> {
+        let tcp = if cfg.port != 0 {
+            Some(TcpListener::bind((cfg.bind.as_str(), cfg.port)).await?)
+        } else {
+            None
+        };
+        let tls = if cfg.tls_port != 0 {
+            let (cert, key) = match (cfg.tls_cert_file.as_ref(), cfg.tls_key_file.as_ref()) {
+                (Some(c), Some(k)) => (c, k),
+                _others => return Err(IoError::new(ErrorKind::InvalidInput,
+                    "tls-cert-file and tls-key-file are required by tls-port")),
+            };
+            let ca = cfg.tls_ca_cert_file.as_ref().map(Path::new);
+            let acceptor = tls::acceptor(Path::new(cert), Path::new(key), ca,
+                                         cfg.tls_auth_clients)?;
+            let listener = TcpListener::bind((cfg.bind.as_str(), cfg.tls_port)).await?;
+            Some((listener, acceptor))
+        } else {
+            None
+        };
+        #[cfg(unix)]
+        let unix = match cfg.unixsocket.as_ref() {
+            Some(path) => Some(bind_unix(path, cfg.unixsocketperm)?),
+            None => None,
+        };
+        #[cfg(unix)]
+        let no_unix = unix.is_none();
+        #[cfg(not(unix))]
+        let no_unix = true;
+        if tcp.is_none() && tls.is_none() && no_unix {
+            return Err(IoError::new(ErrorKind::InvalidInput,
+                "port, tls-port and unixsocket are all disabled"));
+        }
+        Ok(Self{tcp, tls,
+                #[cfg(unix)]
+                unix})
+    }
+
+    async fn accept(&self) -> IoResult<Incoming> {
+        // disabled listener never gets any client
+        let tcp = async {
+            match self.tcp.as_ref() {
+                Some(l) => l.accept().await.map(|(s, _)| Incoming::Tcp(s)),
+                None => pending().await,
+            }
+        };
+        let tls = async {
+            match self.tls.as_ref() {
+                Some((l, a)) => l.accept().await.map(|(s, _)| Incoming::Tls(s, a.clone())),
+                None => pending().await,
+            }
+        };
+        #[cfg(unix)]
+        let unix = async {
+            match self.unix.as_ref() {
+                Some(l) => l.accept().await.map(|(s, _)| Incoming::Unix(s)),
+                None => pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let unix = pending();
+        tokio::select! {
+            accepted = tcp => accepted,
+            accepted = tls => accepted,
+            accepted = unix => accepted,
+        }
+    }
+} // end of Listeners
+
+// the socket file left by previous run is replaced, as Redis does
+#[cfg(unix)]
+fn bind_unix(path:&str, perm:u32) -> IoResult<UnixListener>
+{
+    use std::os::unix::fs::PermissionsExt;
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _others => {},
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(perm))?;
+    Ok(listener)
+}
+
+impl Incoming {
+    async fn into_stream(self) -> IoResult<(BoxedStream, Option<SocketAddr>)> {
+        match self {
+            Self::Tcp(socket) => {
+                let peer_addr = socket.peer_addr().ok();
+                Ok((Box::new(socket), peer_addr))
+            },
+            Self::Tls(socket, acceptor) => {
+                let peer_addr = socket.peer_addr().ok();
+                let stream = timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await
+                    .map_err(|_| IoError::new(ErrorKind::TimedOut, "TLS handshake timed out"))??;
+                Ok((Box::new(stream), peer_addr))
+            },
+            // peer of Unix domain socket has no IP address
+            #[cfg(unix)]
+            Self::Unix(socket) => Ok((Box::new(socket), None)),
+        }
+    }
+}
 
 #[tokio::main]
 async fn main()
 {
-    let url:String = format!("127.0.0.1:{}",  DEFAULT_PORT);
-    if let Ok(listener) = TcpListener::bind(url).await {
-        // create receiver later for each client request
-        let (notify_shutdown, _) = broadcast::channel(5);
-        let limit_conns = Arc::new(Semaphore::new(MAX_CONNECTIONS as usize));
-        tokio::select! {
-            _result = server_start(listener, &limit_conns, &notify_shutdown)
-                => { println!("will never reach here"); }
-            _ = signal::ctrl_c() => { println!("shutdown starts..."); }
-        };
-        let _ = notify_shutdown.send(());
-        while limit_conns.available_permits() < (MAX_CONNECTIONS as usize)
-        { sleep(Duration::new(2u64, 0)).await; }
-        println!("end of testing server");
+    // `server [path/to/redis.conf] [--name value ...]`, any parameter in
+    // the file can be overridden in command line, e.g. `--port 7001`
+    let cfg = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(v) => v,
+        Err(e) => { // logging is not set up yet
+            eprintln!("invalid configuration, {}", e);
+            return;
+        },
+    };
+    if let Err(e) = logging::init(&cfg.loglevel, cfg.logformat) {
+        eprintln!("failed to set up logging, {}", e);
+        return;
+    }
+    if let Err(e) = std::env::set_current_dir(&cfg.dir) {
+        error!(dir = %cfg.dir, "failed to change working directory, {}", e);
+        return;
+    }
+    let fakedb = FakeDatabase::with_config(cfg.clone());
+    if let Some(path) = cfg.cluster_config_file.as_ref() {
+        let loaded = cfg.cluster_address()
+            .and_then(|myself| fakedb.cluster().load(path, &myself));
+        if let Err(e) = loaded {
+            error!("failed to load cluster config, {}", e);
+            return;
+        }
+    }
+    // a missing file starts with the default user only, `ACL SAVE` creates it
+    if let Some(path) = cfg.aclfile.as_ref().filter(|p| Path::new(p).exists()) {
+        match fakedb.acl().load_file(path) {
+            Ok(num_users) => info!(num_users, "users loaded from ACL file"),
+            Err(e) => {
+                error!("failed to load ACL file, {}", e);
+                return;
+            },
+        }
+    }
+    // the append-only file is more up-to-date than snapshot, load snapshot
+    // only if there is no append-only file. Refuse to start with corrupted
+    // file, instead of silently overwriting it later.
+    if cfg.appendonly && Path::new(&cfg.appendfilename).exists() {
+        match AppendOnlyFile::load(&cfg.appendfilename, &fakedb).await {
+            Ok(num_cmds) => info!(num_cmds, "commands replayed from append-only file"),
+            Err(e) => {
+                error!("failed to load append-only file, {}", e);
+                return;
+            },
+        }
     } else {
-        println!("server failed to bind port");
+        match fakedb.snapshotter().load(&fakedb).await {
+            Ok(num_loaded) => info!(num_loaded, "keys loaded from snapshot"),
+            Err(e) => {
+                error!("failed to load snapshot, {}", e);
+                return;
+            },
+        }
+    }
+    if cfg.appendonly {
+        if let Err(e) = fakedb.aof().enable(&cfg.appendfilename, cfg.appendfsync).await {
+            error!("failed to open append-only file, {}", e);
+            return;
+        }
+    }
+    if let Some((host, port)) = cfg.replicaof.as_ref() {
+        if let Err(e) = fakedb.replication().replicate_of(host, *port, &fakedb) {
+            error!("failed to start replication, {}", e);
+            return;
+        }
     }
+    let listeners = match Listeners::bind(&cfg).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("server failed to bind port, {}", e);
+            return;
+        },
+    };
+    let metrics_task = if cfg.metrics_port != 0 {
+        match TcpListener::bind((cfg.bind.as_str(), cfg.metrics_port)).await {
+            Ok(l) => Some(tokio::spawn(serve_metrics(l, fakedb.clone()))),
+            Err(e) => {
+                error!("failed to bind metrics port, {}", e);
+                return;
+            },
+        }
+    } else {
+        None
+    };
+    let (stop_expire, stop_rx) = oneshot::channel();
+    let expire_task = tokio::spawn(active_expire(fakedb.clone(), stop_rx));
+    let mut coordinator = ShutdownCoordinator::new();
+    let mode = server_start(&listeners, &fakedb, &mut coordinator).await;
+    if let Some(task) = metrics_task {
+        task.abort();
+    }
+    // stop accepting, then let connections finish their current command
+    drop(listeners);
+    #[cfg(unix)]
+    if let Some(path) = cfg.unixsocket.as_ref() {
+        let _ = std::fs::remove_file(path);
+    }
+    info!(num_connections = coordinator.num_running(), "shutdown starts");
+    let timeout = fakedb.config().current().map(|c| c.shutdown_timeout)
+        .unwrap_or(cfg.shutdown_timeout);
+    let num_aborted = coordinator.drain(Duration::from_secs(timeout)).await;
+    if num_aborted > 0 {
+        warn!(num_aborted, "connections closed forcibly after {} seconds", timeout);
+    }
+    // let a running cycle finish, its deletions are still logged
+    let _ = stop_expire.send(());
+    let _ = expire_task.await;
+    flush_persistence(&fakedb, mode).await;
+    info!("end of testing server");
 } // end of main
 
-async fn server_start(listener:TcpListener, limit_conns:&Arc<Semaphore>,
-                      notify_shutdown:&broadcast::Sender<()> )
+async fn server_start(listeners:&Listeners, fakedb:&FakeDatabase,
+                      coordinator:&mut ShutdownCoordinator) -> SaveMode
 {
-    let fakedb = FakeDatabase::new();
-    // - `acquire()` and `acquire_owned()` ensures that you will get
-    //   permit eventually only if semaphore is available.
-    // - `acquire()` returns borrowed reference of permit instance, while
-    //   `acquire_owned()` moves ownership of given Arc<Semaphore> instance, and
-    //   returns a permit instance which has ownership so you can move the permit
-    //   to any spawned task
+    // accept errors like too many open files are usually temporary, back
+    // off instead of spinning on the same error
+    let mut backoff = MIN_ACCEPT_BACKOFF;
     loop {
-        let permit = Arc::clone(limit_conns).acquire_owned().await.unwrap();
-        // the 2nd item contains IP and port of the new connection
-        let (_socket, _) = listener.accept().await.unwrap();
+        // waiting for new connection is interrupted as soon as the server
+        // is asked to stop
+        let _socket = tokio::select! {
+            accepted = listeners.accept() => match accepted {
+                Ok(socket) => {
+                    backoff = MIN_ACCEPT_BACKOFF;
+                    socket
+                },
+                Err(e) => {
+                    fakedb.stats().incr_accept_errors();
+                    error!(?backoff, "accept failed, {}", e);
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                },
+            },
+            mode = coordinator.wait_for_stop() => break mode,
+        };
+        // the client is always accepted, then told why it is closed
+        // instead of hanging in the backlog of the listener
+        let maxclients = fakedb.config().current().map(|c| c.maxclients).unwrap_or(0);
+        let slot = match fakedb.stats().register_client(maxclients) {
+            Some(v) => v,
+            None => {
+                warn!(rejected = fakedb.stats().rejected_connections(),
+                      "max number of clients reached");
+                tokio::spawn(reject_client(_socket));
+                continue;
+            },
+        };
         let fdb_cpy = fakedb.clone();
-        let shutdown_monitor = notify_shutdown.subscribe();
+        let req_down = coordinator.subscribe();
         // a new task is spawned for each inbound socket, move the
         // socket to the new task, let Tokio runtime concurrently
         // process as many tasks as possible.
-        tokio::spawn(async move {
-            process_single_request(_socket, fdb_cpy, shutdown_monitor).await;
-            // move the permit here, and drop it after request is done
-            // processing, `drop()` returns the permit back to the semaphore
-            drop(permit);
+        coordinator.spawn(async move {
+            process_single_request(_socket, fdb_cpy, req_down).await;
+            // the client no longer counts once the connection is done
+            drop(slot);
         }); // don't run it immediately by `.await`, the new task will be executed
             // in next iteration when waiting for new socket.
-    } // TODO, how to break from the loop ?
+    }
 } // end of server_start
 
-async fn process_single_request (socket:TcpStream, fakedb:FakeDatabase,
-                                 shutdown_monitor:broadcast::Receiver<()> )
+async fn reject_client(socket:Incoming)
 {
+    if let Ok((mut stream, _)) = socket.into_stream().await {
+        let _ = stream.write_all(b"-ERR max number of clients reached\r\n").await;
+        let _ = stream.shutdown().await;
+    }
+}
+
+// remove expired keys nobody reads, replicas wait for `DEL` of the primary
+// instead, as Redis does
+async fn active_expire(fakedb:FakeDatabase, mut stop:oneshot::Receiver<()>)
+{
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = &mut stop => break,
+        }
+        if fakedb.replication().is_replica() {
+            continue;
+        }
+        match fakedb.active_expire_cycle().await {
+            Ok(0) => {},
+            Ok(num_expired) => debug!(num_expired, "active expiry"),
+            Err(e) => warn!("active expiry failed, {}", e),
+        }
+    }
+}
+
+// plaintext HTTP endpoint for Prometheus, only `GET /metrics` is served,
+// one request per connection
+async fn serve_metrics(listener:TcpListener, fakedb:FakeDatabase)
+{
+    loop {
+        let socket = match listener.accept().await {
+            Ok((s, _)) => s,
+            Err(e) => {
+                warn!("metrics endpoint failed to accept, {}", e);
+                sleep(MAX_ACCEPT_BACKOFF).await;
+                continue;
+            },
+        };
+        let db = fakedb.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics_response(socket, &db).await {
+                debug!("metrics request failed, {}", e);
+            }
+        });
+    }
+}
+
+async fn metrics_response(mut socket:TcpStream, fakedb:&FakeDatabase) -> IoResult<()>
+{
+    // only the request line matters, the rest of headers is skipped
+    let mut buf = Vec::with_capacity(1024);
+    let read_head = async {
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 8192 {
+            if socket.read_buf(&mut buf).await? == 0 {
+                break;
+            }
+        }
+        Ok::<(), IoError>(())
+    };
+    timeout(METRICS_REQUEST_TIMEOUT, read_head).await
+        .map_err(|_| IoError::new(ErrorKind::TimedOut, "incomplete request"))??;
+    let head = String::from_utf8_lossy(&buf);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render_metrics(fakedb).await?),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _others => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                            Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status, body.len(), body);
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+// all connections are closed, nothing is written to the store anymore
+async fn flush_persistence(fakedb:&FakeDatabase, mode:SaveMode)
+{
+    let aof_enabled = fakedb.aof().is_enabled().await;
+    if let Err(e) = fakedb.aof().disable().await {
+        error!("failed to flush append-only file, {}", e);
+    }
+    let save = match mode {
+        SaveMode::Save => true,
+        SaveMode::NoSave => false,
+        SaveMode::Default => !aof_enabled,
+    };
+    if save {
+        match fakedb.snapshotter().save(fakedb).await {
+            Ok(num_saved) => info!(num_saved, "keys saved to snapshot"),
+            Err(e) => error!("failed to save snapshot, {}", e),
+        }
+    }
+}
+
+// everything logged while serving the client is in the span of its id
+// and address
+async fn process_single_request (socket:Incoming, fakedb:FakeDatabase,
+                                 req_down:SingleRequestShutdown )
+{
+    let id = fakedb.stats().next_client_id();
+    let span = info_span!("client", id, peer = field::Empty);
+    serve_client(id, socket, fakedb, req_down).instrument(span).await
+}
+
+// `ip:port` of the client, or `unix` for Unix domain socket
+fn client_addr(conn:&Connection) -> String
+{
+    match conn.session().peer_addr {
+        Some(addr) => addr.to_string(),
+        None => "unix".to_string(),
+    }
+}
+
+async fn serve_client (id:u64, socket:Incoming, mut fakedb:FakeDatabase,
+                       mut req_down:SingleRequestShutdown )
+{
+    let (stream, peer_addr) = match socket.into_stream().await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("failed to set up connection, {}", e);
+            return;
+        },
+    };
+    match peer_addr {
+        Some(addr) => Span::current().record("peer", field::display(addr)),
+        None => Span::current().record("peer", "unix"),
+    };
+    debug!("connected");
     // connection allows user to read/write `redis frame` instead of
     // raw byte streams
-    let mut conn = Connection::new(socket);
-    let mut req_down = SingleRequestShutdown::new(shutdown_monitor) ;
+    let mut conn = Connection::new(stream);
+    conn.session_mut().id = id;
+    conn.session_mut().peer_addr = peer_addr;
+    conn.session_mut().user = fakedb.acl().initial_user();
+    let user = conn.session().user.clone().unwrap_or_default();
+    let registration = match fakedb.clients().register(id, client_addr(&conn), user) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to register client, {}", e);
+            return;
+        },
+    };
+    req_down.set_kill_switch(registration.kill_switch());
+    // invalidation messages of `CLIENT TRACKING` sent to this connection
+    let mut inbox = match fakedb.tracking().register(id) {
+        Ok(i) => i,
+        Err(e) => {
+            error!("failed to register client, {}", e);
+            return;
+        },
+    };
     while !req_down.is_shutdown() {
         // wait on multiple concurrent branches
         // In `select!` macro block, no need to use `await` on each async expression.
         tokio::select! {
             result = conn.read_frame() => {
                 let r_frm = if let Ok(Some(r)) = result {r} else {break;};
-                println!("server GOT: {:?}", r_frm);
-                let cmdobj:Box<dyn cmd::Command> = cmd::from_frame(r_frm).unwrap();
+                let name = cmd::command_name(&r_frm).unwrap_or_default();
+                let span = debug_span!("cmd", name = %name);
+                debug!(parent: &span, frame = ?r_frm, "received");
+                let (qbuf, qbuf_free) = conn.query_buffer();
+                let (obl, user) = (conn.output_buffer(), conn.session().user.clone());
+                fakedb.clients().update(id, |c| {
+                    c.cmd.clone_from(&name);
+                    c.last_active = Instant::now();
+                    c.user = user.unwrap_or_default();
+                    (c.qbuf, c.qbuf_free, c.obl) = (qbuf, qbuf_free, obl);
+                });
+                // kept only for the slow log and monitors, before the
+                // frame is consumed
+                let monitored = fakedb.monitors().is_active();
+                let argv = if monitored || fakedb.slowlog().is_enabled() {
+                    cmd::command_args(&r_frm)
+                } else {
+                    Vec::new()
+                };
+                let cmdobj:Box<dyn cmd::Command> = match cmd::from_frame(r_frm) {
+                    Ok(v) => v,
+                    Err(e) => { // malformed command, the connection is still usable
+                        let e = Frame::Error(format!("ERR {}", e));
+                        if conn.write_frame(&e).await.is_err() { break; }
+                        continue;
+                    },
+                };
+                if let Some(e) = cmd::precheck(&name, cmdobj.as_ref(), &fakedb, &mut conn) {
+                    if cmdobj.is_known() {
+                        fakedb.stats().record_rejected(&name);
+                    }
+                    // keys may be evicted before the command is refused
+                    if let Err(e) = fakedb.propagate().await {
+                        error!(parent: &span, "failed to propagate write command, {:?}", e);
+                    }
+                    if conn.write_frame(&e).await.is_err() { break; }
+                    continue;
+                }
+                // `CLIENT` itself is never held, so `CLIENT UNPAUSE` works
+                if name != "client" && fakedb.clients().is_paused() {
+                    tokio::select! {
+                        _ = fakedb.clients().wait_unpaused(cmdobj.is_write()) => {},
+                        _ = req_down.recv() => break,
+                    }
+                }
+                if monitored && cmdobj.is_known() && name != "monitor" {
+                    fakedb.monitors().feed(conn.session().db, &client_addr(&conn), &argv);
+                }
+                // `CLIENT CACHING` applies only to the command right after it
+                let caching = conn.session_mut().caching.take();
+                if cmdobj.is_read() {
+                    fakedb.track_next(id, caching);
+                }
+                if let Some(frm) = cmdobj.aof_frame() {
+                    fakedb.log_next(frm);
+                }
                 // some commands may send multiple outbound frames in one go
+                let started = Instant::now();
                 let _future = cmdobj.apply(&fakedb, &mut conn, &mut req_down);
-                if let Err(e) = _future.await {
-                    println!("[server][error] {:?}", e);
+                let result = _future.instrument(span.clone()).await;
+                let elapsed = (!cmdobj.is_streaming()).then(|| started.elapsed());
+                if cmdobj.is_known() {
+                    fakedb.stats().record_command(&name, elapsed);
+                }
+                if let Some(elapsed) = elapsed.filter(|_| cmdobj.is_known()) {
+                    fakedb.latency().record(EVENT_COMMAND, elapsed);
+                    if !argv.is_empty() && fakedb.slowlog().is_slow(elapsed) {
+                        let name = conn.session().name.clone().unwrap_or_default();
+                        fakedb.slowlog().record(argv, elapsed, client_addr(&conn), name);
+                    }
+                }
+                if let Err(e) = result {
+                    error!(parent: &span, "failed to apply, {:?}", e);
+                }
+                // even a failed command may have changed the store
+                if let Err(e) = fakedb.propagate().await {
+                    error!(parent: &span, "failed to propagate write command, {:?}", e);
+                }
+                // following commands run on the database chosen by `SELECT`
+                if conn.session().db != fakedb.index() {
+                    fakedb = fakedb.select(conn.session().db);
                 }
             } // end of reading inbound frames
+            Some(msg) = inbox.recv() => {
+                if conn.write_frame(&make_invalidation_message(msg)).await.is_err() { break; }
+            }
             _ = req_down.recv() => {} // will break the loop
         } // end of concurrent select
     } // end of loop
+    drop(inbox);
+    drop(registration);
+    debug!("disconnected");
 } // end of process
 
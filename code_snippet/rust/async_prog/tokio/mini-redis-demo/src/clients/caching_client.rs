@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Bytes;
+
+use crate::AsyncResult;
+use crate::clients::Client;
+use crate::tracking::TrackingOptions;
+
+// Values of the keys recently read, the least recently used one is
+// dropped when the cache is full. Each access gets a new tick, entries
+// are ordered by the tick of their last access.
+struct LruCache {
+    capacity: usize,
+    // key -> (value, tick of last access)
+    entries: HashMap<String, (Bytes, u64)>,
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl LruCache {
+    fn new(capacity:usize) -> Self {
+        Self{ capacity, entries: HashMap::new(), order: BTreeMap::new(), tick: 0 }
+    }
+
+    fn get(&mut self, key:&str) -> Option<Bytes> {
+        self.tick += 1;
+        let (value, last) = self.entries.get_mut(key)?;
+        self.order.remove(last);
+        *last = self.tick;
+        self.order.insert(self.tick, key.to_string());
+        Some(value.clone())
+    }
+
+    fn put(&mut self, key:&str, value:Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(key);
+        while self.entries.len() >= self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => { self.entries.remove(&oldest); },
+                None => break,
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(key.to_string(), (value, self.tick));
+        self.order.insert(self.tick, key.to_string());
+    }
+
+    fn remove(&mut self, key:&str) {
+        if let Some((_, last)) = self.entries.remove(key) {
+            self.order.remove(&last);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+} // end of LruCache
+
+// Client-side caching on top of `Client`. The server tracks the keys read
+// through this connection (`CLIENT TRACKING ON`), and sends invalidation
+// messages on the same connection when they are modified, expired or
+// evicted. The messages are applied before every read, so a cached value
+// is served only if the server hasn't reported a change of it yet.
+pub struct CachingClient {
+    client: Client,
+    cache: LruCache,
+    hits: u64,
+    misses: u64,
+}
+
+impl CachingClient {
+    // `capacity` is the maximum number of keys cached
+    pub async fn new(mut client:Client, capacity:usize) -> AsyncResult<Self> {
+        client.tracking(TrackingOptions::default()).await?;
+        Ok(Self{ client, cache: LruCache::new(capacity), hits: 0, misses: 0 })
+    }
+
+    pub async fn get(&mut self, key:&str) -> AsyncResult<Option<Bytes>> {
+        self.apply_invalidations().await?;
+        if let Some(value) = self.cache.get(key) {
+            self.hits += 1;
+            return Ok(Some(value));
+        }
+        self.misses += 1;
+        let value = self.client.get(key).await?;
+        // messages received along with the reply were sent before the key
+        // was read, they don't affect the value just returned
+        self.apply_invalidations().await?;
+        if let Some(v) = value.as_ref() {
+            self.cache.put(key, v.clone());
+        }
+        Ok(value)
+    }
+
+    // the server also sends invalidation of the key to this client, it is
+    // dropped locally right away
+    pub async fn set(&mut self, key:&str, value:Bytes) -> AsyncResult<()> {
+        self.cache.remove(key);
+        self.client.set(key, value).await
+    }
+
+    // (hits, misses) of the local cache
+    pub fn stats(&self) -> (u64, u64) { (self.hits, self.misses) }
+
+    // number of keys in the local cache
+    pub fn len(&self) -> usize { self.cache.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.cache.entries.is_empty() }
+
+    // other commands are sent through the underlying client, the local
+    // cache is not affected
+    pub fn client(&mut self) -> &mut Client { &mut self.client }
+
+    async fn apply_invalidations(&mut self) -> AsyncResult<()> {
+        for msg in self.client.take_invalidations().await? {
+            match msg {
+                Some(keys) => keys.iter().for_each(|k| self.cache.remove(k)),
+                None => self.cache.clear(),
+            }
+        }
+        Ok(())
+    }
+} // end of CachingClient
@@ -1,20 +1,45 @@
-use crate::{Connection, Frame, AsyncResult};
+use crate::{Connection, BoxedStream, Frame, AsyncResult};
+use crate::pubsub::ChannelKind;
+use crate::tracking::{Invalidation, TrackingOptions, INVALIDATE_CHANNEL};
 use crate::cmd::{
-    Get, Set, Publish, Subscribe, Unsubscribe, SubscribeCommonInit,
-    private_part::Command as PrivCommand
+    Get, Set, Ping, Publish, Subscribe, Save, BgSave, Unsubscribe, SubscribeCommonInit,
+    Client as ClientCmd, private_part::Command as PrivCommand
 };
 
 use async_stream::try_stream;
 use bytes::Bytes;
+use std::collections::VecDeque;
 use std::io::{Error, ErrorKind};
 use std::time::Duration;
+#[cfg(unix)]
+use std::path::Path;
 use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time::timeout;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use crate::tls::TlsConnector;
 use tokio_stream::Stream as TokioAbstractStream;
-// use tracing::{debug, instrument};
+use tracing::{debug, instrument};
 
 pub struct Client {
     connection: Connection,
     subscribed_channels: Vec<String>,
+    // channels subscribed by `SSUBSCRIBE`, in separate namespace
+    subscribed_shard_channels: Vec<String>,
+    // patterns subscribed by `PSUBSCRIBE`
+    subscribed_patterns: Vec<String>,
+    // published messages received while waiting for confirmation of
+    // (un)subscribe commands, they are delivered later by `Subscriber`
+    pending_messages: VecDeque<Frame>,
+    // invalidation messages of `CLIENT TRACKING` received in between
+    // replies, see `take_invalidations()`
+    invalidations: VecDeque<Invalidation>,
+    // set when a command is sent, cleared once all its replies are read.
+    // It stays set if the request is cancelled or fails half way, then
+    // the replies left on the connection would be taken by the next
+    // command, see `PooledClient` which closes such connection.
+    in_flight: bool,
 }
 
 pub struct Subscriber<'a> {
@@ -27,22 +52,61 @@ pub struct Message {
     pub content: String,
 }
 
+// items yielded by `Subscriber::into_stream()`
+#[derive(Debug, Clone)]
+pub enum SubscriberEvent {
+    Message(Message),
+    // message published by `SPUBLISH` to a shard channel
+    ShardMessage(Message),
+    // message of a channel matching the pattern subscribed by `PSUBSCRIBE`
+    PatternMessage { pattern: String, message: Message },
+    // the server dropped `num_dropped` messages of the channel because this
+    // subscriber didn't receive them fast enough
+    Lagged { channel: String, num_dropped: u64 },
+}
+
 impl Client {
     pub async fn connect<T: ToSocketAddrs>(addr: T) -> AsyncResult<Self> {
         // `TcpStream::connect`  performs any asynchronous DNS lookup and
         // attempts to establish the TCP connection. An error at either step
         // returns an error.
         let socket = TcpStream::connect(addr).await?;
+        Ok(Self::with_stream(Box::new(socket)))
+    }
+
+    // `server_name` is checked against the certificate of the server, the
+    // connector is created by `tls::connector()` and can be shared by
+    // many clients
+    pub async fn connect_tls<T: ToSocketAddrs>(addr: T, server_name: &str,
+                                               connector: &TlsConnector) -> AsyncResult<Self> {
+        let socket = TcpStream::connect(addr).await?;
+        let name = ServerName::try_from(server_name.to_string())?;
+        let stream = connector.connect(name, socket).await?;
+        Ok(Self::with_stream(Box::new(stream)))
+    }
+
+    // server on the same host listening on `unixsocket`
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<Path>>(path: P) -> AsyncResult<Self> {
+        let socket = UnixStream::connect(path).await?;
+        Ok(Self::with_stream(Box::new(socket)))
+    }
+
+    fn with_stream(stream: BoxedStream) -> Self {
         // Initialize the connection state. This allocates read/write buffers to
         // perform redis protocol frame parsing.
-        let conn = Connection::new(socket);
-        Ok( Self{connection:conn, subscribed_channels:Vec::new()} )
+        let conn = Connection::new(stream);
+        Self{connection:conn, subscribed_channels:Vec::new(),
+             subscribed_shard_channels:Vec::new(), subscribed_patterns:Vec::new(),
+             pending_messages:VecDeque::new(), invalidations:VecDeque::new(),
+             in_flight:false}
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn get(&mut self, key: &str) -> AsyncResult<Option<Bytes>> {
         let frm = Get::new(key).into_frame();
         // Write the frame to the socket. Wait for the response from server
-        self.connection.write_frame(&frm).await?;
+        self.send(&frm).await?;
         // Both `Simple` and `Bulk` frames are accepted. `Null` represents the
         // key not being present and `None` is returned.
         match self.read_response().await? {
@@ -53,10 +117,12 @@ impl Client {
         }
     }
 
+    #[instrument(level = "debug", skip(self, value))]
     pub async fn set(&mut self, key: &str, value: Bytes) -> AsyncResult<()> {
         self.set_cmd(Set::new(key, value, None)).await
     }
 
+    #[instrument(level = "debug", skip(self, value))]
     pub async fn set_expires(&mut self, key: &str, value: Bytes,
         expiration: Duration) -> AsyncResult<()>
     {
@@ -66,41 +132,136 @@ impl Client {
     async fn set_cmd(&mut self, cmd: Set) -> AsyncResult<()> {
         let frm = cmd.into_frame();
         // Write the frame to the socket. Wait for the response from the server
-        self.connection.write_frame(&frm).await?;
+        self.send(&frm).await?;
         match self.read_response().await? {
             Frame::Simple(resp) if resp == "OK" => Ok(()),
             frm => Err(frm.to_error()),
         }
     }
 
+    // returns `PONG` or the given message echoed back by the server
+    #[instrument(level = "debug", skip(self))]
+    pub async fn ping(&mut self, msg: Option<Bytes>) -> AsyncResult<Bytes> {
+        let frame = Ping::new(msg).into_frame();
+        self.send(&frame).await?;
+        match self.read_response().await? {
+            Frame::Simple(value) => Ok(value.into()),
+            Frame::Bulk(value) => Ok(value),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    // write snapshot file, return after the server finished
+    #[instrument(level = "debug", skip(self))]
+    pub async fn save(&mut self) -> AsyncResult<()> {
+        let frame = Save.into_frame();
+        self.send(&frame).await?;
+        match self.read_response().await? {
+            Frame::Simple(resp) if resp == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    // ask the server to write snapshot file in background
+    #[instrument(level = "debug", skip(self))]
+    pub async fn bgsave(&mut self) -> AsyncResult<()> {
+        let frame = BgSave.into_frame();
+        self.send(&frame).await?;
+        match self.read_response().await? {
+            Frame::Simple(_) => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    #[instrument(level = "debug", skip(self, message))]
     pub async fn publish(&mut self, channel: &str, message: Bytes) -> AsyncResult<u64>
     {
-        let frame = Publish::new(channel, message).into_frame();
-        self.connection.write_frame(&frame).await?;
+        self.publish_cmd(Publish::new(channel, message)).await
+    }
+
+    #[instrument(level = "debug", skip(self, message))]
+    pub async fn spublish(&mut self, channel: &str, message: Bytes) -> AsyncResult<u64>
+    {
+        self.publish_cmd(Publish::new_sharded(channel, message)).await
+    }
+
+    async fn publish_cmd(&mut self, cmd: Publish) -> AsyncResult<u64>
+    {
+        let frame = cmd.into_frame();
+        self.send(&frame).await?;
         match self.read_response().await? {
             Frame::Integer(response) => Ok(response),
             frame => Err(frame.to_error()),
         }
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn subscribe (& mut self, channels: Vec<String>)
-        -> AsyncResult<Subscriber>
+        -> AsyncResult<Subscriber<'_>>
     {
         // Issue the subscribe command to the server and wait for confirmation.
         // The client will then have been transitioned into the "subscriber"
         // state and may only issue pub/sub commands from that point on.
-        self.subscribe_cmd(&channels).await?;
-        self.subscribed_channels.extend(channels.iter().map(Clone::clone));
+        self.subscribe_cmd(ChannelKind::Global, &channels).await?;
+        Ok(Subscriber{client: self})
+    }
+
+    // subscribe shard channels, messages are received as
+    // `SubscriberEvent::ShardMessage`
+    #[instrument(level = "debug", skip(self))]
+    pub async fn ssubscribe (& mut self, channels: Vec<String>)
+        -> AsyncResult<Subscriber<'_>>
+    {
+        self.subscribe_cmd(ChannelKind::Sharded, &channels).await?;
         Ok(Subscriber{client: self})
     }
 
-    async fn subscribe_cmd(&mut self, channels: &[String]) -> AsyncResult<()>
+    // subscribe glob-style patterns, e.g. `news.*`, messages of matching
+    // channels are received as `SubscriberEvent::PatternMessage`
+    #[instrument(level = "debug", skip(self))]
+    pub async fn psubscribe (& mut self, patterns: Vec<String>)
+        -> AsyncResult<Subscriber<'_>>
     {
-        let frame = Subscribe::new(channels.to_vec()).into_frame();
-        self.connection.write_frame(&frame).await?;
-        // check each channel the client is subscribing in the response
+        self.subscribe_cmd(ChannelKind::Pattern, &patterns).await?;
+        Ok(Subscriber{client: self})
+    }
+
+    fn subscribed_mut(&mut self, kind: ChannelKind) -> &mut Vec<String> {
+        match kind {
+            ChannelKind::Global => &mut self.subscribed_channels,
+            ChannelKind::Sharded => &mut self.subscribed_shard_channels,
+            ChannelKind::Pattern => &mut self.subscribed_patterns,
+        }
+    }
+
+    // number of subscriptions the server reports in replies of the kind,
+    // channels and patterns are counted together
+    fn num_subscribed(&self, kind: ChannelKind) -> usize {
+        match kind {
+            ChannelKind::Sharded => self.subscribed_shard_channels.len(),
+            ChannelKind::Global | ChannelKind::Pattern =>
+                self.subscribed_channels.len() + self.subscribed_patterns.len(),
+        }
+    }
+
+    pub(crate) fn is_subscribing(&self) -> bool {
+        !self.subscribed_channels.is_empty() || !self.subscribed_shard_channels.is_empty()
+            || !self.subscribed_patterns.is_empty()
+    }
+
+    async fn subscribe_cmd(&mut self, kind: ChannelKind, channels: &[String])
+        -> AsyncResult<()>
+    {
+        let frame = Subscribe::with_kind(channels.to_vec(), kind).into_frame();
+        let expect_reply = format!("{}subscribe", kind.prefix());
+        self.send(&frame).await?;
+        // check each channel the client is subscribing in the response, the
+        // server may reject some of the channels with error frames, keep
+        // reading replies of the remaining channels so the connection is
+        // still in sync, then report the first failure.
+        let mut first_error = None;
         for channel in channels {
-            let response = self.read_response().await?;
+            let response = self.read_pubsub_reply().await?;
             match response { // Verify the confirmation of subscription
                 Frame::Array(ref frame) => match frame.as_slice() {
                     // The server responds with an array frame in the form of:
@@ -113,26 +274,131 @@ impl Client {
                     // num-subscribed is the number of channels that the client
                     // is currently subscribed to.
                     [subscribe, schannel, ..] // the order has to be the same
-                        if *subscribe == "subscribe" && *schannel == channel => {}
+                        if *subscribe == expect_reply.as_str() && *schannel == channel => {}
                     _ => return Err(response.to_error()),
                 },
+                Frame::Error(msg) => {
+                    first_error.get_or_insert(msg);
+                    continue;
+                },
                 frame => return Err(frame.to_error()),
             };
+            let subscribed = self.subscribed_mut(kind);
+            if !subscribed.contains(channel) {
+                subscribed.push(channel.clone());
+            }
+        }
+        self.in_flight = false;
+        match first_error {
+            Some(msg) => Err(msg.into()),
+            None => Ok(()),
         }
-        Ok(())
     } // end of subscribe_cmd
 
+    // read confirmation of (un)subscribe command, the server may still
+    // publish messages before the confirmation arrives, keep them for
+    // the subscriber. Error frames are returned as they are.
+    async fn read_pubsub_reply(&mut self) -> AsyncResult<Frame> {
+        loop {
+            let response = self.read_any_response().await?;
+            let is_message = match response {
+                Frame::Array(ref frame) => matches!(frame.first(),
+                    Some(ftyp) if *ftyp == "message" || *ftyp == "smessage"
+                        || *ftyp == "pmessage" || *ftyp == "lagged"),
+                _ => false,
+            };
+            if is_message {
+                self.pending_messages.push_back(response);
+            } else {
+                break Ok(response)
+            }
+        }
+    }
+
+    // `CLIENT TRACKING ON`, the server then sends an invalidation message
+    // when a key read by this client is modified
+    #[instrument(level = "debug", skip(self))]
+    pub async fn tracking(&mut self, opts: TrackingOptions) -> AsyncResult<()> {
+        let frame = ClientCmd::Tracking(Some(opts)).into_frame();
+        self.send(&frame).await?;
+        match self.read_response().await? {
+            Frame::Simple(resp) if resp == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    // Invalidation messages received so far, including those already
+    // arrived on the socket but not read yet, which are read without
+    // waiting for more. Note a message may still be on the way when it
+    // returns, a cached value can be stale for a short while as in Redis.
+    pub async fn take_invalidations(&mut self) -> AsyncResult<Vec<Invalidation>> {
+        while !self.is_subscribing() {
+            // `read_frame()` keeps partial frame in its buffer if it is
+            // cancelled, and the future is polled once before timeout
+            let frame = match timeout(Duration::ZERO, self.connection.read_frame()).await {
+                Ok(result) => match result? {
+                    Some(frame) => frame,
+                    None => return Err(Error::new(ErrorKind::ConnectionReset,
+                                                  "connection reset by server").into()),
+                },
+                Err(_elapsed) => break,
+            };
+            match parse_invalidation(&frame) {
+                Some(msg) => self.invalidations.push_back(msg),
+                // nothing else is sent without request
+                None => return Err(frame.to_error()),
+            }
+        }
+        Ok(self.invalidations.drain(..).collect())
+    }
+
+    // send a command then return the reply as it is, including error
+    // frame, e.g. for `ClusterClient` to handle redirections
+    pub(crate) async fn request(&mut self, frm: &Frame) -> AsyncResult<Frame> {
+        self.send(frm).await?;
+        let response = self.read_any_response().await?;
+        self.in_flight = false;
+        Ok(response)
+    }
+
+    pub(crate) fn is_in_flight(&self) -> bool {
+        self.in_flight
+    }
+
+    async fn send(&mut self, frm: &Frame) -> AsyncResult<()> {
+        self.in_flight = true;
+        self.connection.write_frame(frm).await?;
+        Ok(())
+    }
+
+    // the only reply of a command
     async fn read_response(&mut self) -> AsyncResult<Frame> {
-        let response = self.connection.read_frame().await?;
+        let response = self.read_any_response().await?;
+        self.in_flight = false;
         match response {
-            Some(Frame::Error(msg)) => Err(msg.into()),
-            Some(frame) => Ok(frame),
-            None => {
-                // Receiving `None` here indicates the server has closed the
-                // connection without sending a frame. This is unexpected and is
-                // represented as a "connection reset by peer" error.
-                let err = Error::new(ErrorKind::ConnectionReset, "connection reset by server");
-                Err(err.into())
+            Frame::Error(msg) => Err(msg.into()),
+            frame => Ok(frame),
+        }
+    }
+
+    async fn read_any_response(&mut self) -> AsyncResult<Frame> {
+        loop {
+            let response = self.connection.read_frame().await?;
+            debug!(?response);
+            match response {
+                // subscribers get invalidation messages through `Subscriber`
+                Some(frame) if !self.is_subscribing() => match parse_invalidation(&frame) {
+                    Some(msg) => self.invalidations.push_back(msg),
+                    None => break Ok(frame),
+                },
+                Some(frame) => break Ok(frame),
+                None => {
+                    // Receiving `None` here indicates the server has closed the
+                    // connection without sending a frame. This is unexpected and is
+                    // represented as a "connection reset by peer" error.
+                    let err = Error::new(ErrorKind::ConnectionReset, "connection reset by server");
+                    break Err(err.into())
+                }
             }
         }
     }
@@ -141,33 +407,56 @@ impl Client {
         &self.subscribed_channels
     } // Returns the set of channels currently subscribed to.
 
+    pub fn get_shard_subscribed(&self) -> &[String] {
+        &self.subscribed_shard_channels
+    }
+
+    pub fn get_pattern_subscribed(&self) -> &[String] {
+        &self.subscribed_patterns
+    }
+
     pub async fn unsubscribe(&mut self, channels: &[String]) -> AsyncResult<()> {
-        let frame = Unsubscribe::new(channels.to_vec()).into_frame();
-        self.connection.write_frame(&frame).await?;
+        self.unsubscribe_cmd(ChannelKind::Global, channels).await
+    }
+
+    pub async fn sunsubscribe(&mut self, channels: &[String]) -> AsyncResult<()> {
+        self.unsubscribe_cmd(ChannelKind::Sharded, channels).await
+    }
+
+    pub async fn punsubscribe(&mut self, patterns: &[String]) -> AsyncResult<()> {
+        self.unsubscribe_cmd(ChannelKind::Pattern, patterns).await
+    }
+
+    async fn unsubscribe_cmd(&mut self, kind: ChannelKind, channels: &[String])
+        -> AsyncResult<()>
+    {
+        let frame = Unsubscribe::with_kind(channels.to_vec(), kind).into_frame();
+        let expect_reply = format!("{}unsubscribe", kind.prefix());
+        self.send(&frame).await?;
         // if the input channel list is empty, server acknowledges as unsubscribing
         // from all subscribed channels, so we assert that the unsubscribe list received
         // matches the client subscribed one
-        let _num = if channels.is_empty() {
-            self.subscribed_channels.len()
+        let num = if channels.is_empty() {
+            // server still replies once even if nothing was subscribed
+            self.subscribed_mut(kind).len().max(1)
         } else {
             channels.len()
         };
-        /*
         for _ in 0..num { // Read the response
-            let response = self.client.read_response().await?;
+            let response = self.read_pubsub_reply().await?;
             match response {
+                // See `Unsubscribe::make_response()` in `src/cmd/subscribe.rs`
                 Frame::Array(ref frame) => match frame.as_slice() {
-                    [unsubscribe, channel, ..] if *unsubscribe == "unsubscribe" => {
-                        let len = self.subscribed_channels.len();
-                        if len == 0 {
-                            // There must be at least one channel
-                            return Err(response.to_error());
+                    [unsubscribe, channel, Frame::Integer(remain)]
+                        if *unsubscribe == expect_reply.as_str() =>
+                    {
+                        let subscribed = self.subscribed_mut(kind);
+                        if let Frame::Bulk(_) = channel {
+                            subscribed.retain(|c| *channel != &c[..]);
                         }
-                        // unsubscribed channel should exist in the subscribed list at this point
-                        self.subscribed_channels.retain(|c| *channel != &c[..]);
-                        // Only a single channel should be removed from the
-                        // list of subscribed channels.
-                        if self.subscribed_channels.len() != len - 1 {
+                        // the server and the client should agree on number
+                        // of channels still subscribed
+                        if self.num_subscribed(kind) as u64 != *remain {
                             return Err(response.to_error());
                         }
                     }
@@ -176,37 +465,126 @@ impl Client {
                 frame => return Err(frame.to_error()),
             };
         }
-         * */
+        self.in_flight = false;
         Ok(())
-    } // end of unsubscribe
+    } // end of unsubscribe_cmd
 } // end of impl Client
 
+// See `tracking::make_invalidation_message()`, `None` if the frame is not
+// an invalidation message
+fn parse_invalidation(frame: &Frame) -> Option<Invalidation> {
+    match frame {
+        Frame::Array(parts) => match parts.as_slice() {
+            [ftyp, chn, Frame::Array(keys)]
+                if *ftyp == "message" && *chn == INVALIDATE_CHANNEL =>
+                Some(Some(keys.iter().map(|k| k.to_string()).collect())),
+            [ftyp, chn, Frame::Null]
+                if *ftyp == "message" && *chn == INVALIDATE_CHANNEL => Some(None),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 
 impl<'a> Subscriber<'a> {
     // Receive the next message published on a subscribed channel, waiting if
     // necessary.
-    // `None` indicates the subscription has been terminated.
-    async fn next_message(&mut self) -> AsyncResult<Option<Message>> {
-        match self.client.connection.read_frame().await? {
-            Some(mframe) => match mframe {
-                Frame::Array(ref frm) => match frm.as_slice() {
-                    [ftyp, chn, content] if *ftyp == "message" =>
-                        Ok(Some(Message {  channel: chn.to_string(),
-                            content: content.to_string(),
-                        })),
-                    _ => Err(mframe.to_error()),
-                }, // destruct a received frame to 3 pieces, check whether it
-                   // contains published message with a channel.
-                   // See `Subscribe::make_message_response()` in `src/cmd/subscribe.rs`
-                   // to understand how the message frame is formed.
-                frm => Err(frm.to_error()),
-            },
-            None => Ok(None),
+    // `None` indicates the subscription has been terminated, or the client
+    // unsubscribed all the channels and left subscriber mode.
+    pub async fn next_message(&mut self) -> AsyncResult<Option<SubscriberEvent>> {
+        let mframe = if let Some(f) = self.client.pending_messages.pop_front() {
+            f
+        } else if !self.client.is_subscribing() {
+            return Ok(None)
+        } else {
+            match self.client.connection.read_frame().await? {
+                Some(f) => f,
+                None => return Ok(None),
+            }
+        };
+        match mframe {
+            Frame::Array(ref frm) => match frm.as_slice() {
+                [ftyp, chn, content] if *ftyp == "message" =>
+                    Ok(Some(SubscriberEvent::Message(Message {
+                        channel: chn.to_string(),
+                        content: content.to_string(),
+                    }))),
+                [ftyp, chn, content] if *ftyp == "smessage" =>
+                    Ok(Some(SubscriberEvent::ShardMessage(Message {
+                        channel: chn.to_string(),
+                        content: content.to_string(),
+                    }))),
+                // see `Subscribe::make_pmessage_response()`
+                [ftyp, pattern, chn, content] if *ftyp == "pmessage" =>
+                    Ok(Some(SubscriberEvent::PatternMessage {
+                        pattern: pattern.to_string(),
+                        message: Message { channel: chn.to_string(),
+                                           content: content.to_string() },
+                    })),
+                // see `Subscribe::make_lagged_response()`
+                [ftyp, chn, Frame::Integer(n)] if *ftyp == "lagged" =>
+                    Ok(Some(SubscriberEvent::Lagged {
+                        channel: chn.to_string(), num_dropped: *n,
+                    })),
+                _ => Err(mframe.to_error()),
+            }, // destruct a received frame to 3 pieces, check whether it
+               // contains published message with a channel.
+               // See `Subscribe::make_message_response()` in `src/cmd/subscribe.rs`
+               // to understand how the message frame is formed.
+            frm => Err(frm.to_error()),
         }
     } // end of  next_message
 
+    pub fn get_subscribed(&self) -> &[String] {
+        self.client.get_subscribed()
+    }
+
+    // check liveness of the connection in subscriber mode, the server
+    // replies `[ "pong", message ]` instead of simple string
+    pub async fn ping(&mut self, msg: Option<Bytes>) -> AsyncResult<Bytes> {
+        let frame = Ping::new(msg).into_frame();
+        self.client.send(&frame).await?;
+        let response = self.client.read_pubsub_reply().await?;
+        self.client.in_flight = false;
+        match response {
+            Frame::Array(ref frame) => match frame.as_slice() {
+                [pong, Frame::Bulk(payload)] if *pong == "pong" => Ok(payload.clone()),
+                _ => Err(response.to_error()),
+            },
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    // subscribe more channels without leaving the stream
+    pub async fn subscribe(&mut self, channels: &[String]) -> AsyncResult<()> {
+        self.client.subscribe_cmd(ChannelKind::Global, channels).await
+    }
+
+    pub async fn ssubscribe(&mut self, channels: &[String]) -> AsyncResult<()> {
+        self.client.subscribe_cmd(ChannelKind::Sharded, channels).await
+    }
+
+    pub async fn psubscribe(&mut self, patterns: &[String]) -> AsyncResult<()> {
+        self.client.subscribe_cmd(ChannelKind::Pattern, patterns).await
+    }
+
+    // the subscriber stops receiving messages once all channels are
+    // unsubscribed, the client can issue other commands after that.
+    pub async fn unsubscribe(&mut self, channels: &[String]) -> AsyncResult<()> {
+        self.client.unsubscribe(channels).await
+    }
+
+    pub async fn sunsubscribe(&mut self, channels: &[String]) -> AsyncResult<()> {
+        self.client.sunsubscribe(channels).await
+    }
+
+    pub async fn punsubscribe(&mut self, patterns: &[String]) -> AsyncResult<()> {
+        self.client.punsubscribe(patterns).await
+    }
+
     pub fn into_stream(&'a mut self) ->
-        impl TokioAbstractStream<Item=AsyncResult<Message>> + 'a
+        impl TokioAbstractStream<Item=AsyncResult<SubscriberEvent>> + 'a
     { // `self`, `self.client` and the output, must have the same lifetime
       // , otherwisse compiler will report E0700 : hidden type for `impl xxx`
       // captures lifetime that does not appear in bounds
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::{Frame, AsyncResult};
+use crate::clients::Client;
+use crate::cluster::{key_slot, NUM_SLOTS};
+use crate::cmd::{Get, Set, Cluster, Asking, private_part::Command as PrivCommand};
+
+// give up if the cluster keeps redirecting, e.g. slot map is inconsistent
+// between the nodes
+const MAX_REDIRECTIONS:usize = 5;
+
+// Client of cluster mode, commands are sent straight to the node owning
+// the key according to the cached slot map. The map is reloaded when a
+// node replies `-MOVED`, while `-ASK` only redirects that single command.
+pub struct ClusterClient {
+    // connections to each node, keyed by `host:port`
+    nodes: HashMap<String, Client>,
+    // owner of each slot, index to `addrs`
+    slots: Vec<Option<usize>>,
+    addrs: Vec<String>,
+}
+
+enum Redirect {
+    Moved(String),
+    Ask(String),
+}
+
+impl ClusterClient {
+    // `seed` is address of any node in the cluster
+    pub async fn connect(seed: &str) -> AsyncResult<Self> {
+        let client = Client::connect(seed).await?;
+        let mut nodes = HashMap::new();
+        nodes.insert(seed.to_string(), client);
+        let mut obj = Self{ nodes, slots: vec![None; NUM_SLOTS as usize],
+                            addrs: Vec::new() };
+        obj.refresh_slots(seed).await?;
+        Ok(obj)
+    }
+
+    // reload slot map from the given node
+    pub async fn refresh_slots(&mut self, addr: &str) -> AsyncResult<()> {
+        let frm = Cluster::Slots.into_frame();
+        let ranges = match self.node(addr).await?.request(&frm).await? {
+            Frame::Array(items) => items,
+            frm => return Err(frm.to_error()),
+        };
+        self.slots.iter_mut().for_each(|s| *s = None);
+        self.addrs.clear();
+        for item in ranges {
+            let (first, last, addr) = parse_slot_range(item)?;
+            let idx = match self.addrs.iter().position(|a| *a == addr) {
+                Some(idx) => idx,
+                None => {
+                    self.addrs.push(addr);
+                    self.addrs.len() - 1
+                },
+            };
+            for slot in first ..= last.min(NUM_SLOTS - 1) {
+                self.slots[slot as usize] = Some(idx);
+            }
+        }
+        Ok(())
+    }
+
+    // node owning the slot of the key, according to the cached map
+    pub fn node_of(&self, key: &str) -> Option<&str> {
+        let slot = key_slot(key.as_bytes());
+        self.slots[slot as usize].map(|idx| self.addrs[idx].as_str())
+    }
+
+    pub async fn get(&mut self, key: &str) -> AsyncResult<Option<Bytes>> {
+        match self.execute(key, Get::new(key).into_frame()).await? {
+            Frame::Simple(value) => Ok(Some(value.into())),
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frm => Err(frm.to_error()),
+        }
+    }
+
+    pub async fn set(&mut self, key: &str, value: Bytes) -> AsyncResult<()> {
+        let frm = Set::new(key, value, None).into_frame();
+        self.expect_ok(key, frm).await
+    }
+
+    pub async fn set_expires(&mut self, key: &str, value: Bytes,
+        expiration: Duration) -> AsyncResult<()>
+    {
+        let frm = Set::new(key, value, Some(expiration)).into_frame();
+        self.expect_ok(key, frm).await
+    }
+
+    async fn expect_ok(&mut self, key: &str, frm: Frame) -> AsyncResult<()> {
+        match self.execute(key, frm).await? {
+            Frame::Simple(resp) if resp == "OK" => Ok(()),
+            frm => Err(frm.to_error()),
+        }
+    }
+
+    async fn node(&mut self, addr: &str) -> AsyncResult<&mut Client> {
+        if !self.nodes.contains_key(addr) {
+            let client = Client::connect(addr).await?;
+            self.nodes.insert(addr.to_string(), client);
+        }
+        Ok(self.nodes.get_mut(addr).unwrap())
+    }
+
+    // send the command to the node owning the key, following redirections
+    async fn execute(&mut self, key: &str, frm: Frame) -> AsyncResult<Frame> {
+        let mut addr = match self.node_of(key) {
+            Some(a) => a.to_string(),
+            None => return Err("CLUSTERDOWN slot of the key not served".into()),
+        };
+        let mut asking = false;
+        for _ in 0 .. MAX_REDIRECTIONS {
+            let node = self.node(&addr).await?;
+            if asking {
+                match node.request(&Asking.into_frame()).await? {
+                    Frame::Simple(_) => {},
+                    frm => return Err(frm.to_error()),
+                }
+            }
+            let resp = node.request(&frm).await?;
+            match parse_redirect(&resp) {
+                Some(Redirect::Moved(to)) => {
+                    self.refresh_slots(&to).await?;
+                    addr = to;
+                    asking = false;
+                },
+                Some(Redirect::Ask(to)) => {
+                    addr = to;
+                    asking = true;
+                },
+                None => return Ok(resp),
+            }
+        }
+        let e = Error::new(ErrorKind::TimedOut, "too many cluster redirections");
+        Err(e.into())
+    } // end of execute
+} // end of ClusterClient
+
+// `-MOVED slot host:port` or `-ASK slot host:port`
+fn parse_redirect(frm: &Frame) -> Option<Redirect> {
+    let msg = match frm {
+        Frame::Error(msg) => msg,
+        _others => return None,
+    };
+    let mut words = msg.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("MOVED"), Some(_slot), Some(addr)) => Some(Redirect::Moved(addr.to_string())),
+        (Some("ASK"), Some(_slot), Some(addr)) => Some(Redirect::Ask(addr.to_string())),
+        _others => None,
+    }
+}
+
+// `[first, last, [host, port], ...]` in reply of `CLUSTER SLOTS`
+fn parse_slot_range(frm: Frame) -> AsyncResult<(u16, u16, String)> {
+    let malformed = || -> crate::AsyncError { "malformed reply of CLUSTER SLOTS".into() };
+    let items = match frm {
+        Frame::Array(v) => v,
+        _others => return Err(malformed()),
+    };
+    match items.as_slice() {
+        [Frame::Integer(first), Frame::Integer(last), Frame::Array(node), ..] => {
+            match node.as_slice() {
+                [Frame::Bulk(host), Frame::Integer(port), ..] => {
+                    let host = std::str::from_utf8(host).map_err(|_| malformed())?;
+                    Ok((*first as u16, *last as u16, format!("{}:{}", host, port)))
+                },
+                _others => Err(malformed()),
+            }
+        },
+        _others => Err(malformed()),
+    }
+}
@@ -1,3 +1,11 @@
 mod client;
-pub use client::{Client, Message, Subscriber};
+pub use client::{Client, Message, Subscriber, SubscriberEvent};
 
+mod cluster_client;
+pub use cluster_client::ClusterClient;
+
+mod caching_client;
+pub use caching_client::CachingClient;
+
+mod pool;
+pub use pool::{Pool, PoolConfig, PoolStatus, PooledClient};
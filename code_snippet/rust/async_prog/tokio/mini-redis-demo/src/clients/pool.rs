@@ -0,0 +1,331 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep, timeout};
+use tracing::{debug, warn};
+
+use crate::{Frame, AsyncResult};
+use crate::clients::Client;
+use crate::cmd::{Get, Set, Ping, private_part::Command as PrivCommand};
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    // connections kept open even if nobody uses them
+    pub min_size: usize,
+    // connections open at the same time, in use or idle
+    pub max_size: usize,
+    // how long `Pool::checkout()` waits when all connections are in use
+    pub checkout_timeout: Duration,
+    // idle connections above `min_size` are closed after this long
+    pub idle_timeout: Duration,
+    // idle connections are checked by `PING` this often, broken ones are
+    // closed then replaced up to `min_size`
+    pub health_check_interval: Duration,
+    // delay before the n-th reconnection attempt is `reconnect_base * 2^(n-1)`,
+    // no more than `reconnect_max`, the first attempt is made at once
+    pub reconnect_base: Duration,
+    pub reconnect_max: Duration,
+    pub reconnect_attempts: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self{ min_size: 1, max_size: 8, checkout_timeout: Duration::from_secs(5),
+              idle_timeout: Duration::from_secs(300),
+              health_check_interval: Duration::from_secs(30),
+              reconnect_base: Duration::from_millis(100),
+              reconnect_max: Duration::from_secs(5), reconnect_attempts: 6 }
+    }
+}
+
+struct IdleClient {
+    client: Client,
+    since: Instant,
+}
+
+struct PoolInner {
+    addr: String,
+    config: PoolConfig,
+    // most recently returned at the back, they are checked out first so
+    // the rest of them stay idle long enough to be reaped
+    idle: Mutex<VecDeque<IdleClient>>,
+    // one permit for each connection in use, an idle connection holds no
+    // permit, a new connection is opened only if there is no idle one.
+    in_use: Arc<Semaphore>,
+}
+
+// Connections to a single server shared by many tasks. A connection is
+// checked out for exclusive use then returned when the guard is dropped,
+// see `PooledClient`. Cloning the pool is cheap, all clones share the same
+// connections. Idle connections are maintained by a background task which
+// stops when the last clone is dropped.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+// a connection checked out of the pool, usable as `Client`
+pub struct PooledClient {
+    // taken only when the guard is dropped, or replaced on reconnection
+    client: Option<Client>,
+    inner: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+// connections checked by the background task count as in use meanwhile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStatus {
+    pub idle: usize,
+    pub in_use: usize,
+}
+
+impl Pool {
+    // `min_size` connections are opened before it returns, it has to be
+    // called within Tokio runtime
+    pub async fn new(addr: &str, config: PoolConfig) -> AsyncResult<Self> {
+        if config.max_size == 0 || config.min_size > config.max_size {
+            return Err("max_size of the pool should be positive and at least min_size".into());
+        }
+        let inner = Arc::new(PoolInner{ addr: addr.to_string(),
+            in_use: Arc::new(Semaphore::new(config.max_size)),
+            idle: Mutex::new(VecDeque::new()), config });
+        for _ in 0 .. inner.config.min_size {
+            let client = Client::connect(inner.addr.as_str()).await?;
+            inner.put_idle(client, Instant::now())?;
+        }
+        tokio::spawn(maintain(Arc::downgrade(&inner)));
+        Ok(Self{ inner })
+    }
+
+    // wait for a connection, at most `checkout_timeout` including the time
+    // to open a new one
+    pub async fn checkout(&self) -> AsyncResult<PooledClient> {
+        let (started, checkout_timeout) = (Instant::now(), self.inner.config.checkout_timeout);
+        let permits = Arc::clone(&self.inner.in_use);
+        let permit = match timeout(checkout_timeout, permits.acquire_owned()).await {
+            Ok(p) => p?,
+            Err(_elapsed) => return Err(Error::new(ErrorKind::TimedOut,
+                "timed out waiting for a pooled connection").into()),
+        };
+        // the lock is released before connecting
+        let idle = self.inner.lock()?.pop_back();
+        let client = match idle {
+            Some(c) => c.client,
+            None => {
+                let remaining = checkout_timeout.saturating_sub(started.elapsed());
+                match timeout(remaining, self.inner.reconnect()).await {
+                    Ok(c) => c?,
+                    Err(_elapsed) => return Err(Error::new(ErrorKind::TimedOut,
+                        "timed out connecting for a pooled connection").into()),
+                }
+            },
+        };
+        Ok(PooledClient{ client: Some(client), inner: Arc::clone(&self.inner),
+                         _permit: permit })
+    }
+
+    pub fn status(&self) -> AsyncResult<PoolStatus> {
+        let idle = self.inner.lock()?.len();
+        let in_use = self.inner.config.max_size - self.inner.in_use.available_permits();
+        Ok(PoolStatus{ idle, in_use })
+    }
+
+    pub async fn get(&self, key: &str) -> AsyncResult<Option<Bytes>> {
+        match self.execute(Get::new(key).into_frame()).await? {
+            Frame::Simple(value) => Ok(Some(value.into())),
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frm => Err(frm.to_error()),
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: Bytes) -> AsyncResult<()> {
+        self.set_cmd(Set::new(key, value, None)).await
+    }
+
+    pub async fn set_expires(&self, key: &str, value: Bytes, expiration: Duration)
+        -> AsyncResult<()>
+    {
+        self.set_cmd(Set::new(key, value, Some(expiration))).await
+    }
+
+    async fn set_cmd(&self, cmd: Set) -> AsyncResult<()> {
+        match self.execute(cmd.into_frame()).await? {
+            Frame::Simple(resp) if resp == "OK" => Ok(()),
+            frm => Err(frm.to_error()),
+        }
+    }
+
+    pub async fn ping(&self, msg: Option<Bytes>) -> AsyncResult<Bytes> {
+        match self.execute(Ping::new(msg).into_frame()).await? {
+            Frame::Simple(value) => Ok(value.into()),
+            Frame::Bulk(value) => Ok(value),
+            frm => Err(frm.to_error()),
+        }
+    }
+
+    // Send a command on a pooled connection, error reply is returned as
+    // error. If the server closed the connection, e.g. it restarted, the
+    // connection is reopened with backoff and the command is sent once
+    // more, so only use it for commands which are safe to repeat.
+    async fn execute(&self, frm: Frame) -> AsyncResult<Frame> {
+        let mut conn = self.checkout().await?;
+        let response = match conn.request(&frm).await {
+            Err(e) if is_connection_lost(&e) => {
+                debug!(addr = %self.inner.addr, "connection lost, {}", e);
+                conn.client = Some(self.inner.reconnect().await?);
+                conn.request(&frm).await?
+            },
+            result => result?,
+        };
+        match response {
+            Frame::Error(msg) => Err(msg.into()),
+            frm => Ok(frm),
+        }
+    }
+} // end of impl Pool
+
+impl PoolInner {
+    fn lock(&self) -> AsyncResult<MutexGuard<'_, VecDeque<IdleClient>>> {
+        self.idle.lock().map_err(|_| Error::new(
+            ErrorKind::ResourceBusy, "failed to acquire pool lock").into())
+    }
+
+    fn put_idle(&self, client: Client, since: Instant) -> AsyncResult<()> {
+        self.lock()?.push_back(IdleClient{ client, since });
+        Ok(())
+    }
+
+    fn num_open(&self) -> AsyncResult<usize> {
+        let idle = self.lock()?.len();
+        Ok(idle + self.config.max_size - self.in_use.available_permits())
+    }
+
+    // open a new connection, waiting longer after each failure
+    async fn reconnect(&self) -> AsyncResult<Client> {
+        let mut delay = self.config.reconnect_base;
+        let mut attempt = 0;
+        loop {
+            match Client::connect(self.addr.as_str()).await {
+                Ok(client) => break Ok(client),
+                Err(e) if attempt + 1 >= self.config.reconnect_attempts => break Err(e),
+                Err(e) => {
+                    attempt += 1;
+                    debug!(addr = %self.addr, attempt, ?delay, "failed to reconnect, {}", e);
+                    sleep(delay).await;
+                    delay = (delay * 2).min(self.config.reconnect_max);
+                },
+            }
+        }
+    }
+
+    // one round of maintenance on idle connections, see `maintain()`
+    async fn check_idle(&self) -> AsyncResult<()> {
+        let (now, cfg) = (Instant::now(), &self.config);
+        // reap the connections idle for too long, oldest first
+        let num_open = self.num_open()?;
+        let reaped = {
+            let mut idle = self.lock()?;
+            let expired = idle.iter().take_while(|c| now.saturating_duration_since(c.since) >= cfg.idle_timeout).count();
+            let n = expired.min(num_open.saturating_sub(cfg.min_size));
+            idle.drain(.. n).count()
+        };
+        // `PING` the rest, each one is treated as in use meanwhile
+        let num_idle = self.lock()?.len();
+        for _ in 0 .. num_idle {
+            let permit = match Arc::clone(&self.in_use).try_acquire_owned() {
+                Ok(p) => p,
+                Err(_) => break,
+            };
+            let mut c = match self.lock()?.pop_front() {
+                Some(c) => c,
+                None => break,
+            };
+            match timeout(cfg.checkout_timeout, c.client.ping(None)).await {
+                Ok(Ok(_)) => self.put_idle(c.client, c.since)?,
+                Ok(Err(e)) => warn!(addr = %self.addr, "closed broken connection, {}", e),
+                Err(_elapsed) => warn!(addr = %self.addr, "closed unresponsive connection"),
+            }
+            drop(permit);
+        }
+        // replace the broken ones
+        let mut num_open = self.num_open()?;
+        while num_open < cfg.min_size {
+            let permit = match Arc::clone(&self.in_use).try_acquire_owned() {
+                Ok(p) => p,
+                Err(_) => break,
+            };
+            let client = self.reconnect().await?;
+            self.put_idle(client, Instant::now())?;
+            drop(permit);
+            num_open += 1;
+        }
+        if reaped > 0 {
+            debug!(addr = %self.addr, reaped, "closed idle connections");
+        }
+        Ok(())
+    }
+} // end of impl PoolInner
+
+// background task of the pool, it holds no strong reference between the
+// rounds so it ends once all handles of the pool are dropped
+async fn maintain(inner: Weak<PoolInner>) {
+    loop {
+        let interval = match inner.upgrade() {
+            Some(i) => i.config.health_check_interval,
+            None => break,
+        };
+        sleep(interval).await;
+        let inner = match inner.upgrade() {
+            Some(i) => i,
+            None => break,
+        };
+        if let Err(e) = inner.check_idle().await {
+            warn!(addr = %inner.addr, "failed to maintain the pool, {}", e);
+        }
+    }
+}
+
+// errors of `Client` when the server closed the connection, either while
+// reading the reply or sending the command
+fn is_connection_lost(e: &crate::AsyncError) -> bool {
+    match e.downcast_ref::<Error>() {
+        Some(e) => matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::BrokenPipe
+                            | ErrorKind::ConnectionAborted),
+        None => false,
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("pooled client is present until dropped")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("pooled client is present until dropped")
+    }
+}
+
+// the connection goes back to the pool, unless it is still in subscriber
+// mode which cannot serve other commands, or replies of a command may
+// still arrive, e.g. the request was cancelled, the connection was lost
+// and not reopened, or the reply was malformed. Such connection is closed.
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let client = match self.client.take() {
+            Some(c) => c,
+            None => return,
+        };
+        if !client.is_subscribing() && !client.is_in_flight() {
+            let _ = self.inner.put_idle(client, Instant::now());
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::Frame;
+
+pub const NUM_SLOTS:u16 = 16384;
+
+// Same as Redis, if the key contains `{...}` with at least one character
+// in between, only the part inside the first pair of braces is hashed, so
+// related keys like `{user1000}.following` and `{user1000}.followers` are
+// in the same slot.
+pub fn key_slot(key:&[u8]) -> u16 {
+    let hashed = match key.iter().position(|c| *c == b'{') {
+        Some(start) => match key[start + 1 ..].iter().position(|c| *c == b'}') {
+            Some(len) if len > 0 => &key[start + 1 .. start + 1 + len],
+            _others => key,
+        },
+        None => key,
+    };
+    crc16::State::<crc16::XMODEM>::calculate(hashed) % NUM_SLOTS
+}
+
+// Layout of the cluster, shared by all nodes. Each node finds itself by
+// its `cluster-announce-ip` (`bind` if not set) and port.
+//
+// ```text
+// # slot ranges owned by each node
+// slots 0-8191 127.0.0.1:7001
+// slots 8192-16383 127.0.0.1:7002
+// # slot moving from one node to another, it is still owned by the source
+// migrating 42 127.0.0.1:7001 127.0.0.1:7002
+// ```
+struct SlotMap {
+    myself: String,
+    // nodes in form of `host:port`
+    nodes: Vec<String>,
+    // (first slot, last slot, index to `nodes`) in the order of config file
+    ranges: Vec<(u16, u16, usize)>,
+    owners: Vec<Option<usize>>,
+    // slots this node is moving out, and where to
+    migrating: HashMap<u16, usize>,
+    // slots this node is taking over
+    importing: HashSet<u16>,
+}
+
+impl SlotMap {
+    fn node_index(&mut self, addr:&str) -> usize {
+        match self.nodes.iter().position(|n| n == addr) {
+            Some(idx) => idx,
+            None => {
+                self.nodes.push(addr.to_string());
+                self.nodes.len() - 1
+            },
+        }
+    }
+
+    fn parse(content:&str, myself:&str) -> IoResult<Self> {
+        let invalid = |lineno:usize, detail:&str| {
+            IoError::new(ErrorKind::InvalidData,
+                         format!("cluster config line {}, {}", lineno + 1, detail))
+        };
+        let parse_slot = |s:&str, lineno:usize| -> IoResult<u16> {
+            match s.parse::<u16>() {
+                Ok(v) if v < NUM_SLOTS => Ok(v),
+                _others => Err(invalid(lineno, "invalid slot")),
+            }
+        };
+        let mut map = Self{ myself: myself.to_string(), nodes: Vec::new(),
+            ranges: Vec::new(), owners: vec![None; NUM_SLOTS as usize],
+            migrating: HashMap::new(), importing: HashSet::new() };
+        for (lineno, line) in content.lines().enumerate() {
+            let words:Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {},
+                [w, ..] if w.starts_with('#') => {},
+                ["slots", range, addr] => {
+                    let (first, last) = match range.split_once('-') {
+                        Some((a, b)) => (parse_slot(a, lineno)?, parse_slot(b, lineno)?),
+                        None => { let s = parse_slot(range, lineno)?; (s, s) },
+                    };
+                    if first > last {
+                        return Err(invalid(lineno, "invalid slot range"));
+                    }
+                    let idx = map.node_index(addr);
+                    for slot in first ..= last {
+                        if map.owners[slot as usize].replace(idx).is_some() {
+                            return Err(invalid(lineno, "slot assigned more than once"));
+                        }
+                    }
+                    map.ranges.push((first, last, idx));
+                },
+                ["migrating", slot, from, to] => {
+                    let slot = parse_slot(slot, lineno)?;
+                    let to_idx = map.node_index(to);
+                    if *from == myself {
+                        map.migrating.insert(slot, to_idx);
+                    } else if *to == myself {
+                        map.importing.insert(slot);
+                    }
+                },
+                _others => return Err(invalid(lineno, "unknown directive")),
+            }
+        }
+        Ok(map)
+    } // end of parse
+
+    // error frame redirecting the client, `None` if this node serves the keys
+    fn route(&self, keys:&[&str], asking:bool, exists:&dyn Fn(&str) -> bool)
+        -> Option<Frame>
+    {
+        let slot = key_slot(keys.first()?.as_bytes());
+        if keys.iter().any(|k| key_slot(k.as_bytes()) != slot) {
+            return Some(Frame::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot".to_string()));
+        }
+        let owner = match self.owners[slot as usize] {
+            Some(idx) => &self.nodes[idx],
+            None => return Some(Frame::Error(
+                format!("CLUSTERDOWN Hash slot {} not served", slot))),
+        };
+        if *owner == self.myself {
+            // keys already moved to the target are served over there
+            match self.migrating.get(&slot) {
+                Some(to) if !keys.iter().all(|k| exists(k)) => Some(Frame::Error(
+                    format!("ASK {} {}", slot, self.nodes[*to]))),
+                _others => None,
+            }
+        } else if asking && self.importing.contains(&slot) {
+            None
+        } else {
+            Some(Frame::Error(format!("MOVED {} {}", slot, owner)))
+        }
+    }
+} // end of SlotMap
+
+// disabled until the config file is loaded, every node serves all the
+// keys in standalone mode
+#[derive(Default)]
+pub struct Cluster {
+    map: Mutex<Option<SlotMap>>,
+}
+
+impl Cluster {
+    // `myself` is the address other nodes and clients reach this node
+    pub fn load(&self, path:impl AsRef<Path>, myself:&str) -> IoResult<()> {
+        let content = std::fs::read_to_string(path)?;
+        let map = SlotMap::parse(&content, myself)?;
+        if !map.nodes.iter().any(|n| n == myself) {
+            let e = IoError::new(ErrorKind::InvalidData,
+                format!("node {} not found in cluster config", myself));
+            return Err(e);
+        }
+        if let Ok(mut m) = self.map.lock() {
+            *m = Some(map);
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.map.lock().map(|m| m.is_some()).unwrap_or(false)
+    }
+
+    // slot ranges with the owner address, `None` in standalone mode
+    pub fn slots(&self) -> Option<Vec<(u16, u16, String)>> {
+        let m = self.map.lock().ok()?;
+        let map = m.as_ref()?;
+        Some(map.ranges.iter().map(|(first, last, idx)| {
+            (*first, *last, map.nodes[*idx].clone())
+        }).collect())
+    }
+
+    // `asking` is set if the client sent `ASKING` right before the command,
+    // `exists` tells whether a key is in the local store.
+    pub(crate) fn route(&self, keys:&[&str], asking:bool, exists:&dyn Fn(&str) -> bool)
+        -> Option<Frame>
+    {
+        let m = self.map.lock().ok()?;
+        m.as_ref()?.route(keys, asking, exists)
+    }
+} // end of Cluster
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "bar" is in slot 5061 and "foo" in 12182
+    const CONFIG:&str = "\
+# two nodes
+slots 0-8191 127.0.0.1:7001
+slots 8192-16383 127.0.0.1:7002
+migrating 5061 127.0.0.1:7001 127.0.0.1:7002
+";
+
+    fn error_of(frame:Option<Frame>) -> Option<String> {
+        match frame {
+            Some(Frame::Error(e)) => Some(e),
+            _others => None,
+        }
+    }
+
+    #[test]
+    fn key_slot_known_values() {
+        // check value of CRC16/XMODEM
+        assert_eq!(key_slot(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b""), 0);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"bar"), 5061);
+    }
+
+    #[test]
+    fn key_slot_hashtag() {
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"{user1000}.followers"));
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"foo{bar}{zap}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"foo{{bar}}"), key_slot(b"{bar"));
+        // empty tag or no closing brace, the whole key is hashed
+        let whole = crc16::State::<crc16::XMODEM>::calculate(b"foo{}{bar}") % NUM_SLOTS;
+        assert_eq!(key_slot(b"foo{}{bar}"), whole);
+        assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+        assert_ne!(key_slot(b"foo{bar"), key_slot(b"bar"));
+    }
+
+    #[test]
+    fn route_moved() {
+        let map = SlotMap::parse(CONFIG, "127.0.0.1:7001").unwrap();
+        let none = |_:&str| false;
+        assert!(map.route(&["bar"], false, &|_| true).is_none());
+        assert_eq!(error_of(map.route(&["foo"], false, &none)).as_deref(),
+                   Some("MOVED 12182 127.0.0.1:7002"));
+        // `ASKING` doesn't matter for slots not being imported
+        assert_eq!(error_of(map.route(&["foo"], true, &none)).as_deref(),
+                   Some("MOVED 12182 127.0.0.1:7002"));
+        assert!(error_of(map.route(&["foo", "bar"], false, &none)).unwrap()
+            .starts_with("CROSSSLOT"));
+        // a command without keys is served anywhere
+        assert!(map.route(&[], false, &none).is_none());
+    }
+
+    #[test]
+    fn route_ask() {
+        let source = SlotMap::parse(CONFIG, "127.0.0.1:7001").unwrap();
+        // keys not moved yet are still served by the source
+        assert!(source.route(&["bar"], false, &|_| true).is_none());
+        assert_eq!(error_of(source.route(&["bar"], false, &|_| false)).as_deref(),
+                   Some("ASK 5061 127.0.0.1:7002"));
+
+        let target = SlotMap::parse(CONFIG, "127.0.0.1:7002").unwrap();
+        assert_eq!(error_of(target.route(&["bar"], false, &|_| true)).as_deref(),
+                   Some("MOVED 5061 127.0.0.1:7001"));
+        assert!(target.route(&["bar"], true, &|_| false).is_none());
+    }
+
+    #[test]
+    fn route_unassigned_slot() {
+        let map = SlotMap::parse("slots 0-100 127.0.0.1:7001", "127.0.0.1:7001").unwrap();
+        assert_eq!(error_of(map.route(&["foo"], false, &|_| false)).as_deref(),
+                   Some("CLUSTERDOWN Hash slot 12182 not served"));
+    }
+}
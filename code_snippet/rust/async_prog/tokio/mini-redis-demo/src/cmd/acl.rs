@@ -0,0 +1,139 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+#[derive(Debug)]
+pub enum Acl {
+    // create or modify the user with the rules, see `acl::User`
+    SetUser(String, Vec<String>),
+    DelUser(Vec<String>),
+    List,
+    Users,
+    WhoAmI,
+    // reload or store all users from / to `aclfile`
+    Load,
+    Save,
+}
+
+#[async_trait]
+impl PubCommand for Acl {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let acl = db.acl();
+        let response = match self {
+            Self::SetUser(name, rules) => match acl.set_user(name, rules) {
+                Ok(_) => Frame::Simple("OK".to_string()),
+                Err(e) => Frame::Error(format!("ERR Error in ACL SETUSER modifier, {}", e)),
+            },
+            Self::DelUser(names) => match acl.del_users(names) {
+                Ok(n) => Frame::Integer(n as u64),
+                Err(e) => Frame::Error(format!("ERR {}", e)),
+            },
+            Self::List => bulk_array(acl.list()?),
+            Self::Users => bulk_array(acl.users()?),
+            Self::WhoAmI => match dst.session().user.as_ref() {
+                Some(user) => Frame::Bulk(Bytes::from(user.clone().into_bytes())),
+                None => Frame::Null,
+            },
+            Self::Load | Self::Save => match db.config().current()?.aclfile {
+                Some(path) => {
+                    let result = if matches!(self, Self::Load) {
+                        acl.load_file(&path).map(|_| ())
+                    } else {
+                        acl.save_file(&path)
+                    };
+                    match result {
+                        Ok(_) => Frame::Simple("OK".to_string()),
+                        Err(e) => Frame::Error(format!("ERR {}", e)),
+                    }
+                },
+                None => Frame::Error("ERR This Redis instance is not configured to use an ACL file. \
+                                      You may want to specify users via the ACL SETUSER command and \
+                                      then issue a CONFIG REWRITE (assuming you have a Redis \
+                                      configuration file set) in order to store users in the Redis \
+                                      configuration.".to_string()),
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+fn bulk_array(items:Vec<String>) -> Frame
+{
+    let mut frm = Frame::array();
+    for item in items {
+        frm.push_bulk(Bytes::from(item.into_bytes()));
+    }
+    frm
+}
+
+fn remaining_strings(parse:&mut Parse) -> AsyncResult<Vec<String>>
+{
+    let mut out = Vec::new();
+    loop {
+        match parse.next_string() {
+            Ok(s) => out.push(s),
+            Err(ParseError::EndOfStream) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(out)
+}
+
+impl PrivCommand for Acl {
+    // # Format
+    // ```text
+    // ACL SETUSER username [rule [rule ...]]
+    // ACL DELUSER username [username ...]
+    // ACL LIST | USERS | WHOAMI | LOAD | SAVE
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "setuser" => {
+                let name = parse.next_string()?;
+                Self::SetUser(name, remaining_strings(parse)?)
+            },
+            "deluser" => {
+                let mut names = vec![parse.next_string()?];
+                names.extend(remaining_strings(parse)?);
+                Self::DelUser(names)
+            },
+            "list" => Self::List,
+            "users" => Self::Users,
+            "whoami" => Self::WhoAmI,
+            "load" => Self::Load,
+            "save" => Self::Save,
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut args = match self {
+            Self::SetUser(name, rules) => {
+                let mut args = vec!["setuser".to_string(), name];
+                args.extend(rules);
+                args
+            },
+            Self::DelUser(names) => {
+                let mut args = vec!["deluser".to_string()];
+                args.extend(names);
+                args
+            },
+            Self::List => vec!["list".to_string()],
+            Self::Users => vec!["users".to_string()],
+            Self::WhoAmI => vec!["whoami".to_string()],
+            Self::Load => vec!["load".to_string()],
+            Self::Save => vec!["save".to_string()],
+        };
+        args.insert(0, "acl".to_string());
+        bulk_array(args)
+    }
+}
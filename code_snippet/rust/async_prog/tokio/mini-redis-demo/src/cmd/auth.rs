@@ -0,0 +1,72 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+use crate::acl::DEFAULT_USER;
+
+// authenticate the connection, the client then runs commands with
+// permissions of the user
+#[derive(Debug)]
+pub struct Auth {
+    // `None` means the default user
+    user: Option<String>,
+    password: String,
+}
+
+impl Auth {
+    pub fn new(user:Option<&str>, password:&str) -> Self {
+        Self{ user: user.map(|u| u.to_string()), password: password.to_string() }
+    }
+}
+
+#[async_trait]
+impl PubCommand for Auth {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let acl = db.acl();
+        let name = self.user.as_deref().unwrap_or(DEFAULT_USER);
+        let response = if self.user.is_none() && !acl.default_user_has_password() {
+            Frame::Error("ERR AUTH <password> called without any password configured \
+                          for the default user. Are you sure your configuration is correct?"
+                         .to_string())
+        } else if acl.authenticate(name, &self.password) {
+            dst.session_mut().user = Some(name.to_string());
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Error("WRONGPASS invalid username-password pair or user is disabled."
+                         .to_string())
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Auth {
+    // # Format
+    // ```text
+    // AUTH [username] password
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let first = parse.next_string()?;
+        let obj = match parse.next_string() {
+            Ok(password) => Self{ user: Some(first), password },
+            Err(ParseError::EndOfStream) => Self{ user: None, password: first },
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("auth".as_bytes()));
+        if let Some(user) = self.user {
+            frm.push_bulk(Bytes::from(user.into_bytes()));
+        }
+        frm.push_bulk(Bytes::from(self.password.into_bytes()));
+        frm
+    }
+}
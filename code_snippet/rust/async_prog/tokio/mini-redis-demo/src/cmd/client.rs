@@ -0,0 +1,294 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+use crate::registry::{KillFilter, PauseMode};
+use crate::tracking::TrackingOptions;
+
+// inspect and control connections through `registry::ClientRegistry`
+#[derive(Debug)]
+pub enum Client {
+    // all clients if `None`
+    List(Option<Vec<u64>>),
+    // `CLIENT KILL ip:port`, the old form replying OK or error
+    KillAddr(String),
+    // `SKIPME` is resolved to id of the caller when the command runs
+    Kill{ id:Option<u64>, addr:Option<String>, skipme:bool },
+    SetName(String),
+    GetName,
+    Id,
+    // timeout in milliseconds
+    Pause(u64, PauseMode),
+    Unpause,
+    // `CLIENT TRACKING ON` with the options, or `OFF` if `None`
+    Tracking(Option<TrackingOptions>),
+    // `CLIENT CACHING yes | no`
+    Caching(bool),
+}
+
+#[async_trait]
+impl PubCommand for Client {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let clients = db.clients();
+        let my_id = dst.session().id;
+        let response = match self {
+            Self::List(ids) => {
+                let text:String = clients.list(ids.as_deref())?.iter()
+                    .map(|c| c.describe() + "\n").collect();
+                Frame::Bulk(Bytes::from(text.into_bytes()))
+            },
+            Self::KillAddr(addr) => {
+                let filter = KillFilter{ addr: Some(addr.clone()), ..Default::default() };
+                match clients.kill(&filter)? {
+                    0 => Frame::Error("ERR No such client".to_string()),
+                    _ => Frame::Simple("OK".to_string()),
+                }
+            },
+            Self::Kill{id, addr, skipme} => {
+                let filter = KillFilter{ id: *id, addr: addr.clone(),
+                                         skip: skipme.then_some(my_id) };
+                Frame::Integer(clients.kill(&filter)? as u64)
+            },
+            Self::SetName(name) => {
+                if name.bytes().any(|b| !(b'!' ..= b'~').contains(&b)) {
+                    Frame::Error("ERR Client names cannot contain spaces, newlines \
+                                  or special characters.".to_string())
+                } else {
+                    clients.update(my_id, |c| c.name = name.clone());
+                    dst.session_mut().name = Some(name.clone()).filter(|n| !n.is_empty());
+                    Frame::Simple("OK".to_string())
+                }
+            },
+            Self::GetName => match dst.session().name.as_ref() {
+                Some(name) => Frame::Bulk(Bytes::from(name.clone().into_bytes())),
+                None => Frame::Null,
+            },
+            Self::Id => Frame::Integer(my_id),
+            Self::Pause(timeout, mode) => {
+                clients.pause(Duration::from_millis(*timeout), *mode);
+                Frame::Simple("OK".to_string())
+            },
+            Self::Unpause => {
+                clients.unpause();
+                Frame::Simple("OK".to_string())
+            },
+            Self::Tracking(Some(opts)) => {
+                let redirect_gone = match opts.redirect {
+                    Some(target) => clients.list(Some(&[target]))?.is_empty(),
+                    None => false,
+                };
+                if let Err(e) = opts.validate() {
+                    Frame::Error(e)
+                } else if redirect_gone {
+                    Frame::Error("ERR The client ID you want redirect to does not exist"
+                                 .to_string())
+                } else {
+                    db.tracking().enable(my_id, opts.clone())?;
+                    Frame::Simple("OK".to_string())
+                }
+            },
+            Self::Tracking(None) => {
+                db.tracking().disable(my_id)?;
+                Frame::Simple("OK".to_string())
+            },
+            Self::Caching(yes) => match db.tracking().options(my_id)? {
+                Some(opts) if opts.optin && *yes || opts.optout && !*yes => {
+                    dst.session_mut().caching = Some(*yes);
+                    Frame::Simple("OK".to_string())
+                },
+                Some(opts) if opts.optin => Frame::Error("ERR CLIENT CACHING NO is only \
+                    valid when tracking is enabled in OPTOUT mode.".to_string()),
+                Some(opts) if opts.optout => Frame::Error("ERR CLIENT CACHING YES is only \
+                    valid when tracking is enabled in OPTIN mode.".to_string()),
+                _others => Frame::Error("ERR CLIENT CACHING can be called only when the \
+                    client is in tracking mode with OPTIN or OPTOUT mode enabled".to_string()),
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+// `first` is the name of the first filter, already taken from `parse`
+fn parse_kill_filters(first:String, parse:&mut Parse) -> AsyncResult<Client>
+{
+    let (mut id, mut addr, mut skipme) = (None, None, true);
+    let mut opt = Some(first);
+    while let Some(name) = opt {
+        match name.to_lowercase().as_str() {
+            "id" => { id = Some(parse.next_int()?); },
+            "addr" => { addr = Some(parse.next_string()?); },
+            "skipme" => {
+                skipme = match parse.next_string()?.to_lowercase().as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _others => return Err("syntax error".into()),
+                };
+            },
+            _others => return Err("syntax error".into()),
+        }
+        opt = match parse.next_string() {
+            Ok(s) => Some(s),
+            Err(ParseError::EndOfStream) => None,
+            Err(e) => return Err(e.into()),
+        };
+    }
+    Ok(Client::Kill{ id, addr, skipme })
+}
+
+// options following `CLIENT TRACKING ON`, in any order
+fn parse_tracking_options(parse:&mut Parse) -> AsyncResult<TrackingOptions>
+{
+    let mut opts = TrackingOptions::default();
+    loop {
+        let name = match parse.next_string() {
+            Ok(s) => s,
+            Err(ParseError::EndOfStream) => break,
+            Err(e) => return Err(e.into()),
+        };
+        match name.to_lowercase().as_str() {
+            "redirect" => { opts.redirect = Some(parse.next_int()?); },
+            "prefix" => { opts.prefixes.push(parse.next_string()?); },
+            "bcast" => { opts.bcast = true; },
+            "optin" => { opts.optin = true; },
+            "optout" => { opts.optout = true; },
+            _others => return Err("syntax error".into()),
+        }
+    }
+    Ok(opts)
+}
+
+impl PrivCommand for Client {
+    // # Format
+    // ```text
+    // CLIENT LIST [ID client-id [client-id ...]]
+    // CLIENT KILL ip:port
+    // CLIENT KILL [ID client-id] [ADDR ip:port] [SKIPME yes|no]
+    // CLIENT SETNAME name
+    // CLIENT GETNAME | ID | UNPAUSE
+    // CLIENT PAUSE timeout [WRITE | ALL]
+    // CLIENT TRACKING ON [REDIRECT client-id] [PREFIX prefix [PREFIX prefix ...]]
+    //     [BCAST] [OPTIN] [OPTOUT]
+    // CLIENT TRACKING OFF
+    // CLIENT CACHING YES | NO
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "list" => match parse.next_string() {
+                Ok(opt) if opt.eq_ignore_ascii_case("id") => {
+                    let mut ids = vec![parse.next_int()?];
+                    loop {
+                        match parse.next_int() {
+                            Ok(id) => ids.push(id),
+                            Err(ParseError::EndOfStream) => break,
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                    Self::List(Some(ids))
+                },
+                Ok(_) => return Err("syntax error".into()),
+                Err(ParseError::EndOfStream) => Self::List(None),
+                Err(e) => return Err(e.into()),
+            },
+            "kill" => {
+                let first = parse.next_string()?;
+                if first.contains(':') {
+                    Self::KillAddr(first)
+                } else {
+                    parse_kill_filters(first, parse)?
+                }
+            },
+            "setname" => Self::SetName(parse.next_string()?),
+            "getname" => Self::GetName,
+            "id" => Self::Id,
+            "pause" => {
+                let timeout = parse.next_int()?;
+                let mode = match parse.next_string() {
+                    Ok(m) => m.parse()?,
+                    Err(ParseError::EndOfStream) => PauseMode::All,
+                    Err(e) => return Err(e.into()),
+                };
+                Self::Pause(timeout, mode)
+            },
+            "unpause" => Self::Unpause,
+            "tracking" => match parse.next_string()?.to_lowercase().as_str() {
+                "on" => Self::Tracking(Some(parse_tracking_options(parse)?)),
+                "off" => Self::Tracking(None),
+                _others => return Err("syntax error".into()),
+            },
+            "caching" => match parse.next_string()?.to_lowercase().as_str() {
+                "yes" => Self::Caching(true),
+                "no" => Self::Caching(false),
+                _others => return Err("syntax error".into()),
+            },
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut args = vec!["client".to_string()];
+        match self {
+            Self::List(ids) => {
+                args.push("list".to_string());
+                if let Some(ids) = ids {
+                    args.push("id".to_string());
+                    args.extend(ids.iter().map(|i| i.to_string()));
+                }
+            },
+            Self::KillAddr(addr) => args.extend(["kill".to_string(), addr]),
+            Self::Kill{id, addr, skipme} => {
+                args.push("kill".to_string());
+                if let Some(id) = id {
+                    args.extend(["id".to_string(), id.to_string()]);
+                }
+                if let Some(addr) = addr {
+                    args.extend(["addr".to_string(), addr]);
+                }
+                let skipme = if skipme {"yes"} else {"no"};
+                args.extend(["skipme".to_string(), skipme.to_string()]);
+            },
+            Self::SetName(name) => args.extend(["setname".to_string(), name]),
+            Self::GetName => args.push("getname".to_string()),
+            Self::Id => args.push("id".to_string()),
+            Self::Pause(timeout, mode) => {
+                let mode = match mode { PauseMode::Write => "write", PauseMode::All => "all" };
+                args.extend(["pause".to_string(), timeout.to_string(), mode.to_string()]);
+            },
+            Self::Unpause => args.push("unpause".to_string()),
+            Self::Tracking(None) => args.extend(["tracking".to_string(), "off".to_string()]),
+            Self::Tracking(Some(opts)) => {
+                args.extend(["tracking".to_string(), "on".to_string()]);
+                if let Some(id) = opts.redirect {
+                    args.extend(["redirect".to_string(), id.to_string()]);
+                }
+                for p in opts.prefixes {
+                    args.extend(["prefix".to_string(), p]);
+                }
+                for (set, name) in [(opts.bcast, "bcast"), (opts.optin, "optin"),
+                                    (opts.optout, "optout")] {
+                    if set {
+                        args.push(name.to_string());
+                    }
+                }
+            },
+            Self::Caching(yes) => {
+                let yes = if yes {"yes"} else {"no"};
+                args.extend(["caching".to_string(), yes.to_string()]);
+            },
+        }
+        let mut frm = Frame::array();
+        for a in args {
+            frm.push_bulk(Bytes::from(a.into_bytes()));
+        }
+        frm
+    }
+}
@@ -0,0 +1,111 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::cluster::key_slot;
+use crate::db::FakeDatabase;
+
+#[derive(Debug)]
+pub enum Cluster {
+    // slot ranges and the node owning each of them
+    Slots,
+    // slot of the given key
+    KeySlot(String),
+}
+
+// the next command may access a slot this node is importing, sent by
+// client after `-ASK` redirection
+#[derive(Debug, Default)]
+pub struct Asking;
+
+#[async_trait]
+impl PubCommand for Cluster {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match self {
+            Self::Slots => match db.cluster().slots() {
+                Some(ranges) => slots_frame(ranges),
+                None => Frame::Error("ERR This instance has cluster support disabled".to_string()),
+            },
+            Self::KeySlot(key) => Frame::Integer(key_slot(key.as_bytes()) as u64),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+// `[[first, last, [host, port]], ...]`
+fn slots_frame(ranges:Vec<(u16, u16, String)>) -> Frame
+{
+    let items = ranges.into_iter().map(|(first, last, addr)| {
+        let (host, port) = addr.rsplit_once(':').unwrap_or((addr.as_str(), "0"));
+        let mut node = Frame::array();
+        node.push_bulk(Bytes::from(host.to_string().into_bytes()));
+        node.push_int(port.parse::<u64>().unwrap_or(0));
+        let mut item = Frame::array();
+        item.push_int(first as u64);
+        item.push_int(last as u64);
+        if let Frame::Array(v) = &mut item {
+            v.push(node);
+        }
+        item
+    }).collect();
+    Frame::Array(items)
+}
+
+impl PrivCommand for Cluster {
+    // # Format
+    // ```text
+    // CLUSTER SLOTS
+    // CLUSTER KEYSLOT key
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "slots" => Self::Slots,
+            "keyslot" => Self::KeySlot(parse.next_string()?),
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("cluster".as_bytes()));
+        match self {
+            Self::Slots => frm.push_bulk(Bytes::from("slots".as_bytes())),
+            Self::KeySlot(key) => {
+                frm.push_bulk(Bytes::from("keyslot".as_bytes()));
+                frm.push_bulk(Bytes::from(key.into_bytes()));
+            },
+        }
+        frm
+    }
+}
+
+#[async_trait]
+impl PubCommand for Asking {
+    async fn apply(&self, _db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        dst.session_mut().asking = true;
+        dst.write_frame(&Frame::Simple("OK".to_string())).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Asking {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("asking".as_bytes()));
+        frm
+    }
+}
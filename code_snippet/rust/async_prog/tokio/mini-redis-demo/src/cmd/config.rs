@@ -0,0 +1,161 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+use tracing::warn;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+use crate::logging;
+use crate::config::ServerConfig;
+
+#[derive(Debug)]
+pub enum Config {
+    // parameters matching any of the glob-style patterns
+    Get(Vec<String>),
+    // parameter / value pairs
+    Set(Vec<(String, String)>),
+}
+
+#[async_trait]
+impl PubCommand for Config {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match self {
+            Self::Get(patterns) => {
+                let mut frm = Frame::array();
+                let mut seen = Vec::new();
+                for pattern in patterns {
+                    for (name, value) in db.config().get_matched(pattern)? {
+                        if seen.contains(&name) {
+                            continue;
+                        }
+                        frm.push_bulk(Bytes::from(name.clone().into_bytes()));
+                        frm.push_bulk(Bytes::from(value.into_bytes()));
+                        seen.push(name);
+                    }
+                }
+                frm
+            },
+            Self::Set(params) => {
+                let previous = db.config().current()?;
+                match db.config().updated(params) {
+                    // stored only once the server runs with the new values
+                    Ok(updated) => match apply_changes(db, &updated, params).await {
+                        Ok(_) => {
+                            db.config().set_many(params)?;
+                            Frame::Simple("OK".to_string())
+                        },
+                        Err(e) => {
+                            // parameters applied before the failed one
+                            if let Err(e) = apply_changes(db, &previous, params).await {
+                                warn!("failed to restore settings after CONFIG SET, {}", e);
+                            }
+                            Frame::Error(format!("ERR {}", e))
+                        },
+                    },
+                    Err(e) => Frame::Error(format!("ERR {}", e)),
+                }
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+// parameters which take effect immediately in other parts of the server,
+// the rest is read by the server whenever it needs. Values are taken from
+// `cfg`, either the new settings or the previous ones to roll back.
+async fn apply_changes(db:&FakeDatabase, cfg:&ServerConfig, params:&[(String, String)])
+    -> AsyncResult<()>
+{
+    for (name, _) in params {
+        match name.to_lowercase().as_str() {
+            "dbfilename" => db.snapshotter().set_path(&cfg.dbfilename),
+            "loglevel" => logging::set_level(&cfg.loglevel)?,
+            "requirepass" => db.acl().set_default_password(cfg.requirepass.as_deref())?,
+            "slowlog-log-slower-than" => db.slowlog().set_threshold(cfg.slowlog_log_slower_than),
+            "slowlog-max-len" => db.slowlog().set_max_len(cfg.slowlog_max_len)?,
+            "latency-monitor-threshold" => {
+                db.latency().set_threshold(cfg.latency_monitor_threshold)
+            },
+            "maxmemory" | "maxmemory-policy" | "maxmemory-samples" => {
+                db.set_maxmemory(cfg.maxmemory, cfg.maxmemory_policy, cfg.maxmemory_samples)?;
+                // shrink at once instead of waiting for next write, as Redis does
+                db.evict_if_needed()?;
+            },
+            "notify-keyspace-events" => db.notifier().set_flags(cfg.notify_keyspace_events),
+            "appendfsync" => db.aof().set_policy(cfg.appendfsync).await,
+            "appendonly" => {
+                if !cfg.appendonly {
+                    db.aof().disable().await?;
+                } else if !db.aof().is_enabled().await {
+                    // start the file from current data set, as Redis does
+                    db.aof().enable(&cfg.appendfilename, cfg.appendfsync).await?;
+                    db.aof().bgrewrite(db)?;
+                }
+            },
+            _others => {},
+        }
+    }
+    Ok(())
+}
+
+impl PrivCommand for Config {
+    // # Format
+    // ```text
+    // CONFIG GET pattern [pattern ...]
+    // CONFIG SET parameter value [parameter value ...]
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "get" => {
+                let mut patterns = vec![parse.next_string()?];
+                loop {
+                    match parse.next_string() {
+                        Ok(p) => patterns.push(p),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Self::Get(patterns)
+            },
+            "set" => {
+                let mut params = vec![(parse.next_string()?, parse.next_string()?)];
+                loop {
+                    match parse.next_string() {
+                        Ok(name) => params.push((name, parse.next_string()?)),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Self::Set(params)
+            },
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("config".as_bytes()));
+        match self {
+            Self::Get(patterns) => {
+                frm.push_bulk(Bytes::from("get".as_bytes()));
+                for p in patterns {
+                    frm.push_bulk(Bytes::from(p.into_bytes()));
+                }
+            },
+            Self::Set(params) => {
+                frm.push_bulk(Bytes::from("set".as_bytes()));
+                for (name, value) in params {
+                    frm.push_bulk(Bytes::from(name.into_bytes()));
+                    frm.push_bulk(Bytes::from(value.into_bytes()));
+                }
+            },
+        }
+        frm
+    }
+}
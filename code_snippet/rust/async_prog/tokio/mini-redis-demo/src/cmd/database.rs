@@ -0,0 +1,288 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+// commands on the logical databases as a whole. The index selected by a
+// connection lives in its session, the server hands the handle of that
+// database to each command, see `FakeDatabase::select()`
+#[derive(Debug)]
+pub struct Select {
+    index: usize,
+}
+
+#[derive(Debug)]
+pub struct Move {
+    key: String,
+    // destination database
+    index: usize,
+}
+
+#[derive(Debug)]
+pub struct SwapDb {
+    first: usize,
+    second: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct DbSize;
+
+// `FLUSHDB` on the selected database, or `FLUSHALL`
+#[derive(Debug)]
+pub struct Flush {
+    all: bool,
+    // `ASYNC`, memory is released in background
+    lazy: bool,
+}
+
+impl Select {
+    pub fn new(index:usize) -> Self { Self{index} }
+}
+
+impl Flush {
+    pub(crate) fn parse_frames_of(parse: &mut Parse, all:bool)
+        -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let lazy = match parse.next_string() {
+            Ok(mode) if mode.eq_ignore_ascii_case("async") => true,
+            Ok(mode) if mode.eq_ignore_ascii_case("sync") => false,
+            Ok(_) => return Err("syntax error".into()),
+            Err(ParseError::EndOfStream) => false,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Box::new(Self{all, lazy}))
+    }
+}
+
+// error reply if the database index cannot be used on this server
+fn check_index(db:&FakeDatabase, cmd_name:&str, index:usize) -> AsyncResult<Option<Frame>>
+{
+    if db.cluster().is_enabled() && index != 0 {
+        let e = format!("ERR {} is not allowed in cluster mode", cmd_name);
+        return Ok(Some(Frame::Error(e)));
+    }
+    if index >= db.num_dbs()? {
+        return Ok(Some(Frame::Error("ERR DB index is out of range".to_string())));
+    }
+    Ok(None)
+}
+
+fn ok_frame() -> Frame { Frame::Simple("OK".to_string()) }
+
+#[async_trait]
+impl PubCommand for Select {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match check_index(db, "SELECT", self.index)? {
+            Some(e) => e,
+            None => {
+                let (id, index) = (dst.session().id, self.index);
+                dst.session_mut().db = index;
+                db.clients().update(id, |c| c.db = index);
+                ok_frame()
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PubCommand for Move {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match check_index(db, "MOVE", self.index)? {
+            Some(e) => e,
+            None if self.index == db.index() => Frame::Error(
+                "ERR source and destination objects are the same".to_string()),
+            None => Frame::Integer(db.move_key(&self.key, self.index)? as u64),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+
+    fn is_write(&self) -> bool { true }
+
+    fn keys(&self) -> Vec<&str> { vec![self.key.as_str()] }
+
+    fn aof_frame(&self) -> Option<Frame> {
+        Some(Self{ key: self.key.clone(), index: self.index }.into_frame())
+    }
+}
+
+#[async_trait]
+impl PubCommand for SwapDb {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let checked = match check_index(db, "SWAPDB", self.first)? {
+            Some(e) => Some(e),
+            None => check_index(db, "SWAPDB", self.second)?,
+        };
+        let response = match checked {
+            Some(e) => e,
+            None => {
+                db.swap_db(self.first, self.second)?;
+                ok_frame()
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+
+    fn is_write(&self) -> bool { true }
+
+    fn aof_frame(&self) -> Option<Frame> {
+        Some(Self{ first: self.first, second: self.second }.into_frame())
+    }
+}
+
+#[async_trait]
+impl PubCommand for DbSize {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = Frame::Integer(db.dbsize()? as u64);
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PubCommand for Flush {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        db.flush(self.all, self.lazy)?;
+        dst.write_frame(&ok_frame()).await ?;
+        Ok(())
+    }
+
+    fn is_write(&self) -> bool { true }
+
+    fn aof_frame(&self) -> Option<Frame> {
+        Some(Self{ all: self.all, lazy: self.lazy }.into_frame())
+    }
+}
+
+fn args_frame(args:&[&str]) -> Frame
+{
+    let mut frm = Frame::array();
+    for a in args {
+        frm.push_bulk(Bytes::from(a.to_string().into_bytes()));
+    }
+    frm
+}
+
+// Commands refused by `apply()` are still logged, they are no-op on
+// replay as well instead of failing to load the append-only file.
+impl PrivCommand for Select {
+    // # Format
+    // ```text
+    // SELECT index
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let index = parse.next_int()? as usize;
+        Ok(Box::new(Self{index}))
+    }
+    fn replay(&self, db:&mut FakeDatabase) -> AsyncResult<()>
+    {
+        if self.index >= db.num_dbs()? {
+            return Err(format!("DB index {} is out of range", self.index).into());
+        }
+        *db = db.select(self.index);
+        Ok(())
+    }
+    fn into_frame(self) -> Frame
+    {
+        args_frame(&["select", &self.index.to_string()])
+    }
+}
+
+impl PrivCommand for Move {
+    // # Format
+    // ```text
+    // MOVE key db
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let key = parse.next_string()?;
+        let index = parse.next_int()? as usize;
+        Ok(Box::new(Self{key, index}))
+    }
+    fn replay(&self, db:&mut FakeDatabase) -> AsyncResult<()>
+    {
+        if self.index < db.num_dbs()? && self.index != db.index() {
+            db.move_key(&self.key, self.index)?;
+        }
+        Ok(())
+    }
+    fn into_frame(self) -> Frame
+    {
+        args_frame(&["move", &self.key, &self.index.to_string()])
+    }
+}
+
+impl PrivCommand for SwapDb {
+    // # Format
+    // ```text
+    // SWAPDB index1 index2
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let first = parse.next_int()? as usize;
+        let second = parse.next_int()? as usize;
+        Ok(Box::new(Self{first, second}))
+    }
+    fn replay(&self, db:&mut FakeDatabase) -> AsyncResult<()>
+    {
+        let num_dbs = db.num_dbs()?;
+        if self.first < num_dbs && self.second < num_dbs {
+            db.swap_db(self.first, self.second)?;
+        }
+        Ok(())
+    }
+    fn into_frame(self) -> Frame
+    {
+        args_frame(&["swapdb", &self.first.to_string(), &self.second.to_string()])
+    }
+}
+
+impl PrivCommand for DbSize {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        args_frame(&["dbsize"])
+    }
+}
+
+impl PrivCommand for Flush {
+    // # Format
+    // ```text
+    // FLUSHDB [ASYNC | SYNC]
+    // FLUSHALL [ASYNC | SYNC]
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Self::parse_frames_of(parse, false)
+    }
+    fn replay(&self, db:&mut FakeDatabase) -> AsyncResult<()>
+    {
+        db.flush(self.all, self.lazy)?;
+        Ok(())
+    }
+    fn into_frame(self) -> Frame
+    {
+        let name = if self.all {"flushall"} else {"flushdb"};
+        let mode = if self.lazy {"async"} else {"sync"};
+        args_frame(&[name, mode])
+    }
+}
@@ -0,0 +1,70 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+// Besides clients, the server itself logs `DEL` for keys removed by
+// eviction and expiry, so the append-only file and the replicas drop them
+// as well.
+#[derive(Debug)]
+pub struct Del {
+    keys: Vec<String>,
+}
+
+impl Del {
+    pub fn new(keys:Vec<String>) -> Self { Self{keys} }
+}
+
+#[async_trait]
+impl PubCommand for Del {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = Frame::Integer(db.del(&self.keys)? as u64);
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+
+    fn is_write(&self) -> bool { true }
+
+    fn keys(&self) -> Vec<&str> { self.keys.iter().map(|k| k.as_str()).collect() }
+
+    fn aof_frame(&self) -> Option<Frame> {
+        Some(Self::new(self.keys.clone()).into_frame())
+    }
+}
+
+impl PrivCommand for Del {
+    // # Format
+    // ```text
+    // DEL key [key ...]
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let mut keys = vec![parse.next_string()?];
+        loop {
+            match parse.next_string() {
+                Ok(k) => keys.push(k),
+                Err(ParseError::EndOfStream) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Box::new(Self{keys}))
+    }
+    fn replay(&self, db:&mut FakeDatabase) -> AsyncResult<()>
+    {
+        db.del(&self.keys)?;
+        Ok(())
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("del".as_bytes()));
+        for k in self.keys {
+            frm.push_bulk(Bytes::from(k.into_bytes()));
+        }
+        frm
+    }
+}
@@ -19,6 +19,10 @@ impl Get {
 
 #[async_trait]
 impl PubCommand for Get {
+    fn keys(&self) -> Vec<&str> { vec![self.key.as_str()] }
+
+    fn is_read(&self) -> bool { true }
+
     // Apply the `Get` command to the specified `Db` instance.
     // The response is written to `dst`. This is called by the server in order
     // to execute a received command.
@@ -0,0 +1,58 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+use crate::info::render_info;
+
+// report state of the server in sections, see `info` module
+#[derive(Debug, Default)]
+pub struct Info {
+    sections: Vec<String>,
+}
+
+impl Info {
+    pub fn new(sections:&[&str]) -> Self {
+        Self{ sections: sections.iter().map(|s| s.to_string()).collect() }
+    }
+}
+
+#[async_trait]
+impl PubCommand for Info {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let text = render_info(db, &self.sections).await?;
+        dst.write_frame(&Frame::Bulk(Bytes::from(text.into_bytes()))).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Info {
+    // # Format
+    // ```text
+    // INFO [section [section ...]]
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let mut sections = Vec::new();
+        loop {
+            match parse.next_string() {
+                Ok(s) => sections.push(s),
+                Err(ParseError::EndOfStream) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Box::new(Self{sections}))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("info".as_bytes()));
+        for s in self.sections {
+            frm.push_bulk(Bytes::from(s.into_bytes()));
+        }
+        frm
+    }
+}
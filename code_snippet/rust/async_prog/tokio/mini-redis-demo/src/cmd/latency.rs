@@ -0,0 +1,96 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+#[derive(Debug)]
+pub enum Latency {
+    Latest,
+    History(String),
+    // all events if empty
+    Reset(Vec<String>),
+}
+
+#[async_trait]
+impl PubCommand for Latency {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let monitor = db.latency();
+        let response = match self {
+            // event name, time of the latest spike, its latency, max latency
+            Self::Latest => {
+                let items = monitor.latest()?.into_iter().map(|(name, sample, max_ms)| {
+                    Frame::Array(vec![
+                        Frame::Bulk(Bytes::from(name.into_bytes())),
+                        Frame::Integer(sample.timestamp),
+                        Frame::Integer(sample.latency_ms),
+                        Frame::Integer(max_ms),
+                    ])
+                }).collect();
+                Frame::Array(items)
+            },
+            Self::History(event) => {
+                let items = monitor.history(event)?.into_iter().map(|sample| {
+                    Frame::Array(vec![Frame::Integer(sample.timestamp),
+                                      Frame::Integer(sample.latency_ms)])
+                }).collect();
+                Frame::Array(items)
+            },
+            Self::Reset(events) => Frame::Integer(monitor.reset(events)? as u64),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Latency {
+    // # Format
+    // ```text
+    // LATENCY LATEST
+    // LATENCY HISTORY event
+    // LATENCY RESET [event [event ...]]
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "latest" => Self::Latest,
+            "history" => Self::History(parse.next_string()?),
+            "reset" => {
+                let mut events = Vec::new();
+                loop {
+                    match parse.next_string() {
+                        Ok(s) => events.push(s),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Self::Reset(events)
+            },
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("latency".as_bytes()));
+        match self {
+            Self::Latest => frm.push_bulk(Bytes::from("latest".as_bytes())),
+            Self::History(event) => {
+                frm.push_bulk(Bytes::from("history".as_bytes()));
+                frm.push_bulk(Bytes::from(event.into_bytes()));
+            },
+            Self::Reset(events) => {
+                frm.push_bulk(Bytes::from("reset".as_bytes()));
+                for e in events {
+                    frm.push_bulk(Bytes::from(e.into_bytes()));
+                }
+            },
+        }
+        frm
+    }
+}
@@ -1,8 +1,10 @@
 use crate::{Frame, Connection, Parse, AsyncResult, SingleRequestShutdown};
 use crate::db::FakeDatabase;
+use crate::pubsub::ChannelKind;
 use crate::cmd::private_part::Command as PrivCommand;
 
 use async_trait::async_trait;
+use bytes::Bytes;
 
 mod get;
 pub use get::Get;
@@ -10,6 +12,9 @@ pub use get::Get;
 mod set;
 pub use set::Set;
 
+mod del;
+pub use del::Del;
+
 mod publish;
 pub use publish::Publish;
 
@@ -17,6 +22,57 @@ mod subscribe;
 pub use subscribe::{ Subscribe, Unsubscribe, 
     CommonInit as SubscribeCommonInit};
 
+mod ping;
+pub use ping::Ping;
+
+mod quit;
+pub use quit::Quit;
+
+mod reset;
+pub use reset::Reset;
+
+mod save;
+pub use save::{Save, BgSave, BgRewriteAof};
+
+mod replication;
+pub use replication::{ReplicaOf, Role, Psync, ReplConf};
+
+mod cluster;
+pub use cluster::{Cluster, Asking};
+
+mod config;
+pub use config::Config;
+
+mod shutdown;
+pub use shutdown::Shutdown;
+
+mod auth;
+pub use auth::Auth;
+
+mod acl;
+pub use acl::Acl;
+
+mod info;
+pub use info::Info;
+
+mod slowlog;
+pub use slowlog::Slowlog;
+
+mod latency;
+pub use latency::Latency;
+
+mod monitor;
+pub use monitor::Monitor;
+
+mod client;
+pub use client::Client;
+
+mod object;
+pub use object::{Object, Memory};
+
+mod database;
+pub use database::{Select, Move, SwapDb, DbSize, Flush};
+
 mod unknown;
 pub use unknown::Unknown;
 
@@ -36,8 +92,44 @@ pub fn from_frame(frm:Frame) -> AsyncResult<Box<dyn Command>>
     let obj = match cmd_name_ref {
         "get" => Get::parse_frames(&mut parsed)?,
         "set" => Set::parse_frames(&mut parsed)?,
+        "del" => Del::parse_frames(&mut parsed)?,
         "publish" => Publish::parse_frames(&mut parsed)?,
+        "spublish" => Publish::parse_frames_of(&mut parsed, ChannelKind::Sharded)?,
         "subscribe" => Subscribe::parse_frames(&mut parsed)?,
+        "ssubscribe" => Subscribe::parse_frames_of(&mut parsed, ChannelKind::Sharded)?,
+        "unsubscribe" => Unsubscribe::parse_frames(&mut parsed)?,
+        "sunsubscribe" => Unsubscribe::parse_frames_of(&mut parsed, ChannelKind::Sharded)?,
+        "psubscribe" => Subscribe::parse_frames_of(&mut parsed, ChannelKind::Pattern)?,
+        "punsubscribe" => Unsubscribe::parse_frames_of(&mut parsed, ChannelKind::Pattern)?,
+        "ping" => Ping::parse_frames(&mut parsed)?,
+        "quit" => Quit::parse_frames(&mut parsed)?,
+        "reset" => Reset::parse_frames(&mut parsed)?,
+        "save" => Save::parse_frames(&mut parsed)?,
+        "bgsave" => BgSave::parse_frames(&mut parsed)?,
+        "bgrewriteaof" => BgRewriteAof::parse_frames(&mut parsed)?,
+        "replicaof" => ReplicaOf::parse_frames(&mut parsed)?,
+        "role" => Role::parse_frames(&mut parsed)?,
+        "psync" => Psync::parse_frames(&mut parsed)?,
+        "replconf" => ReplConf::parse_frames(&mut parsed)?,
+        "cluster" => Cluster::parse_frames(&mut parsed)?,
+        "asking" => Asking::parse_frames(&mut parsed)?,
+        "config" => Config::parse_frames(&mut parsed)?,
+        "shutdown" => Shutdown::parse_frames(&mut parsed)?,
+        "auth" => Auth::parse_frames(&mut parsed)?,
+        "acl" => Acl::parse_frames(&mut parsed)?,
+        "info" => Info::parse_frames(&mut parsed)?,
+        "slowlog" => Slowlog::parse_frames(&mut parsed)?,
+        "latency" => Latency::parse_frames(&mut parsed)?,
+        "monitor" => Monitor::parse_frames(&mut parsed)?,
+        "client" => Client::parse_frames(&mut parsed)?,
+        "object" => Object::parse_frames(&mut parsed)?,
+        "memory" => Memory::parse_frames(&mut parsed)?,
+        "select" => Select::parse_frames(&mut parsed)?,
+        "move" => Move::parse_frames(&mut parsed)?,
+        "swapdb" => SwapDb::parse_frames(&mut parsed)?,
+        "dbsize" => DbSize::parse_frames(&mut parsed)?,
+        "flushdb" => Flush::parse_frames_of(&mut parsed, false)?,
+        "flushall" => Flush::parse_frames_of(&mut parsed, true)?,
         _others => Unknown::parse_frames(&mut parsed)?,
     };
     // Check if there is any remaining unconsumed fields in the `Parse`
@@ -48,6 +140,66 @@ pub fn from_frame(frm:Frame) -> AsyncResult<Box<dyn Command>>
     Ok(obj)
 }
 
+// lowercase name of the command in the frame, e.g. for checking
+// permissions of the client
+pub fn command_name(frm:&Frame) -> Option<String>
+{
+    match frm {
+        Frame::Array(items) => match items.first() {
+            Some(Frame::Bulk(b)) => std::str::from_utf8(b).ok().map(|s| s.to_lowercase()),
+            Some(Frame::Simple(s)) => Some(s.to_lowercase()),
+            _others => None,
+        },
+        _others => None,
+    }
+}
+
+// all arguments of the command including its name, the frame data is
+// shared, not copied
+pub fn command_args(frm:&Frame) -> Vec<Bytes>
+{
+    match frm {
+        Frame::Array(items) => items.iter().filter_map(|item| match item {
+            Frame::Bulk(b) => Some(b.clone()),
+            Frame::Simple(s) => Some(Bytes::from(s.clone().into_bytes())),
+            Frame::Integer(n) => Some(Bytes::from(n.to_string().into_bytes())),
+            _others => None,
+        }).collect(),
+        _others => Vec::new(),
+    }
+}
+
+// Checks before applying the command, returns error frame sent to the
+// client instead if the command should not run on this server, or the
+// client is not allowed to run it.
+pub fn precheck(name:&str, cmdobj:&dyn Command, db:&FakeDatabase, dst:&mut Connection)
+    -> Option<Frame>
+{
+    let asking = std::mem::take(&mut dst.session_mut().asking);
+    let user = dst.session().user.as_deref();
+    if let Some(e) = db.acl().check(user, name, &cmdobj.keys()) {
+        return Some(Frame::Error(e));
+    }
+    if cmdobj.is_write() && db.replication().is_replica() {
+        return Some(Frame::Error(
+            "READONLY You can't write against a read only replica.".to_string()));
+    }
+    let keys = cmdobj.keys();
+    if !keys.is_empty() {
+        let exists = |k:&str| db.exists(k).unwrap_or(false);
+        if let Some(e) = db.cluster().route(&keys, asking, &exists) {
+            return Some(e);
+        }
+    }
+    // make room for the data the command may add, commands which only
+    // remove data, e.g. `DEL` or `FLUSHDB`, still run when memory is full
+    if cmdobj.may_grow() && !db.evict_if_needed().unwrap_or(true) {
+        return Some(Frame::Error(
+            "OOM command not allowed when used memory > 'maxmemory'.".to_string()));
+    }
+    None
+}
+
 // It is unnecessary to add visibility qualifier like `pub` or `pub crate`
 // in concrete type methods implementing any trait which defines public abstract
 // functions, the visibility of these concrete types will be implied by
@@ -63,6 +215,40 @@ pub trait Command : PrivCommand + Send
     //    because the caller doesn't know the size.
     async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
                    shutdown:&mut SingleRequestShutdown) -> AsyncResult<()>;
+
+    // Frame appended to the append-only file and sent to the replicas,
+    // only commands which modify the store return the frame. It is logged
+    // when the command changes the store, see `FakeDatabase::log_next()`.
+    // The frame can be different from the one received, e.g. relative
+    // expiry time is converted to absolute time so it is still valid on
+    // replay.
+    fn aof_frame(&self) -> Option<Frame> { None }
+
+    // write commands are refused on a replica, its data set only follows
+    // the primary
+    fn is_write(&self) -> bool { false }
+
+    // write commands which may add data, they are refused while memory
+    // is over `maxmemory` and nothing can be evicted
+    fn may_grow(&self) -> bool { false }
+
+    // keys accessed by the command, in cluster mode they decide which
+    // node serves the command
+    fn keys(&self) -> Vec<&str> { Vec::new() }
+
+    // commands returning values of their keys, the keys are remembered
+    // for clients with `CLIENT TRACKING` enabled as they are read, see
+    // `FakeDatabase::track_next()`
+    fn is_read(&self) -> bool { false }
+
+    // commands not supported by the server are not counted in
+    // statistics, see `Unknown`
+    fn is_known(&self) -> bool { true }
+
+    // commands holding the connection until the client leaves, e.g.
+    // `SUBSCRIBE` or `MONITOR`, their duration is not a latency so they
+    // are left out of the slow log, latency monitor and histograms
+    fn is_streaming(&self) -> bool { false }
 } // end of trait
 
 
@@ -70,7 +256,8 @@ pub(crate) mod private_part {
     use std::io::{Error as IoError, ErrorKind};
     use crate::{Frame, Parse, AsyncResult};
     use crate::cmd::Command as PubCommand;
-    
+    use crate::db::FakeDatabase;
+
     pub trait Command {
         fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
             where Self: Sized // required for object safety
@@ -79,6 +266,16 @@ pub(crate) mod private_part {
             Err(Box::new(e))
         }
         fn into_frame(self) -> Frame;
+
+        // apply the command to the store without any client connection,
+        // this is for replaying commands loaded from the append-only file,
+        // see `Command::aof_frame()`. `SELECT` switches `db` to another
+        // database for the commands after it.
+        fn replay(&self, _db:&mut FakeDatabase) -> AsyncResult<()>
+        {
+            let e = IoError::new( ErrorKind::Unsupported, "not a write command");
+            Err(Box::new(e))
+        }
     }
 } // end of trait
 
@@ -0,0 +1,101 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::{Connection, AsyncResult, Parse, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::cmd::{Ping, Reset};
+use crate::db::{FakeDatabase, LagPolicy};
+
+// turn the connection into a feed of all commands processed by the
+// server, one simple string per command, see `monitor::MonitorHub`
+#[derive(Debug, Default)]
+pub struct Monitor;
+
+#[async_trait]
+impl PubCommand for Monitor {
+    fn is_streaming(&self) -> bool { true }
+
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   shutdown:&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let mut rx = db.monitors().subscribe();
+        let client_id = dst.session().id;
+        db.clients().update(client_id, |c| c.monitor = true);
+        dst.write_frame(&Frame::Simple("OK".to_string())).await ?;
+        let mut monitoring = true;
+        while monitoring && !shutdown.is_shutdown() {
+            tokio::select! {
+                result = rx.recv() => match result {
+                    Ok(line) => dst.write_frame(&Frame::Simple(line)).await?,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(num_dropped = n, "monitor too slow, lines dropped");
+                        if db.lag_policy() == LagPolicy::Disconnect {
+                            shutdown.terminate();
+                        }
+                    },
+                    Err(RecvError::Closed) => break,
+                },
+                result = dst.read_frame() => {
+                    let result = match result {Ok(r) => r, _others => break}; // network error
+                    let frm = match result {Some(f) => f, None => break}; // end of stream
+                    let (reply, keep) = handle_cmd_in_stream(frm, db, dst, shutdown)?;
+                    monitoring = keep;
+                    dst.write_frame(&reply).await?;
+                },
+                _ = shutdown.recv() => {
+                    debug!("receive shutdown when streaming to monitor");
+                },
+            }
+        }
+        db.clients().update(client_id, |c| c.monitor = false);
+        Ok(())
+    }
+}
+
+// commands received while monitoring, returns the reply and whether the
+// connection stays in monitor mode
+fn handle_cmd_in_stream(frm:Frame, db:&FakeDatabase, dst:&mut Connection,
+                        shutdown:&mut SingleRequestShutdown) -> AsyncResult<(Frame, bool)>
+{
+    let mut parsed = Parse::new(frm)?;
+    let command_name = parsed.next_string()?.to_lowercase();
+    let out = match &command_name[..] {
+        "ping" => {
+            // the optional message is ignored
+            Ping::parse_args(&mut parsed)?;
+            (Frame::Simple("PONG".to_string()), true)
+        },
+        "quit" => {
+            shutdown.terminate();
+            (Frame::Simple("OK".to_string()), false)
+        },
+        "reset" => {
+            Reset::reset_session(db, dst)?;
+            (Reset::make_response(), false)
+        },
+        // the rest of arguments are not checked
+        _others => return Ok((Frame::Error(format!("not supported in stream, type:{}",
+                                                   command_name)), true)),
+    };
+    parsed.finish()?;
+    Ok(out)
+}
+
+impl PrivCommand for Monitor {
+    // # Format
+    // ```text
+    // MONITOR
+    // ```
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("monitor".as_bytes()));
+        frm
+    }
+}
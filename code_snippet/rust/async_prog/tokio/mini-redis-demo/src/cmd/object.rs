@@ -0,0 +1,145 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+// internals of a key, see `db::ObjectInfo`, none of them touches the key
+#[derive(Debug)]
+pub enum Object {
+    Freq(String),
+    IdleTime(String),
+    Encoding(String),
+}
+
+#[derive(Debug)]
+pub enum Memory {
+    Usage(String),
+}
+
+#[async_trait]
+impl PubCommand for Object {
+    fn keys(&self) -> Vec<&str> {
+        match self {
+            Self::Freq(k) | Self::IdleTime(k) | Self::Encoding(k) => vec![k.as_str()],
+        }
+    }
+
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let is_lfu = db.config().current()?.maxmemory_policy.is_lfu();
+        let response = match self {
+            Self::Freq(_) if !is_lfu => Frame::Error(
+                "ERR An LFU maxmemory policy is not selected, access frequency not tracked. \
+                 Please note that when switching between policies at runtime LRU and LFU \
+                 data will take some time to adjust.".to_string()),
+            Self::IdleTime(_) if is_lfu => Frame::Error(
+                "ERR An LFU maxmemory policy is selected, idle time not tracked. Please note \
+                 that when switching between policies at runtime LRU and LFU data will take \
+                 some time to adjust.".to_string()),
+            Self::Freq(k) | Self::IdleTime(k) | Self::Encoding(k) => match db.object_info(k)? {
+                Some(info) => match self {
+                    Self::Freq(_) => Frame::Integer(info.freq as u64),
+                    Self::IdleTime(_) => Frame::Integer(info.idle.as_secs()),
+                    Self::Encoding(_) => Frame::Bulk(Bytes::from_static(info.encoding.as_bytes())),
+                },
+                None => Frame::Null,
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PubCommand for Memory {
+    fn keys(&self) -> Vec<&str> {
+        match self {
+            Self::Usage(k) => vec![k.as_str()],
+        }
+    }
+
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match self {
+            Self::Usage(k) => match db.object_info(k)? {
+                Some(info) => Frame::Integer(info.size as u64),
+                None => Frame::Null,
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Object {
+    // # Format
+    // ```text
+    // OBJECT FREQ | IDLETIME | ENCODING key
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "freq" => Self::Freq(parse.next_string()?),
+            "idletime" => Self::IdleTime(parse.next_string()?),
+            "encoding" => Self::Encoding(parse.next_string()?),
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let (sub, key) = match self {
+            Self::Freq(k) => ("freq", k),
+            Self::IdleTime(k) => ("idletime", k),
+            Self::Encoding(k) => ("encoding", k),
+        };
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("object".as_bytes()));
+        frm.push_bulk(Bytes::from(sub.as_bytes()));
+        frm.push_bulk(Bytes::from(key.into_bytes()));
+        frm
+    }
+}
+
+impl PrivCommand for Memory {
+    // # Format
+    // ```text
+    // MEMORY USAGE key [SAMPLES count]
+    // ```
+    // `SAMPLES` is accepted for compatibility, values are never sampled
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "usage" => {
+                let key = parse.next_string()?;
+                match parse.next_string() {
+                    Ok(opt) if opt.eq_ignore_ascii_case("samples") => { parse.next_int()?; },
+                    Ok(_) => return Err("syntax error".into()),
+                    Err(ParseError::EndOfStream) => {},
+                    Err(e) => return Err(e.into()),
+                }
+                Self::Usage(key)
+            },
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("memory".as_bytes()));
+        match self {
+            Self::Usage(k) => {
+                frm.push_bulk(Bytes::from("usage".as_bytes()));
+                frm.push_bulk(Bytes::from(k.into_bytes()));
+            },
+        }
+        frm
+    }
+}
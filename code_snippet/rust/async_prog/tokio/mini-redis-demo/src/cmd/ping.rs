@@ -0,0 +1,72 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+#[derive(Debug, Default)]
+pub struct Ping {
+    // optional payload echoed back by the server
+    msg: Option<Bytes>,
+}
+
+impl Ping {
+    pub fn new(msg: Option<Bytes>) -> Self {
+        Self {msg}
+    }
+
+    // # Format
+    // ```text
+    // PING [message]
+    // ```
+    pub(crate) fn parse_args(parse: &mut Parse) -> AsyncResult<Self> {
+        match parse.next_bytes() {
+            Ok(msg) => Ok(Self{msg:Some(msg)}),
+            Err(ParseError::EndOfStream) => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // A client in subscriber mode receives array frame in the form of
+    // `[ "pong", message ]`, the message is empty if not given, so the
+    // client can tell the reply from published messages.
+    pub(crate) fn make_stream_response(&self) -> Frame {
+        let payload = self.msg.clone().unwrap_or_default();
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from_static(b"pong"));
+        frm.push_bulk(payload);
+        frm
+    }
+}
+
+#[async_trait]
+impl PubCommand for Ping {
+    async fn apply(&self, _db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match &self.msg {
+            Some(m) => Frame::Bulk(m.clone()),
+            None => Frame::Simple("PONG".to_string()),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Ping {
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let obj = Self::parse_args(parse)?;
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("ping".as_bytes()));
+        if let Some(msg) = self.msg {
+            frm.push_bulk(msg);
+        }
+        frm
+    }
+}
@@ -1,28 +1,52 @@
+
 use bytes::Bytes;
 use async_trait::async_trait;
 
 use crate::{Connection, AsyncResult, Parse, Frame, SingleRequestShutdown};
 use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
 use crate::db::FakeDatabase;
+use crate::pubsub::ChannelKind;
 
 #[derive(Debug)]
 pub struct Publish {
     channel: String,
     message: Bytes,
+    // `PUBLISH` or `SPUBLISH`
+    kind: ChannelKind,
 }
 
 impl Publish {
     pub(crate) fn new(chn:impl ToString, msg:Bytes) -> Self {
-        Self{channel: chn.to_string(), message:msg}
+        Self{channel: chn.to_string(), message:msg, kind:ChannelKind::Global}
+    }
+    pub(crate) fn new_sharded(chn:impl ToString, msg:Bytes) -> Self {
+        Self{channel: chn.to_string(), message:msg, kind:ChannelKind::Sharded}
+    }
+    pub(crate) fn parse_frames_of(parse: &mut Parse, kind:ChannelKind)
+        -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let channel = parse.next_string()?;
+        let message = parse.next_bytes()?;
+        let out = Box::new(Self{channel, message, kind});
+        Ok(out)
     }
 }
 
 #[async_trait]
 impl PubCommand for Publish {
+    // shard channels are distributed over the cluster like keys, while
+    // global channels are served by every node
+    fn keys(&self) -> Vec<&str> {
+        match self.kind {
+            ChannelKind::Sharded => vec![self.channel.as_str()],
+            ChannelKind::Global | ChannelKind::Pattern => Vec::new(),
+        }
+    }
+
     async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
                    _ :&mut SingleRequestShutdown) -> AsyncResult<()>
     {
-        let response = match db.publish(&self.channel, &self.message)
+        let response = match db.publish(self.kind, &self.channel, &self.message).await
         {
             Ok(num_subsribers) => Frame::Integer(num_subsribers as u64),
             Err(e) => Frame::Error(e.to_string()),
@@ -35,15 +59,13 @@ impl PubCommand for Publish {
 impl PrivCommand for Publish {
     fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
     {
-        let channel = parse.next_string()?;
-        let message = parse.next_bytes()?;
-        let out = Box::new(Self{channel, message});
-        Ok(out)
+        Self::parse_frames_of(parse, ChannelKind::Global)
     }
     fn into_frame(self) -> Frame
     {
         let mut out = Frame::array();
-        out.push_bulk(Bytes::from("publish".to_string()));
+        let cmd_name = format!("{}publish", self.kind.prefix());
+        out.push_bulk(Bytes::from(cmd_name));
         out.push_bulk(Bytes::from(self.channel.into_bytes()));
         out.push_bulk(self.message);
         out
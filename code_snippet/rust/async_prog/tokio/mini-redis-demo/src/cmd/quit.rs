@@ -0,0 +1,35 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+// ask the server to close the connection after the reply is sent
+#[derive(Debug, Default)]
+pub struct Quit;
+
+#[async_trait]
+impl PubCommand for Quit {
+    async fn apply(&self, _db:&FakeDatabase, dst:&mut Connection,
+                   shutdown:&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = Frame::Simple("OK".to_string());
+        dst.write_frame(&response).await ?;
+        shutdown.terminate();
+        Ok(())
+    }
+}
+
+impl PrivCommand for Quit {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("quit".as_bytes()));
+        frm
+    }
+}
@@ -0,0 +1,314 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+use crate::replication::{RoleInfo, SyncPlan};
+
+// `REPLICAOF host port` follows another server, `REPLICAOF NO ONE` turns
+// the replica back to primary
+#[derive(Debug)]
+pub struct ReplicaOf {
+    // `None` means `NO ONE`
+    primary: Option<(String, u16)>,
+}
+
+// report whether the server is primary or replica, and the offsets
+#[derive(Debug, Default)]
+pub struct Role;
+
+// sent by replica to primary, the connection then carries the command
+// stream until either side closes it
+#[derive(Debug)]
+pub struct Psync {
+    replid: String,
+    // bytes of the stream the replica already applied, `None` for `-1`
+    offset: Option<u64>,
+}
+
+// options a replica reports to its primary before `PSYNC`
+#[derive(Debug, Default)]
+pub struct ReplConf {
+    options: Vec<(String, String)>,
+}
+
+impl ReplicaOf {
+    pub fn new(primary:Option<(&str, u16)>) -> Self {
+        Self{ primary: primary.map(|(h, p)| (h.to_string(), p)) }
+    }
+}
+
+impl Psync {
+    pub fn new(replid:impl ToString, offset:Option<u64>) -> Self {
+        Self{ replid: replid.to_string(), offset }
+    }
+}
+
+#[async_trait]
+impl PubCommand for ReplicaOf {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let repl = db.replication();
+        let response = match &self.primary {
+            Some((host, port)) => match repl.replicate_of(host, *port, db) {
+                Ok(true) => Frame::Simple("OK".to_string()),
+                Ok(false) => Frame::Simple("OK Already connected to specified master".to_string()),
+                Err(e) => Frame::Error(format!("ERR {}", e)),
+            },
+            None => match repl.promote() {
+                Ok(_) => Frame::Simple("OK".to_string()),
+                Err(e) => Frame::Error(format!("ERR {}", e)),
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for ReplicaOf {
+    // # Format
+    // ```text
+    // REPLICAOF host port
+    // REPLICAOF NO ONE
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let host = parse.next_string()?;
+        let port = parse.next_string()?;
+        let primary = if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            None
+        } else {
+            let port = port.parse::<u16>().map_err(|_| "invalid master port")?;
+            Some((host, port))
+        };
+        Ok(Box::new(Self{primary}))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("replicaof".as_bytes()));
+        match self.primary {
+            Some((host, port)) => {
+                frm.push_bulk(Bytes::from(host.into_bytes()));
+                frm.push_bulk(Bytes::from(port.to_string().into_bytes()));
+            },
+            None => {
+                frm.push_bulk(Bytes::from("no".as_bytes()));
+                frm.push_bulk(Bytes::from("one".as_bytes()));
+            },
+        }
+        frm
+    }
+}
+
+#[async_trait]
+impl PubCommand for Role {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match db.replication().role() {
+            Ok(info) => role_frame(info),
+            Err(e) => Frame::Error(format!("ERR {}", e)),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Role {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("role".as_bytes()));
+        frm
+    }
+}
+
+// same layout as Redis :
+// - `["master", offset, [[ip, port, offset], ...]]`
+// - `["slave", host, port, state, offset]`
+fn role_frame(info:RoleInfo) -> Frame
+{
+    let mut frm = Frame::array();
+    match info {
+        RoleInfo::Primary{offset, replicas} => {
+            frm.push_bulk(Bytes::from_static(b"master"));
+            frm.push_int(offset);
+            let replicas = replicas.into_iter().map(|(ip, port, ack)| {
+                let mut r = Frame::array();
+                r.push_bulk(Bytes::from(ip.into_bytes()));
+                r.push_bulk(Bytes::from(port.to_string().into_bytes()));
+                r.push_bulk(Bytes::from(ack.to_string().into_bytes()));
+                r
+            }).collect();
+            if let Frame::Array(items) = &mut frm {
+                items.push(Frame::Array(replicas));
+            }
+        },
+        RoleInfo::Replica{host, port, state, offset} => {
+            frm.push_bulk(Bytes::from_static(b"slave"));
+            frm.push_bulk(Bytes::from(host.into_bytes()));
+            frm.push_int(port as u64);
+            frm.push_bulk(Bytes::from_static(state.as_str().as_bytes()));
+            frm.push_int(offset);
+        },
+    }
+    frm
+}
+
+#[async_trait]
+impl PubCommand for Psync {
+    fn is_streaming(&self) -> bool { true }
+
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   shutdown:&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let repl = db.replication();
+        if repl.is_replica() {
+            // replicas of a replica are not supported, the stream of the
+            // primary cannot be forwarded after a full synchronization
+            // since it may need `SELECT` the primary doesn't send
+            let response = Frame::Error("ERR PSYNC is not supported by replicas".to_string());
+            dst.write_frame(&response).await ?;
+            return Ok(());
+        }
+        let (plan, mut stream) = repl.prepare_sync(&self.replid, self.offset, db)?;
+        db.stats().incr_sync(matches!(plan, SyncPlan::Continue{..}));
+        match plan {
+            SyncPlan::Continue{replid, backlog} => {
+                dst.write_frame(&Frame::Simple(format!("CONTINUE {}", replid))).await?;
+                dst.write_raw(&backlog).await?;
+            },
+            SyncPlan::Full{replid, offset, snapshot} => {
+                let header = Frame::Simple(format!("FULLRESYNC {} {}", replid, offset));
+                dst.write_frame(&header).await?;
+                dst.write_frame(&Frame::Bulk(Bytes::from(snapshot))).await?;
+            },
+        }
+        let session = dst.session();
+        let id = repl.register_replica(session.peer_addr, session.listening_port)?;
+        while !shutdown.is_shutdown() {
+            tokio::select! {
+                chunk = stream.recv() => match chunk {
+                    Ok(data) => if dst.write_raw(&data).await.is_err() { break; },
+                    Err(RecvError::Lagged(n)) => {
+                        // the replica resumes from the backlog after reconnecting
+                        warn!(replica = id, num_chunks = n, "replica lagged behind");
+                        break;
+                    },
+                    Err(RecvError::Closed) => break,
+                },
+                result = dst.read_frame() => match result {
+                    Ok(Some(frm)) => if let Some(offset) = parse_ack(frm) {
+                        repl.update_ack(id, offset);
+                    },
+                    _others => break,
+                },
+                _ = shutdown.recv() => {},
+            }
+        }
+        repl.unregister_replica(id);
+        // the connection was taken over by the stream, it cannot serve
+        // other commands anymore
+        shutdown.terminate();
+        Ok(())
+    } // end of apply
+}
+
+impl PrivCommand for Psync {
+    // # Format
+    // ```text
+    // PSYNC replid offset
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let replid = parse.next_string()?;
+        let offset = parse.next_string()?.parse::<u64>().ok();
+        Ok(Box::new(Self{replid, offset}))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let offset = self.offset.map(|v| v.to_string()).unwrap_or_else(|| "-1".to_string());
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("psync".as_bytes()));
+        frm.push_bulk(Bytes::from(self.replid.into_bytes()));
+        frm.push_bulk(Bytes::from(offset.into_bytes()));
+        frm
+    }
+}
+
+// `REPLCONF ACK offset` the replica sends periodically during the stream
+fn parse_ack(frm:Frame) -> Option<u64>
+{
+    let mut parse = Parse::new(frm).ok()?;
+    let name = parse.next_string().ok()?;
+    let option = parse.next_string().ok()?;
+    if name.eq_ignore_ascii_case("replconf") && option.eq_ignore_ascii_case("ack") {
+        parse.next_int().ok()
+    } else {
+        None
+    }
+}
+
+#[async_trait]
+impl PubCommand for ReplConf {
+    async fn apply(&self, _db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let mut response = Frame::Simple("OK".to_string());
+        for (option, value) in self.options.iter() {
+            match option.to_lowercase().as_str() {
+                "listening-port" => match value.parse::<u16>() {
+                    Ok(port) => { dst.session_mut().listening_port = Some(port); },
+                    Err(_) => {
+                        response = Frame::Error("ERR invalid listening port".to_string());
+                    },
+                },
+                // acknowledgement is only meaningful in the stream, never replied
+                "ack" => return Ok(()),
+                _others => {},
+            }
+        }
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for ReplConf {
+    // # Format
+    // ```text
+    // REPLCONF option value [option value ...]
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let mut options = Vec::new();
+        loop {
+            let option = match parse.next_string() {
+                Ok(s) => s,
+                Err(ParseError::EndOfStream) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let value = parse.next_string()?;
+            options.push((option, value));
+        }
+        Ok(Box::new(Self{options}))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("replconf".as_bytes()));
+        for (option, value) in self.options {
+            frm.push_bulk(Bytes::from(option.into_bytes()));
+            frm.push_bulk(Bytes::from(value.into_bytes()));
+        }
+        frm
+    }
+}
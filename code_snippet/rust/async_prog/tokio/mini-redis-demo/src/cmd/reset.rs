@@ -0,0 +1,54 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+// bring the connection back to its initial state, a client in subscriber
+// mode unsubscribes all channels, see `Subscribe::handle_cmd_in_stream()`.
+// The client is authenticated as default user again, or not at all if the
+// default user requires password. Database 0 is selected again.
+#[derive(Debug, Default)]
+pub struct Reset;
+
+impl Reset {
+    pub(crate) fn make_response() -> Frame {
+        Frame::Simple("RESET".to_string())
+    }
+
+    // the part shared with `RESET` received in stream, subscriptions and
+    // monitors are left to the caller
+    pub(crate) fn reset_session(db:&FakeDatabase, dst:&mut Connection) -> AsyncResult<()> {
+        dst.session_mut().user = db.acl().initial_user();
+        dst.session_mut().db = 0;
+        let id = dst.session().id;
+        db.clients().update(id, |c| c.db = 0);
+        db.tracking().disable(id)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PubCommand for Reset {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        Self::reset_session(db, dst)?;
+        dst.write_frame(&Self::make_response()).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Reset {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("reset".as_bytes()));
+        frm
+    }
+}
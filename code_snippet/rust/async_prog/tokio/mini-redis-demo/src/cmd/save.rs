@@ -0,0 +1,100 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+// dump the store to snapshot file, reply after the file is written
+#[derive(Debug, Default)]
+pub struct Save;
+
+// dump the store in background, reply immediately
+#[derive(Debug, Default)]
+pub struct BgSave;
+
+// compact the append-only file in background, reply immediately
+#[derive(Debug, Default)]
+pub struct BgRewriteAof;
+
+#[async_trait]
+impl PubCommand for Save {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match db.snapshotter().save(db).await {
+            Ok(_num_saved) => Frame::Simple("OK".to_string()),
+            Err(e) => Frame::Error(format!("ERR {}", e)),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for Save {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("save".as_bytes()));
+        frm
+    }
+}
+
+#[async_trait]
+impl PubCommand for BgSave {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match db.snapshotter().bgsave(db) {
+            Ok(_) => Frame::Simple("Background saving started".to_string()),
+            Err(e) => Frame::Error(format!("ERR {}", e)),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for BgSave {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("bgsave".as_bytes()));
+        frm
+    }
+}
+
+#[async_trait]
+impl PubCommand for BgRewriteAof {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let response = match db.aof().bgrewrite(db) {
+            Ok(_) => Frame::Simple(
+                "Background append only file rewriting started".to_string()),
+            Err(e) => Frame::Error(format!("ERR {}", e)),
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+impl PrivCommand for BgRewriteAof {
+    fn parse_frames(_parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Ok(Box::new(Self))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("bgrewriteaof".as_bytes()));
+        frm
+    }
+}
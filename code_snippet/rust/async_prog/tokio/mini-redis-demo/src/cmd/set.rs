@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use bytes::Bytes;
 use async_trait::async_trait;
 
@@ -28,11 +28,31 @@ impl PubCommand for Set {
     {
         // may require error handling once it goes huge
         // , the `value()` returns `Bytes`, require 3rd-party crate `bytes`
-        let _ = fdb.set(self.key(), self.value().to_vec());
+        let _ = fdb.set(self.key(), self.value().to_vec(), self.expire);
         let response = Frame::Simple("OK".to_string());
         dst.write_frame(&response).await ? ;
         Ok(())
     }
+
+    fn is_write(&self) -> bool { true }
+
+    fn may_grow(&self) -> bool { true }
+
+    fn keys(&self) -> Vec<&str> { vec![self.key.as_str()] }
+
+    fn aof_frame(&self) -> Option<Frame> {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("set".as_bytes()));
+        frm.push_bulk(Bytes::from(self.key.clone().into_bytes()));
+        frm.push_bulk(self.value.clone());
+        if let Some(d) = self.expire {
+            let at = SystemTime::now() + d;
+            let ms = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+            frm.push_bulk(Bytes::from("pxat".as_bytes()));
+            frm.push_int(ms as u64);
+        }
+        Some(frm)
+    }
 }
 
 impl PrivCommand for Set {
@@ -58,6 +78,14 @@ impl PrivCommand for Set {
                 let ms = parse.next_int()?;
                 _expire = Some(Duration::from_millis(ms));
             }
+            Ok(s) if s.to_uppercase() == "PXAT" => {
+                // An expiration is specified as unix time in milliseconds,
+                // the key expires immediately if the time already passed.
+                let ms = parse.next_int()?;
+                let at = UNIX_EPOCH + Duration::from_millis(ms);
+                let remain = at.duration_since(SystemTime::now()).unwrap_or_default();
+                _expire = Some(remain);
+            }
             // other set options are treated as format error
             Ok(_) => return Err("currently `SET` only supports the expiration option".into()),
             // The `EndOfStream` error indicates there is no further data to
@@ -72,6 +100,11 @@ impl PrivCommand for Set {
         Ok(Box::new(obj))
     }
 
+    fn replay(&self, fdb:&mut FakeDatabase) -> AsyncResult<()> {
+        fdb.set(self.key(), self.value().to_vec(), self.expire)?;
+        Ok(())
+    }
+
     fn into_frame(self) -> Frame {
         let mut frm = Frame::array();
         frm.push_bulk(Bytes::from("set".as_bytes()));
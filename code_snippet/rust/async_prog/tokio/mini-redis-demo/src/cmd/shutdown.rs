@@ -0,0 +1,60 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown, SaveMode};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+
+// stop the server, the connection is closed without reply on success
+#[derive(Debug)]
+pub struct Shutdown {
+    mode: SaveMode,
+}
+
+impl Shutdown {
+    pub fn new(mode:SaveMode) -> Self {
+        Self{mode}
+    }
+}
+
+#[async_trait]
+impl PubCommand for Shutdown {
+    async fn apply(&self, _db:&FakeDatabase, dst:&mut Connection,
+                   shutdown:&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        if !shutdown.stop_server(self.mode) {
+            let response = Frame::Error("ERR Errors trying to SHUTDOWN".to_string());
+            dst.write_frame(&response).await ?;
+        }
+        Ok(())
+    }
+}
+
+impl PrivCommand for Shutdown {
+    // # Format
+    // ```text
+    // SHUTDOWN [NOSAVE | SAVE]
+    // ```
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let mode = match parse.next_string() {
+            Ok(s) if s.eq_ignore_ascii_case("save") => SaveMode::Save,
+            Ok(s) if s.eq_ignore_ascii_case("nosave") => SaveMode::NoSave,
+            Ok(s) => return Err(format!("invalid option '{}'", s).into()),
+            Err(ParseError::EndOfStream) => SaveMode::Default,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Box::new(Self{mode}))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("shutdown".as_bytes()));
+        match self.mode {
+            SaveMode::Save => frm.push_bulk(Bytes::from("save".as_bytes())),
+            SaveMode::NoSave => frm.push_bulk(Bytes::from("nosave".as_bytes())),
+            SaveMode::Default => {},
+        }
+        frm
+    }
+}
@@ -0,0 +1,98 @@
+use bytes::Bytes;
+use async_trait::async_trait;
+
+use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
+use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
+use crate::db::FakeDatabase;
+use crate::slowlog::SlowLogEntry;
+
+// number of entries returned by `SLOWLOG GET` without count
+const DEFAULT_GET_COUNT:usize = 10;
+
+#[derive(Debug)]
+pub enum Slowlog {
+    // `None` returns all entries
+    Get(Option<usize>),
+    Len,
+    Reset,
+}
+
+#[async_trait]
+impl PubCommand for Slowlog {
+    async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        let slowlog = db.slowlog();
+        let response = match self {
+            Self::Get(count) => {
+                let entries = slowlog.get(*count)?;
+                Frame::Array(entries.into_iter().map(entry_frame).collect())
+            },
+            Self::Len => Frame::Integer(slowlog.len()? as u64),
+            Self::Reset => {
+                slowlog.reset()?;
+                Frame::Simple("OK".to_string())
+            },
+        };
+        dst.write_frame(&response).await ?;
+        Ok(())
+    }
+}
+
+// id, timestamp, duration, arguments, client address, client name
+fn entry_frame(entry:SlowLogEntry) -> Frame
+{
+    let argv = entry.argv.into_iter().map(Frame::Bulk).collect();
+    Frame::Array(vec![
+        Frame::Integer(entry.id),
+        Frame::Integer(entry.timestamp),
+        Frame::Integer(entry.duration_usec),
+        Frame::Array(argv),
+        Frame::Bulk(Bytes::from(entry.client_addr.into_bytes())),
+        Frame::Bulk(Bytes::from(entry.client_name.into_bytes())),
+    ])
+}
+
+impl PrivCommand for Slowlog {
+    // # Format
+    // ```text
+    // SLOWLOG GET [count]
+    // SLOWLOG LEN | RESET
+    // ```
+    // negative count returns all entries
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let sub = parse.next_string()?.to_lowercase();
+        let obj = match sub.as_str() {
+            "get" => match parse.next_string() {
+                Ok(s) => {
+                    let count = s.parse::<i64>().map_err(|_| {
+                        "value is out of range, must be positive".to_string()
+                    })?;
+                    Self::Get(usize::try_from(count).ok())
+                },
+                Err(ParseError::EndOfStream) => Self::Get(Some(DEFAULT_GET_COUNT)),
+                Err(e) => return Err(e.into()),
+            },
+            "len" => Self::Len,
+            "reset" => Self::Reset,
+            _others => return Err(format!("unknown subcommand '{}'", sub).into()),
+        };
+        Ok(Box::new(obj))
+    }
+    fn into_frame(self) -> Frame
+    {
+        let mut frm = Frame::array();
+        frm.push_bulk(Bytes::from("slowlog".as_bytes()));
+        match self {
+            Self::Get(count) => {
+                frm.push_bulk(Bytes::from("get".as_bytes()));
+                let count = count.map(|c| c as i64).unwrap_or(-1);
+                frm.push_bulk(Bytes::from(count.to_string().into_bytes()));
+            },
+            Self::Len => frm.push_bulk(Bytes::from("len".as_bytes())),
+            Self::Reset => frm.push_bulk(Bytes::from("reset".as_bytes())),
+        }
+        frm
+    }
+}
@@ -1,52 +1,79 @@
 use std::pin::Pin;
-use std::thread::sleep;
-use std::time::Duration;
-use std::cell::RefCell;
 
 use bytes::Bytes;
 use async_trait::async_trait;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::{Stream as TokioAbstractStream, StreamExt, StreamMap};
+use tracing::debug;
 
 use crate::{Connection, AsyncResult, Parse, ParseError, Frame, SingleRequestShutdown};
 use crate::cmd::{Command as PubCommand, private_part::Command as PrivCommand};
-use crate::db::FakeDatabase;
+use crate::cmd::{Ping, Reset};
+use crate::db::{FakeDatabase, LagPolicy};
+use crate::pubsub::{ChannelKind, PatternMessage};
+use crate::tracking::{Invalidation, INVALIDATE_CHANNEL, make_invalidation_message};
+
+// items produced by the stream of each subscribed channel
+enum ChannelEvent {
+    Message(Bytes),
+    // message of a channel matching the subscribed pattern, with name of
+    // the channel
+    PatternMessage(String, Bytes),
+    // number of messages the subscriber missed because it was too slow
+    // to consume the broadcast channel
+    Lagged(u64),
+    // of the clients redirecting their tracking messages to this one
+    Invalidate(Invalidation),
+}
 
 // the trait `Stream` in `tokio-stream` doesn't implement `Send` trait
 // , but it is required for transferring ownership of new messages, so
 // add it in application code.
-type MessagesPipe = Pin<Box<dyn TokioAbstractStream<Item = Bytes> + Send>>;
+type MessagesPipe = Pin<Box<dyn TokioAbstractStream<Item = ChannelEvent> + Send>>;
 
 #[derive(Debug)]
 pub struct Subscribe {
-    // this project requires interior mutability on the field,
-    // also it is accessible only by the thread running the `Subscribe` instance.
-    channels:RefCell<Vec<String>>,
+    // channels, or patterns of `PSUBSCRIBE`
+    channels: Vec<String>,
+    // `SUBSCRIBE`, `SSUBSCRIBE` or `PSUBSCRIBE`
+    kind: ChannelKind,
 }
-// This project guarantees that a `Subscribe` instance won't be referenced
-// and mutated simultaneously among several threads. It should be harmless
-// to implement the traits `Sync`
-unsafe impl Sync for Subscribe {}
 
 #[derive(Debug)]
 pub struct Unsubscribe {
     channels: Vec<String>,
+    // `UNSUBSCRIBE`, `SUNSUBSCRIBE` or `PUNSUBSCRIBE`
+    kind: ChannelKind,
 }
 
-pub trait CommonInit {
-    fn new(chns:Vec<String>) -> Self;
+pub trait CommonInit : Sized {
+    fn with_kind(chns:Vec<String>, kind:ChannelKind) -> Self;
+    fn new(chns:Vec<String>) -> Self {
+        Self::with_kind(chns, ChannelKind::Global)
+    }
 }
 impl CommonInit for Subscribe {
-    fn new(chns:Vec<String>) -> Self {
-        Self{channels: RefCell::new(chns)}
+    fn with_kind(chns:Vec<String>, kind:ChannelKind) -> Self {
+        Self{channels: chns, kind}
     }
 }
 impl CommonInit for Unsubscribe {
-    fn new(chns:Vec<String>) -> Self {
-        Self{channels: chns}
+    fn with_kind(chns:Vec<String>, kind:ChannelKind) -> Self {
+        Self{channels: chns, kind}
     }
 }
 
+// State of a connection in subscriber mode. It is created and owned by the
+// task running `Subscribe::apply()`, so the command itself stays immutable
+// and no interior mutability is needed when the client subscribes or
+// unsubscribes more channels in the middle of streaming.
+struct SubscriberState {
+    // gather streams for all channels, a client may subscribe both kinds
+    // of channels on the same connection
+    streams: StreamMap<(ChannelKind, String), MessagesPipe>,
+    client_id: u64,
+}
+
 // - each individual channel is handled using a `tokio::sync::broadcast::Receiver`
 //   channel, messages are then fanned out to all clients that already subscribed
 //   the channel in advance.
@@ -54,45 +81,65 @@ impl CommonInit for Unsubscribe {
 //   any given time.
 #[async_trait]
 impl PubCommand for Subscribe {
+    fn is_streaming(&self) -> bool { true }
+
+    fn keys(&self) -> Vec<&str> {
+        match self.kind {
+            ChannelKind::Sharded => self.channels.iter().map(|c| c.as_str()).collect(),
+            ChannelKind::Global | ChannelKind::Pattern => Vec::new(),
+        }
+    }
+
     async fn apply(&self, db:&FakeDatabase, dst:&mut Connection,
                    shutdown:&mut SingleRequestShutdown) -> AsyncResult<()>
-    { // gather streams for all channels, has to be declared as mutable instance
-        let mut subscriptions:StreamMap<String, MessagesPipe> = StreamMap::new();
-        while !shutdown.is_shutdown()  {
-            let _chns:Vec<String> = {
-                // the inner scope ensures the mutable reference from `RefMut<T>` type
-                // will be dropped earlier before calling the async functions
-                let x = & mut * self.channels.borrow_mut();
-                x.drain(..).collect()
-            };
-            for chn in _chns {
-                let num_subs = if subscriptions.contains_key(chn.as_str()) {
-                        subscriptions.len()
-                    } else {
-                        refresh_subscriptions(&mut subscriptions, db, chn.clone())
-                    };
-                let frm = self.make_subscribe_response(chn, num_subs);
-                dst.write_frame(&frm).await ?;
-            } // move the new channel labels to given stream map
+    {
+        let mut state = SubscriberState::new(dst.session().id);
+        for frm in state.subscribe(db, self.kind, &self.channels) {
+            dst.write_frame(&frm).await ?;
+        }
+        state.report(db, dst.session().id);
+        // leave subscriber mode as soon as the client has no subscription,
+        // e.g. all channels failed to subscribe, or the client unsubscribed
+        // all of them.
+        while !shutdown.is_shutdown() && !state.streams.is_empty() {
             tokio::select! {
                 // Note the method `next()` comes from the trait `StreamExt`
-                Some((k, v)) = subscriptions.next() => {
-                    let frm = self.make_message_response(k,v);
-                    dst.write_frame(&frm).await?;
-                }
+                Some(((kind, k), evt)) = state.streams.next() => match evt {
+                    ChannelEvent::Message(v) => {
+                        let frm = make_message_response(kind, k, v);
+                        dst.write_frame(&frm).await?;
+                    },
+                    ChannelEvent::PatternMessage(chn, v) => {
+                        let frm = make_pmessage_response(k, chn, v);
+                        dst.write_frame(&frm).await?;
+                    },
+                    ChannelEvent::Lagged(num_dropped) => {
+                        let frm = make_lagged_response(k, num_dropped);
+                        dst.write_frame(&frm).await?;
+                        if db.lag_policy() == LagPolicy::Disconnect {
+                            shutdown.terminate();
+                        }
+                    },
+                    ChannelEvent::Invalidate(msg) => {
+                        dst.write_frame(&make_invalidation_message(msg)).await?;
+                    },
+                },
                 result = dst.read_frame() => {
                     let result = match result {Ok(r) => r, _others => break}; // network error
                     let frm = match result {Some(f) => f, None => break}; // end of stream
-                    let result = self.handle_cmd_in_stream(frm, &mut subscriptions)?;
-                    if let Some(frm) = result {
+                    let replies = state.handle_cmd_in_stream(frm, db, dst, shutdown)?;
+                    for frm in replies {
                         dst.write_frame(&frm).await?;
                     }
+                    state.report(db, dst.session().id);
                 } // more frames from client
                 _ = shutdown.recv() => {
-                    println!("receive shutdown when streaming to subcribers");
+                    debug!("receive shutdown when streaming to subcribers");
                 } // will break the loop
             }; // end of macro tokio::select
         } // end of loop
+        state.clear(db);
+        state.report(db, dst.session().id);
         Ok(())
     } // end of apply
 } // end of impl PubCommand
@@ -100,18 +147,14 @@ impl PubCommand for Subscribe {
 impl PrivCommand for Subscribe {
     fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
     {
-        match  inner_parse_frames::<Self>(parse) {
-            Ok(v) => Ok(Box::new(v)),
-            Err(e) => Err(e),
-        }
+        Self::parse_frames_of(parse, ChannelKind::Global)
     }
     fn into_frame(self) -> Frame
     {
         let mut frm = Frame::array();
-        frm.push_bulk(Bytes::from("subscribe".as_bytes()));
-        let chns = self.channels.borrow();
-        for c in &*chns { // only require the reference to each string
-            let cb = Bytes::from(c.clone().into_bytes());
+        frm.push_bulk(Bytes::from(format!("{}subscribe", self.kind.prefix())));
+        for c in self.channels {
+            let cb = Bytes::from(c.into_bytes());
             frm.push_bulk(cb);
         }
         frm
@@ -119,10 +162,14 @@ impl PrivCommand for Subscribe {
 } // end of impl PrivCommand
 
 impl PrivCommand for Unsubscribe {
+    fn parse_frames(parse: &mut Parse) -> AsyncResult<Box<dyn PubCommand>>
+    {
+        Self::parse_frames_of(parse, ChannelKind::Global)
+    }
     fn into_frame(self) -> Frame
     {
         let mut frm = Frame::array();
-        frm.push_bulk(Bytes::from("unsubscribe".as_bytes()));
+        frm.push_bulk(Bytes::from(format!("{}unsubscribe", self.kind.prefix())));
         for c in self.channels {
             let cb = Bytes::from(c.into_bytes());
             frm.push_bulk(cb);
@@ -133,75 +180,258 @@ impl PrivCommand for Unsubscribe {
 
 
 impl Subscribe {
-    fn make_subscribe_response(&self, channel:String, num_subs:usize) -> Frame
+    pub(crate) fn parse_frames_of(parse: &mut Parse, kind:ChannelKind)
+        -> AsyncResult<Box<dyn PubCommand>>
     {
-        let mut frm = Frame::array();
-        frm.push_bulk(Bytes::from_static(b"subscribe"));
-        frm.push_bulk(Bytes::from(channel));
-        frm.push_int(num_subs as u64);
-        frm
+        let v = inner_parse_frames::<Self>(parse, kind)?;
+        Ok(Box::new(v))
+    }
+}
+
+fn make_subscribe_response(kind:ChannelKind, channel:String, num_subs:usize) -> Frame
+{
+    let mut frm = Frame::array();
+    frm.push_bulk(Bytes::from(format!("{}subscribe", kind.prefix())));
+    frm.push_bulk(Bytes::from(channel));
+    frm.push_int(num_subs as u64);
+    frm
+}
+fn make_message_response(kind:ChannelKind, chn:String, msg:Bytes) -> Frame
+{
+    let mut frm = Frame::array();
+    frm.push_bulk(Bytes::from(format!("{}message", kind.prefix())));
+    frm.push_bulk(Bytes::from(chn));
+    frm.push_bulk(msg);
+    frm
+}
+// message of a channel matching the pattern, in the form of :
+// ```
+// [ "pmessage", pattern, channel, message ]
+// ```
+fn make_pmessage_response(pattern:String, chn:String, msg:Bytes) -> Frame
+{
+    let mut frm = Frame::array();
+    frm.push_bulk(Bytes::from_static(b"pmessage"));
+    frm.push_bulk(Bytes::from(pattern));
+    frm.push_bulk(Bytes::from(chn));
+    frm.push_bulk(msg);
+    frm
+}
+// out-of-band frame telling the subscriber how many messages of the
+// channel were overwritten before it could receive them
+fn make_lagged_response(chn:String, num_dropped:u64) -> Frame
+{
+    let mut frm = Frame::array();
+    frm.push_bulk(Bytes::from_static(b"lagged"));
+    frm.push_bulk(Bytes::from(chn));
+    frm.push_int(num_dropped);
+    frm
+}
+
+impl SubscriberState {
+    fn new(client_id:u64) -> Self {
+        Self{streams: StreamMap::new(), client_id}
     }
-    fn make_message_response(&self, chn:String, msg:Bytes) -> Frame
+
+    // register a stream for each new channel, the reply for each channel
+    // is either a subscribe confirmation or an error frame, the client
+    // is informed of failure instead of waiting on a channel which will
+    // never be available.
+    fn subscribe(&mut self, db:&FakeDatabase, kind:ChannelKind, channels:&[String])
+        -> Vec<Frame>
     {
-        let mut frm = Frame::array();
-        frm.push_bulk(Bytes::from_static(b"message"));
-        frm.push_bulk(Bytes::from(chn));
-        frm.push_bulk(msg);
-        frm
+        channels.iter().map(|chn| {
+            let key = (kind, chn.clone());
+            if self.streams.contains_key(&key) {
+                return make_subscribe_response(kind, chn.clone(), self.count(kind));
+            }
+            // `CLIENT TRACKING ... REDIRECT` messages don't go through
+            // the broker, the channel is only a way to receive them
+            let subscribed = if kind == ChannelKind::Global && chn == INVALIDATE_CHANNEL {
+                db.tracking().subscribe_redirect(self.client_id).map(into_invalidations_pipe)
+            } else if kind == ChannelKind::Pattern {
+                db.psubscribe(chn.clone()).map(into_pmessages_pipe)
+            } else {
+                db.subscribe(kind, chn.clone()).map(into_messages_pipe)
+            };
+            match subscribed {
+                Ok(pipe) => {
+                    self.streams.insert(key, pipe);
+                    make_subscribe_response(kind, chn.clone(), self.count(kind))
+                },
+                Err(e) => Frame::Error(format!(
+                    "failed to subscribe channel {}, {}", chn, e)),
+            }
+        }).collect()
+    }
+
+    fn count_of(&self, kind:ChannelKind) -> usize {
+        self.streams.keys().filter(|(k, _)| *k == kind).count()
+    }
+
+    // number of subscriptions in replies of (un)subscribe commands, as in
+    // Redis channels and patterns are counted together
+    fn count(&self, kind:ChannelKind) -> usize {
+        match kind {
+            ChannelKind::Sharded => self.count_of(kind),
+            ChannelKind::Global | ChannelKind::Pattern =>
+                self.count_of(ChannelKind::Global) + self.count_of(ChannelKind::Pattern),
+        }
+    }
+
+    // subscription counts shown in `CLIENT LIST`
+    fn report(&self, db:&FakeDatabase, client_id:u64) {
+        let (sub, ssub, psub) = (self.count_of(ChannelKind::Global),
+            self.count_of(ChannelKind::Sharded), self.count_of(ChannelKind::Pattern));
+        db.clients().update(client_id, |c| { c.sub = sub; c.ssub = ssub; c.psub = psub; });
+    }
+
+    // the receiver is dropped before the broker checks whether anyone
+    // else still subscribes the channel
+    fn remove(&mut self, db:&FakeDatabase, kind:ChannelKind, chn:&str) {
+        if self.streams.remove(&(kind, chn.to_string())).is_some() {
+            db.unsubscribe(kind, chn);
+        }
+    }
+
+    fn clear(&mut self, db:&FakeDatabase) {
+        let keys:Vec<(ChannelKind, String)> = self.streams.keys().cloned().collect();
+        for (kind, chn) in keys {
+            self.remove(db, kind, &chn);
+        }
     }
-    fn handle_cmd_in_stream(&self, frm:Frame, subscriptions:&mut StreamMap<String, MessagesPipe>
-                           ) -> AsyncResult<Option<Frame>>
+
+    fn unsubscribe(&mut self, db:&FakeDatabase, mut cmd:Unsubscribe) -> Vec<Frame>
+    {
+        let kind = cmd.kind;
+        debug!(?cmd, "streaming server got");
+        if cmd.channels.is_empty() {
+            let src:Vec<String> = self.streams.keys()
+                .filter(|(k, _)| *k == kind)
+                .map(|(_, chn)| chn.clone()).collect();
+            cmd.channels.extend(src);
+        }
+        // e.g. other kinds of subscriptions are still active
+        let num_subs = self.count(kind);
+        cmd.unsubscribe_each(num_subs, |chn| {
+            self.remove(db, kind, chn);
+            self.count(kind)
+        })
+    }
+
+    fn handle_cmd_in_stream(&mut self, frm:Frame, db:&FakeDatabase, dst:&mut Connection,
+                            shutdown:&mut SingleRequestShutdown) -> AsyncResult<Vec<Frame>>
     { // commands received in the middleware of streaming process
         let mut parsed = Parse::new(frm)?;
         let command_name = parsed.next_string()?.to_lowercase();
         let out = match &command_name[..] {
-            "subscribe" => {
-                let cmd2 = inner_parse_frames::<Self>(&mut parsed)?;
-                println!("streaming server GOT: {:?}", cmd2);
-                let dst:&mut Vec<String> = & mut * self.channels.borrow_mut();
-                let src:&mut Vec<String> = & mut * cmd2.channels.borrow_mut();
-                let src:Vec<String> = src.drain(..).collect();
-                dst.extend(src);
-                None
-            },
-            "unsubscribe" => {
-                let mut cmd2 = inner_parse_frames::<Unsubscribe>(&mut parsed)?;
-                println!("streaming server GOT: {:?}", cmd2);
-                if cmd2.channels.is_empty() {
-                    let src:Vec<String> = subscriptions.keys()
-                        .map(|k| k.to_string()).collect();
-                    cmd2.channels.extend(src);
+            "subscribe" | "ssubscribe" | "psubscribe" => {
+                let kind = channel_kind_of(&command_name);
+                let cmd2 = inner_parse_frames::<Subscribe>(&mut parsed, kind)?;
+                debug!(cmd = ?cmd2, "streaming server got");
+                // same checks as the command received out of the stream,
+                // e.g. sharded channel owned by another node
+                match crate::cmd::precheck(&command_name, &cmd2, db, dst) {
+                    Some(e) => vec![e],
+                    None => self.subscribe(db, kind, &cmd2.channels),
                 }
-                for chn in &cmd2.channels {
-                    subscriptions.remove(chn);
-                }
-                let frm = cmd2.make_response(subscriptions.len()) ;
-                Some(frm)
+            },
+            "unsubscribe" | "sunsubscribe" | "punsubscribe" => {
+                let kind = channel_kind_of(&command_name);
+                let cmd2 = inner_parse_frames::<Unsubscribe>(&mut parsed, kind)?;
+                self.unsubscribe(db, cmd2)
+            },
+            "ping" => {
+                let cmd2 = Ping::parse_args(&mut parsed)?;
+                vec![cmd2.make_stream_response()]
+            },
+            "quit" => {
+                shutdown.terminate();
+                vec![Frame::Simple("OK".to_string())]
+            },
+            "reset" => { // caller leaves subscriber mode with no subscription
+                self.clear(db);
+                Reset::reset_session(db, dst)?;
+                vec![Reset::make_response()]
             },
             _others => {
                 let detail = format!("not supported in stream, type:{}",
                                      &command_name[..] );
-                Some(Frame::Error(detail))
+                vec![Frame::Error(detail)]
             }
         };
         parsed.finish()?;
         Ok(out)
     } // end of handle_cmd_in_stream
-} // end of Subscribe
+} // end of SubscriberState
 
 impl Unsubscribe {
-    fn make_response(&self, num_subs:usize) -> Frame
+    // The server responds with one array frame per channel, in the form of :
+    //
+    // ```
+    // [ "unsubscribe", channel, num-subscribed ]
+    // ```
+    //
+    // where `num-subscribed` is the number of channels the client still
+    // subscribes after the channel is removed, `channel` is null if the
+    // client has no subscription of the kind to remove.
+    fn make_response(&self, channel:Option<&str>, num_subs:usize) -> Frame
     {
-        let mut frm = Frame::array();
-        frm.push_bulk(Bytes::from_static(b"unsubscribe"));
-        // frm.push_bulk(Bytes::from(channel));
-        frm.push_int(num_subs as u64);
-        frm
+        let channel = match channel {
+            Some(c) => Frame::Bulk(Bytes::from(c.to_string())),
+            None => Frame::Null,
+        };
+        let cmd_name = format!("{}unsubscribe", self.kind.prefix());
+        Frame::Array(vec![ Frame::Bulk(Bytes::from(cmd_name)),
+            channel, Frame::Integer(num_subs as u64) ])
+    }
+    pub(crate) fn parse_frames_of(parse: &mut Parse, kind:ChannelKind)
+        -> AsyncResult<Box<dyn PubCommand>>
+    {
+        let v = inner_parse_frames::<Self>(parse, kind)?;
+        Ok(Box::new(v))
+    }
+    // `remove` drops a channel from the caller's subscriptions then returns
+    // number of remaining subscribed channels, `num_subs` is the number
+    // reported if there is no channel to remove
+    fn unsubscribe_each<F>(&self, num_subs:usize, mut remove:F) -> Vec<Frame>
+        where F: FnMut(&str) -> usize
+    {
+        if self.channels.is_empty() {
+            vec![self.make_response(None, num_subs)]
+        } else {
+            self.channels.iter().map(|chn| {
+                let num_subs = remove(chn);
+                self.make_response(Some(chn), num_subs)
+            }).collect()
+        }
     }
 }
 
+// received when the client is not in subscriber mode, there is nothing
+// to remove but each channel is still acknowledged
+#[async_trait]
+impl PubCommand for Unsubscribe {
+    async fn apply(&self, _db:&FakeDatabase, dst:&mut Connection,
+                   _ :&mut SingleRequestShutdown) -> AsyncResult<()>
+    {
+        for frm in self.unsubscribe_each(0, |_| 0) {
+            dst.write_frame(&frm).await?;
+        }
+        Ok(())
+    }
+}
 
-fn inner_parse_frames<T:CommonInit>(parse: &mut Parse) -> AsyncResult<T>
+
+fn channel_kind_of(cmd_name:&str) -> ChannelKind {
+    match cmd_name {
+        "ssubscribe" | "sunsubscribe" => ChannelKind::Sharded,
+        "psubscribe" | "punsubscribe" => ChannelKind::Pattern,
+        _others => ChannelKind::Global,
+    }
+}
+
+fn inner_parse_frames<T:CommonInit>(parse: &mut Parse, kind:ChannelKind) -> AsyncResult<T>
 {
     let mut _channels = vec![];
     loop {
@@ -211,32 +441,47 @@ fn inner_parse_frames<T:CommonInit>(parse: &mut Parse) -> AsyncResult<T>
             Err(e) => return Err(e.into()),
         };
     };
-    Ok(T::new(_channels))
+    Ok(T::with_kind(_channels, kind))
 }
 
-// internal work for getting broadcast receiver, responding to client with
-// subscription result, generate and register a stream.
-fn refresh_subscriptions (subscriptions:&mut StreamMap<String, MessagesPipe>,
-                                db:&FakeDatabase, channel:String) -> usize
+// convert the broadcast receiver of a channel to a stream which can be
+// registered to `StreamMap`
+fn into_messages_pipe(mut rx:broadcast::Receiver<Bytes>) -> MessagesPipe
 {
-    let mut rx = loop {
-        if let Ok(v) = db.subscribe(channel.clone()) {
-            break v 
-        } else {
-            sleep(Duration::new(1u64, 0));
-        } // retry until success
+    let streaming_rx = async_stream::stream!{
+        loop {
+            match rx.recv().await {
+                Ok(msg) => yield ChannelEvent::Message(msg),
+                Err(broadcast::error::RecvError::Lagged(n)) =>
+                    yield ChannelEvent::Lagged(n),
+                Err(_) => break,
+            }
+        }
     };
+    Box::pin(streaming_rx)
+} // end of into_messages_pipe
+
+fn into_pmessages_pipe(mut rx:broadcast::Receiver<PatternMessage>) -> MessagesPipe
+{
     let streaming_rx = async_stream::stream!{
         loop {
             match rx.recv().await {
-                Ok(msg) => yield msg,
-                Err(broadcast::error::RecvError::Lagged(_)) => {},
+                Ok((chn, msg)) => yield ChannelEvent::PatternMessage(chn, msg),
+                Err(broadcast::error::RecvError::Lagged(n)) =>
+                    yield ChannelEvent::Lagged(n),
                 Err(_) => break,
             }
         }
     };
-    let streaming_src = Box::pin(streaming_rx);
-    subscriptions.insert(channel.clone(), streaming_src);
-    subscriptions.len()
-} // end of  refresh_subscriptions
+    Box::pin(streaming_rx)
+}
 
+fn into_invalidations_pipe(mut rx:mpsc::UnboundedReceiver<Invalidation>) -> MessagesPipe
+{
+    let streaming_rx = async_stream::stream!{
+        while let Some(msg) = rx.recv().await {
+            yield ChannelEvent::Invalidate(msg);
+        }
+    };
+    Box::pin(streaming_rx)
+}
@@ -36,4 +36,6 @@ impl PubCommand for Unknown {
         dst.write_frame(&response).await ? ;
         Ok(())
     }
+
+    fn is_known(&self) -> bool { false }
 }
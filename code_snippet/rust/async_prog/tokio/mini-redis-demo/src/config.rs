@@ -0,0 +1,422 @@
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{DEFAULT_PORT, MAX_CONNECTIONS, DEFAULT_CHANNEL_CAPACITY};
+use crate::persist::{FsyncPolicy, DEFAULT_SNAPSHOT_PATH, DEFAULT_AOF_PATH};
+use crate::replication::DEFAULT_BACKLOG_SIZE;
+use crate::tls::ClientAuth;
+use crate::logging::LogFormat;
+use crate::slowlog::{DEFAULT_SLOWLOG_THRESHOLD_USEC, DEFAULT_SLOWLOG_MAX_LEN};
+use crate::db::{DEFAULT_DATABASES, LagPolicy};
+use crate::notify::NotifyFlags;
+use crate::evict::{EvictionPolicy, DEFAULT_MAXMEMORY_SAMPLES, parse_memory};
+
+pub const LOG_LEVELS:[&str; 5] = ["debug", "verbose", "notice", "warning", "nothing"];
+
+// Settings of the server, read from `redis.conf`-style file and command
+// line arguments at startup, part of them can be changed later by
+// `CONFIG SET`. Names of the parameters are the same as Redis.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind: String,
+    // plain TCP port, 0 disables it
+    pub port: u16,
+    // port of TLS connections, 0 disables it
+    pub tls_port: u16,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    // certificates of CA which signed client certificates
+    pub tls_ca_cert_file: Option<String>,
+    pub tls_auth_clients: ClientAuth,
+    // path of Unix domain socket, and its permission bits
+    pub unixsocket: Option<String>,
+    pub unixsocketperm: u32,
+    // port of HTTP endpoint for Prometheus scraping, 0 disables it
+    pub metrics_port: u16,
+    pub maxclients: usize,
+    // number of logical databases, `SELECT` takes index below it
+    pub databases: usize,
+    // working directory, persistence files are relative to it
+    pub dir: String,
+    pub dbfilename: String,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    pub repl_backlog_size: usize,
+    pub replicaof: Option<(String, u16)>,
+    pub cluster_config_file: Option<String>,
+    // address other nodes know this one by, `bind` is used if not set
+    pub cluster_announce_ip: Option<String>,
+    pub loglevel: String,
+    pub logformat: LogFormat,
+    // clients have to authenticate if it is set
+    pub requirepass: Option<String>,
+    // users and their rules, see `Acl::load_file()`
+    pub aclfile: Option<String>,
+    // credentials a replica sends to its primary
+    pub masteruser: Option<String>,
+    pub masterauth: Option<String>,
+    // seconds to wait for connections to finish on shutdown
+    pub shutdown_timeout: u64,
+    // microseconds, commands running longer are logged, negative disables it
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    // milliseconds, spikes over it are recorded, 0 disables it
+    pub latency_monitor_threshold: u64,
+    // bytes taken by the keyspace before keys are evicted, 0 means no limit
+    pub maxmemory: usize,
+    pub maxmemory_policy: EvictionPolicy,
+    pub maxmemory_samples: usize,
+    // keyspace events published to subscribers, none by default
+    pub notify_keyspace_events: NotifyFlags,
+    // messages buffered for the slowest subscriber of a channel, and what
+    // happens to a subscriber once older messages are dropped
+    pub pubsub_channel_capacity: usize,
+    pub pubsub_lag_policy: LagPolicy,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self{ bind: "127.0.0.1".to_string(), port: DEFAULT_PORT,
+              tls_port: 0, tls_cert_file: None, tls_key_file: None,
+              tls_ca_cert_file: None, tls_auth_clients: ClientAuth::Required,
+              unixsocket: None, unixsocketperm: 0o700, metrics_port: 0,
+              maxclients: MAX_CONNECTIONS as usize, databases: DEFAULT_DATABASES,
+              dir: ".".to_string(),
+              dbfilename: DEFAULT_SNAPSHOT_PATH.to_string(), appendonly: false,
+              appendfilename: DEFAULT_AOF_PATH.to_string(),
+              appendfsync: FsyncPolicy::EverySec,
+              repl_backlog_size: DEFAULT_BACKLOG_SIZE, replicaof: None,
+              cluster_config_file: None, cluster_announce_ip: None, loglevel: "notice".to_string(),
+              logformat: LogFormat::Text,
+              requirepass: None, aclfile: None, masteruser: None,
+              masterauth: None, shutdown_timeout: 10,
+              slowlog_log_slower_than: DEFAULT_SLOWLOG_THRESHOLD_USEC,
+              slowlog_max_len: DEFAULT_SLOWLOG_MAX_LEN, latency_monitor_threshold: 0,
+              maxmemory: 0, maxmemory_policy: EvictionPolicy::NoEviction,
+              maxmemory_samples: DEFAULT_MAXMEMORY_SAMPLES,
+              notify_keyspace_events: NotifyFlags::default(),
+              pubsub_channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+              pubsub_lag_policy: LagPolicy::Notify }
+    }
+}
+
+// (name, whether it can be changed by `CONFIG SET`)
+const PARAMS:[(&str, bool); 37] = [
+    ("bind", false), ("port", false), ("tls-port", false), ("tls-cert-file", false),
+    ("tls-key-file", false), ("tls-ca-cert-file", false), ("tls-auth-clients", false),
+    ("unixsocket", false), ("unixsocketperm", false), ("metrics-port", false), ("maxclients", true),
+    ("databases", false), ("dir", false),
+    ("dbfilename", true), ("appendonly", true), ("appendfilename", false),
+    ("appendfsync", true), ("repl-backlog-size", false), ("replicaof", false),
+    ("cluster-config-file", false), ("cluster-announce-ip", false), ("loglevel", true), ("logformat", false), ("requirepass", true),
+    ("aclfile", false), ("masteruser", true), ("masterauth", true),
+    ("shutdown-timeout", true), ("slowlog-log-slower-than", true),
+    ("slowlog-max-len", true), ("latency-monitor-threshold", true),
+    ("maxmemory", true), ("maxmemory-policy", true), ("maxmemory-samples", true),
+    ("notify-keyspace-events", true), ("pubsub-channel-capacity", false),
+    ("pubsub-lag-policy", false),
+];
+
+fn invalid(detail:String) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, detail)
+}
+
+fn parse_value<T:FromStr>(name:&str, value:&str) -> IoResult<T> {
+    value.parse::<T>().map_err(|_| {
+        invalid(format!("argument '{}' is invalid for '{}'", value, name))
+    })
+}
+
+fn parse_yes_no(name:&str, value:&str) -> IoResult<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _others => Err(invalid(format!("argument must be 'yes' or 'no' for '{}'", name))),
+    }
+}
+
+impl ServerConfig {
+    // the file is read first, then arguments in the form `--name value`
+    // override it, e.g. `server redis.conf --port 7001`
+    pub fn from_args(args:impl Iterator<Item=String>) -> IoResult<Self> {
+        let args:Vec<String> = args.collect();
+        let mut cfg = Self::default();
+        let mut rest = &args[..];
+        if let Some(path) = rest.first().filter(|a| !a.starts_with("--")) {
+            cfg.load_file(path)?;
+            rest = &rest[1..];
+        }
+        // each option is followed by its values until next option
+        let mut idx = 0;
+        while idx < rest.len() {
+            let name = match rest[idx].strip_prefix("--") {
+                Some(n) => n,
+                None => return Err(invalid(format!("unexpected argument '{}'", rest[idx]))),
+            };
+            let num_values = rest[idx + 1 ..].iter().take_while(|a| !a.starts_with("--")).count();
+            let values = &rest[idx + 1 .. idx + 1 + num_values];
+            cfg.set_param(name, &values.join(" "))?;
+            idx += 1 + num_values;
+        }
+        Ok(cfg)
+    }
+
+    // one directive per line, `#` starts a comment, values containing
+    // spaces can be double-quoted.
+    pub fn load_file(&mut self, path:impl AsRef<Path>) -> IoResult<()> {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            self.set_param(name, value).map_err(|e| {
+                invalid(format!("{} line {}, {}", path.as_ref().display(), lineno + 1, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn set_param(&mut self, name:&str, value:&str) -> IoResult<()> {
+        match name.to_lowercase().as_str() {
+            "bind" => { self.bind = value.to_string(); },
+            "port" => { self.port = parse_value(name, value)?; },
+            "tls-port" => { self.tls_port = parse_value(name, value)?; },
+            "tls-cert-file" => { self.tls_cert_file = Some(value.to_string()).filter(|v| !v.is_empty()); },
+            "tls-key-file" => { self.tls_key_file = Some(value.to_string()).filter(|v| !v.is_empty()); },
+            "tls-ca-cert-file" => {
+                self.tls_ca_cert_file = Some(value.to_string()).filter(|v| !v.is_empty());
+            },
+            "tls-auth-clients" => { self.tls_auth_clients = value.parse()?; },
+            "metrics-port" => { self.metrics_port = parse_value(name, value)?; },
+            "unixsocket" => { self.unixsocket = Some(value.to_string()).filter(|v| !v.is_empty()); },
+            "unixsocketperm" => {
+                // octal as in `chmod`, e.g. 770
+                self.unixsocketperm = u32::from_str_radix(value, 8).ok().filter(|p| *p <= 0o777)
+                    .ok_or_else(|| invalid(format!("argument '{}' is invalid for '{}'", value, name)))?;
+            },
+            "maxclients" => {
+                self.maxclients = parse_value(name, value)?;
+                if self.maxclients == 0 {
+                    return Err(invalid("maxclients has to be at least 1".to_string()));
+                }
+            },
+            "databases" => {
+                self.databases = parse_value(name, value)?;
+                if self.databases == 0 {
+                    return Err(invalid("databases has to be at least 1".to_string()));
+                }
+            },
+            "dir" => { self.dir = value.to_string(); },
+            "dbfilename" => { self.dbfilename = value.to_string(); },
+            "appendonly" => { self.appendonly = parse_yes_no(name, value)?; },
+            "appendfilename" => { self.appendfilename = value.to_string(); },
+            "appendfsync" => { self.appendfsync = value.parse()?; },
+            "repl-backlog-size" => { self.repl_backlog_size = parse_value(name, value)?; },
+            "replicaof" => {
+                self.replicaof = match value.split_once(' ') {
+                    Some((h, p)) if h.eq_ignore_ascii_case("no") && p.eq_ignore_ascii_case("one")
+                        => None,
+                    Some((h, p)) => Some((h.to_string(), parse_value(name, p.trim())?)),
+                    None => return Err(invalid("replicaof expects host and port".to_string())),
+                };
+            },
+            "cluster-config-file" => {
+                self.cluster_config_file = Some(value.to_string()).filter(|v| !v.is_empty());
+            },
+            "cluster-announce-ip" => {
+                self.cluster_announce_ip = Some(value.to_string()).filter(|v| !v.is_empty());
+            },
+            "loglevel" => {
+                let level = value.to_lowercase();
+                if !LOG_LEVELS.contains(&level.as_str()) {
+                    return Err(invalid(format!("invalid log level '{}'", value)));
+                }
+                self.loglevel = level;
+            },
+            "logformat" => { self.logformat = value.parse()?; },
+            "requirepass" => {
+                self.requirepass = Some(value.to_string()).filter(|v| !v.is_empty());
+            },
+            "aclfile" => { self.aclfile = Some(value.to_string()).filter(|v| !v.is_empty()); },
+            "masteruser" => {
+                self.masteruser = Some(value.to_string()).filter(|v| !v.is_empty());
+            },
+            "masterauth" => {
+                self.masterauth = Some(value.to_string()).filter(|v| !v.is_empty());
+            },
+            "shutdown-timeout" => { self.shutdown_timeout = parse_value(name, value)?; },
+            "slowlog-log-slower-than" => { self.slowlog_log_slower_than = parse_value(name, value)?; },
+            "slowlog-max-len" => { self.slowlog_max_len = parse_value(name, value)?; },
+            "latency-monitor-threshold" => {
+                self.latency_monitor_threshold = parse_value(name, value)?;
+            },
+            "maxmemory" => {
+                self.maxmemory = parse_memory(value).ok_or_else(|| {
+                    invalid(format!("argument '{}' is invalid for '{}'", value, name))
+                })?;
+            },
+            "maxmemory-policy" => { self.maxmemory_policy = value.parse()?; },
+            "maxmemory-samples" => {
+                self.maxmemory_samples = parse_value(name, value)?;
+                if self.maxmemory_samples == 0 {
+                    return Err(invalid("maxmemory-samples has to be at least 1".to_string()));
+                }
+            },
+            "notify-keyspace-events" => { self.notify_keyspace_events = value.parse()?; },
+            "pubsub-channel-capacity" => {
+                self.pubsub_channel_capacity = parse_value(name, value)?;
+                if self.pubsub_channel_capacity == 0 {
+                    return Err(invalid("pubsub-channel-capacity has to be at least 1".to_string()));
+                }
+            },
+            "pubsub-lag-policy" => { self.pubsub_lag_policy = value.parse()?; },
+            _others => return Err(invalid(format!("unknown parameter '{}'", name))),
+        }
+        Ok(())
+    } // end of set_param
+
+    pub fn get_param(&self, name:&str) -> Option<String> {
+        let value = match name.to_lowercase().as_str() {
+            "bind" => self.bind.clone(),
+            "port" => self.port.to_string(),
+            "tls-port" => self.tls_port.to_string(),
+            "tls-cert-file" => self.tls_cert_file.clone().unwrap_or_default(),
+            "tls-key-file" => self.tls_key_file.clone().unwrap_or_default(),
+            "tls-ca-cert-file" => self.tls_ca_cert_file.clone().unwrap_or_default(),
+            "tls-auth-clients" => self.tls_auth_clients.to_string(),
+            "metrics-port" => self.metrics_port.to_string(),
+            "unixsocket" => self.unixsocket.clone().unwrap_or_default(),
+            "unixsocketperm" => format!("{:o}", self.unixsocketperm),
+            "maxclients" => self.maxclients.to_string(),
+            "databases" => self.databases.to_string(),
+            "dir" => self.dir.clone(),
+            "dbfilename" => self.dbfilename.clone(),
+            "appendonly" => (if self.appendonly {"yes"} else {"no"}).to_string(),
+            "appendfilename" => self.appendfilename.clone(),
+            "appendfsync" => self.appendfsync.to_string(),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "replicaof" => self.replicaof.as_ref()
+                .map(|(h, p)| format!("{} {}", h, p)).unwrap_or_default(),
+            "cluster-config-file" => self.cluster_config_file.clone().unwrap_or_default(),
+            "cluster-announce-ip" => self.cluster_announce_ip.clone().unwrap_or_default(),
+            "loglevel" => self.loglevel.clone(),
+            "logformat" => self.logformat.to_string(),
+            "requirepass" => self.requirepass.clone().unwrap_or_default(),
+            "aclfile" => self.aclfile.clone().unwrap_or_default(),
+            "masteruser" => self.masteruser.clone().unwrap_or_default(),
+            "masterauth" => self.masterauth.clone().unwrap_or_default(),
+            "shutdown-timeout" => self.shutdown_timeout.to_string(),
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than.to_string(),
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            "latency-monitor-threshold" => self.latency_monitor_threshold.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.to_string(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "notify-keyspace-events" => self.notify_keyspace_events.to_string(),
+            "pubsub-channel-capacity" => self.pubsub_channel_capacity.to_string(),
+            "pubsub-lag-policy" => self.pubsub_lag_policy.to_string(),
+            _others => return None,
+        };
+        Some(value)
+    }
+
+    // `host:port` of this node as written in the cluster config, other
+    // nodes cannot reach a wildcard `bind` so `cluster-announce-ip` is
+    // required then
+    pub fn cluster_address(&self) -> IoResult<String> {
+        let host = match self.cluster_announce_ip.as_deref() {
+            Some(ip) => ip,
+            None if self.bind.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_unspecified()) => {
+                return Err(invalid(format!(
+                    "cluster-announce-ip is required when bind is {}", self.bind)));
+            },
+            None => self.bind.as_str(),
+        };
+        Ok(format!("{}:{}", host, self.port))
+    }
+} // end of ServerConfig
+
+// shared by all connections through `FakeDatabase`
+#[derive(Default)]
+pub struct Config {
+    inner: Mutex<ServerConfig>,
+}
+
+impl Config {
+    pub fn new(cfg:ServerConfig) -> Self {
+        Self{ inner: Mutex::new(cfg) }
+    }
+
+    fn lock(&self) -> IoResult<MutexGuard<'_, ServerConfig>> {
+        self.inner.lock().map_err(|_| {
+            IoError::new(ErrorKind::ResourceBusy, "failed to acquire config lock")
+        })
+    }
+
+    // copy of current settings
+    pub fn current(&self) -> IoResult<ServerConfig> {
+        Ok(self.lock()?.clone())
+    }
+
+    // `CONFIG GET`, all parameters matching the glob-style pattern
+    pub fn get_matched(&self, pattern:&str) -> IoResult<Vec<(String, String)>> {
+        let cfg = self.lock()?;
+        let pattern = pattern.to_lowercase();
+        let out = PARAMS.iter()
+            .filter(|(name, _)| glob_match(pattern.as_bytes(), name.as_bytes()))
+            .filter_map(|(name, _)| cfg.get_param(name).map(|v| (name.to_string(), v)))
+            .collect();
+        Ok(out)
+    }
+
+    // `CONFIG SET`, parameters read only at startup are refused. Returns
+    // current settings with the given parameters changed, nothing is
+    // stored until `set_many`, the caller applies them to the running
+    // server first.
+    pub fn updated(&self, params:&[(String, String)]) -> IoResult<ServerConfig> {
+        let mut updated = self.current()?;
+        for (name, value) in params {
+            let lower = name.to_lowercase();
+            match PARAMS.iter().find(|(n, _)| *n == lower) {
+                Some((_, true)) => {},
+                Some((_, false)) => return Err(invalid(format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - can't set immutable config", name))),
+                None => return Err(invalid(format!(
+                    "Unknown option or number of arguments for CONFIG SET - '{}'", name))),
+            }
+            updated.set_param(name, value).map_err(|e| invalid(format!(
+                "CONFIG SET failed (possibly related to argument '{}') - {}", name, e)))?;
+        }
+        Ok(updated)
+    }
+
+    // nothing is changed if any of the given parameters is invalid
+    pub fn set_many(&self, params:&[(String, String)]) -> IoResult<()> {
+        self.updated(params)?;
+        let mut cfg = self.lock()?;
+        for (name, value) in params {
+            cfg.set_param(name, value)?;
+        }
+        Ok(())
+    }
+} // end of Config
+
+// glob-style pattern supporting `*` and `?`
+pub(crate) fn glob_match(pattern:&[u8], s:&[u8]) -> bool {
+    match (pattern.first(), s.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => glob_match(&pattern[1..], s)
+            || (!s.is_empty() && glob_match(pattern, &s[1..])),
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &s[1..]),
+        (Some(p), Some(c)) if p == c => glob_match(&pattern[1..], &s[1..]),
+        _others => false,
+    }
+}
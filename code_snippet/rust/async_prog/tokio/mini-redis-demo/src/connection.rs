@@ -1,37 +1,88 @@
 use std::io::Cursor;
+use std::net::SocketAddr;
 use bytes::{BytesMut, Buf};
 
-// A single call to `TcpStream.read()` or `TcpStream.write()` will fetch
-// or deliver arbitrary amount of data from/to low-level socket respectively.
-use tokio::net::TcpStream;
+// A single call to `read()` or `write()` of the underlying stream, e.g.
+// `TcpStream`, will fetch or deliver arbitrary amount of data from/to
+// low-level socket respectively.
 
 // `AsyncReadExt` is required when reading raw data from internal buffer
 //  by calling `BufWriter.read_buf(&BytesMut)`.
 //  `BufWriter` implements both the traits `AsyncReadExt` and `AsyncRead`,
 //  since `read_buf(...)` is declared only in `AsyncReadExt`, the trait
 //  is imported as telling `BufWriter` to apply the methods on `AsyncReadExt`
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt, BufWriter};
+use tracing::{debug, trace};
 
 use crate::{AsyncResult};
 use crate::frame::{self, Frame};
 
-pub struct Connection {
-    // `TcpStream` decorated with `BufWriter`, it is possible to perform write
-    // operations directly on `TcpStream`, but it could be better to write the
-    // content to some buffer then flush it to `TcpStream` as soon as it is full
-    stream: BufWriter<TcpStream>,
+// any byte stream the frames can go through, e.g. plain TCP socket or
+// TLS session on top of it
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+// commands accept connection of any transport, the type of the stream is
+// erased so `Command` trait remains object-safe
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+pub struct Connection<S = BoxedStream> {
+    // stream decorated with `BufWriter`, it is possible to perform write
+    // operations directly on the stream, but it could be better to write the
+    // content to some buffer then flush it to the stream as soon as it is full
+    stream: BufWriter<S>,
     buffer: BytesMut,
+    session: Session,
+}
+
+// state of the client bound to the connection, which lives across
+// commands, commands can read or update it through the connection.
+#[derive(Debug, Default)]
+pub struct Session {
+    // given by the server when the client is accepted
+    pub id: u64,
+    pub peer_addr: Option<SocketAddr>,
+    // port the replica is listening on, reported by `REPLCONF listening-port`
+    pub listening_port: Option<u16>,
+    // set by `ASKING`, valid only for the next command
+    pub asking: bool,
+    // authenticated user, `None` until `AUTH` succeeds if the default
+    // user requires password
+    pub user: Option<String>,
+    // set by `CLIENT SETNAME`
+    pub name: Option<String>,
+    // index of the database set by `SELECT`
+    pub db: usize,
+    // set by `CLIENT CACHING`, valid only for the next command
+    pub caching: Option<bool>,
 }
 
-impl Connection {
-    pub fn new (socket:TcpStream) -> Self {
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    // `peer_addr` of the session is unknown to the stream, the caller
+    // sets it if needed
+    pub fn new (stream:S) -> Self {
         let buf_nbytes:usize = 1usize << 10;
         Self{
-            stream:BufWriter::new(socket),
-            buffer:BytesMut::with_capacity(buf_nbytes)
+            stream:BufWriter::new(stream),
+            buffer:BytesMut::with_capacity(buf_nbytes),
+            session:Session::default(),
         }
     }
 
+    pub fn session(&self) -> &Session { &self.session }
+    pub fn session_mut(&mut self) -> &mut Session { &mut self.session }
+
+    // bytes received but not parsed yet, and free space left in the
+    // read buffer
+    pub fn query_buffer(&self) -> (usize, usize) {
+        (self.buffer.len(), self.buffer.capacity() - self.buffer.len())
+    }
+
+    // bytes of frames written but not flushed yet
+    pub fn output_buffer(&self) -> usize {
+        self.stream.buffer().len()
+    }
+
     fn parse_frame(&mut self) -> AsyncResult<Option<Frame>>
     {
         let sliced:&[u8] = &self.buffer[..];
@@ -79,8 +130,10 @@ impl Connection {
             let nread = self.stream.read_buf(& mut self.buffer).await ?;
             if 0 == nread { // `zero` indicates end of stream
                 if self.buffer.is_empty() {
+                    trace!("end of stream");
                     break Ok(None)
                 } else {
+                    debug!(num_bytes = self.buffer.len(), "stream closed in the middle of frame");
                     break Err("connection reset by peer".into())
                 }
             } // if new bytes were loaded, they'd be parsed in next iteration.
@@ -91,6 +144,7 @@ impl Connection {
     // and `Sync`, therefore no need to use `AsyncResult<T>`
     pub async fn write_frame(&mut self, frm:&Frame) -> std::io::Result<()>
     {
+        trace!(?frm, "write frame");
         match frm {
             Frame::Array(frmlist) => {
                 // craft 2 bytes ahead, tell the peer it is array of frames
@@ -101,12 +155,20 @@ impl Connection {
                     self.write_single_frame(f).await?;
                 } // should not take ownership at here ?
             },
-            _others => self.write_single_frame(&frm).await? ,
+            _others => self.write_single_frame(frm).await? ,
         };
         // flush the content in BufWriter to the TCP stream
         self.stream.flush().await
     }
 
+    // send data which is already encoded, e.g. command stream forwarded
+    // to replicas
+    pub async fn write_raw(&mut self, data:&[u8]) -> std::io::Result<()>
+    {
+        self.stream.write_all(data).await?;
+        self.stream.flush().await
+    }
+
     async fn write_decimal(&mut self, val: u64) -> std::io::Result<()>
     {
         use std::io::Write;
@@ -135,10 +197,10 @@ impl Connection {
             },
             Frame::Integer(val) => {
                 self.stream.write_u8(b':').await?;
-                self.write_decimal(*val as u64).await?;
+                self.write_decimal(*val).await?;
             },
             Frame::Bulk(val) => {
-                let _val:&[u8] = &val;
+                let _val:&[u8] = val;
                 let sz = val.len();
                 self.stream.write_u8(b'$').await?;
                 self.write_decimal(sz as u64).await?;
@@ -148,8 +210,13 @@ impl Connection {
             Frame::Null => {
                 self.stream.write_all(b"$-1\r\n").await?;
             },
-            // nested array not supported
-            Frame::Array(_) => unreachable!(),
+            // nested array, e.g. reply of `ROLE`, async fn cannot recurse
+            // without boxing, so simply encode it in memory
+            Frame::Array(_) => {
+                let mut buf = Vec::new();
+                frm.encode(&mut buf);
+                self.stream.write_all(&buf).await?;
+            },
         }
         Ok(())
     }
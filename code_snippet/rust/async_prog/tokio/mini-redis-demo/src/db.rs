@@ -1,22 +1,384 @@
-use std::collections::{HashMap, hash_map};
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::sync::{Arc, Mutex};
 use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::time::{Duration, Instant, SystemTime};
 use bytes::Bytes;
-use tokio::sync::broadcast;
+use indexmap::IndexMap;
+use tokio::sync::{broadcast, OwnedMutexGuard};
+use tracing::{trace, instrument};
+
+use crate::DEFAULT_CHANNEL_CAPACITY;
+use crate::pubsub::{PubSubBroker, ChannelKind, PatternMessage, DEFAULT_NUM_SHARDS};
+use crate::persist::{Snapshotter, AppendOnlyFile};
+use crate::replication::Replication;
+use crate::cluster::Cluster;
+use crate::config::{Config, ServerConfig};
+use crate::acl::Acl;
+use crate::stats::ServerStats;
+use crate::slowlog::SlowLog;
+use crate::latency::{LatencyMonitor, EVENT_AOF_WRITE, EVENT_EVICTION, EVENT_EXPIRE_CYCLE};
+use crate::evict::{EvictionPolicy, LfuCounter, next_random, DEFAULT_MAXMEMORY_SAMPLES};
+use crate::monitor::MonitorHub;
+use crate::registry::ClientRegistry;
+use crate::notify::{KeyspaceNotifier, NotifyFlags};
+use crate::tracking::TrackingTable;
+use crate::cmd::{Del, private_part::Command as _};
+use crate::Frame;
+
+struct Entry {
+    value: Vec<u8>,
+    // the entry is treated as absent once the deadline passed
+    expire_at: Option<Instant>,
+    // read by eviction policies and `OBJECT`
+    last_access: Instant,
+    lfu: LfuCounter,
+}
+
+impl Entry {
+    fn new(value:Vec<u8>, expire_at:Option<Instant>) -> Self {
+        let now = Instant::now();
+        Self{ value, expire_at, last_access: now, lfu: LfuCounter::new(now) }
+    }
+    fn is_expired(&self, now:Instant) -> bool {
+        matches!(self.expire_at, Some(t) if t <= now)
+    }
+}
+
+// rough bytes taken by each entry besides its key and value, i.e. the
+// slot in the hash map and heap allocation headers
+const ENTRY_OVERHEAD:usize = 64;
+
+fn entry_size(k:&str, v:&Entry) -> usize {
+    k.len() + v.value.len() + ENTRY_OVERHEAD
+}
+
+// default number of logical databases, see `SELECT`
+pub const DEFAULT_DATABASES:usize = 16;
+
+// Active expiry as in Redis, every `ACTIVE_EXPIRE_INTERVAL` a few random
+// keys with time to live are checked in each database, and checked again
+// while more than a quarter of them turn out expired. A cycle gives up
+// after `ACTIVE_EXPIRE_BUDGET` so it never holds the store for long.
+pub const ACTIVE_EXPIRE_INTERVAL:Duration = Duration::from_millis(100);
+const ACTIVE_EXPIRE_SAMPLES:usize = 20;
+const ACTIVE_EXPIRE_BUDGET:Duration = Duration::from_millis(25);
+
+// one logical database, all insertions and removals go through the
+// methods below, so the counters are always up-to-date
+#[derive(Default)]
+struct Keyspace {
+    // entries can be picked by position, for sampling keys to evict
+    keyval: IndexMap<String, Entry>,
+    // estimate of memory taken by all entries
+    used_memory: usize,
+    // number of entries with time to live
+    num_expires: usize,
+}
+
+impl Keyspace {
+    // returns false if it replaced existing entry
+    fn insert(&mut self, k:String, v:Entry) -> bool {
+        self.used_memory += entry_size(&k, &v);
+        self.num_expires += v.expire_at.is_some() as usize;
+        let ksize = k.len();
+        match self.keyval.insert(k, v) {
+            Some(old) => {
+                self.used_memory -= ksize + old.value.len() + ENTRY_OVERHEAD;
+                self.num_expires -= old.expire_at.is_some() as usize;
+                false
+            },
+            None => true,
+        }
+    }
+    fn remove(&mut self, k:&str) -> Option<Entry> {
+        // order of the entries does not matter, avoid shifting them
+        let old = self.keyval.swap_remove(k)?;
+        self.used_memory -= entry_size(k, &old);
+        self.num_expires -= old.expire_at.is_some() as usize;
+        Some(old)
+    }
+}
 
 struct InnerDataStore {
-    keyval: HashMap<String, Vec<u8>>,
-    pubsub: HashMap<String, broadcast::Sender<Bytes>>,
+    // indexed by `SELECT`, the number never changes after startup
+    dbs: Vec<Keyspace>,
+    // limit of memory taken by all databases, 0 means no limit
+    maxmemory: usize,
+    policy: EvictionPolicy,
+    samples: usize,
+    // state of random number generator for sampling
+    rng: u64,
+    // (database index, command) of mutations not written to the append-only
+    // file and the replicas yet. Queued while holding the lock with the
+    // mutation, so the log follows the order they are applied.
+    log: VecDeque<(usize, Frame)>,
+}
+
+// logged for keys the server removes by itself, e.g. expired or evicted
+fn del_frame(k:&str) -> Frame {
+    Del::new(vec![k.to_string()]).into_frame()
+}
+
+impl InnerDataStore {
+    fn used_memory(&self) -> usize {
+        self.dbs.iter().map(|ks| ks.used_memory).sum()
+    }
+    fn clear(&mut self) {
+        self.dbs.iter_mut().for_each(|ks| *ks = Keyspace::default());
+    }
+
+    // uniformly distributed in [0, 1)
+    fn random_f64(&mut self) -> f64 {
+        (next_random(&mut self.rng) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Approximated LRU / LFU / TTL as in Redis, the best candidate among a
+    // few randomly sampled keys of each database is evicted, instead of
+    // keeping all keys ordered. `None` if no key can be evicted under the
+    // policy, otherwise (database index, key).
+    fn pick_victim(&mut self, now:Instant) -> Option<(usize, String)> {
+        let volatile = self.policy.is_volatile();
+        // (database, position, score), the higher score, the sooner the
+        // key is evicted
+        let mut best:Option<(usize, usize, u128)> = None;
+        for (db, ks) in self.dbs.iter().enumerate() {
+            let n = ks.keyval.len();
+            if n == 0 || (volatile && ks.num_expires == 0) {
+                continue;
+            }
+            let (mut found, mut tries) = (0, 0);
+            // give up early if only few keys have time to live
+            while found < self.samples && tries < self.samples * 10 {
+                tries += 1;
+                let idx = (next_random(&mut self.rng) % n as u64) as usize;
+                let e = match ks.keyval.get_index(idx) {
+                    Some((_, e)) => e,
+                    None => break,
+                };
+                if volatile && e.expire_at.is_none() {
+                    continue;
+                }
+                found += 1;
+                let score = match self.policy {
+                    EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru =>
+                        now.saturating_duration_since(e.last_access).as_nanos(),
+                    EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu =>
+                        (u8::MAX - e.lfu.value(now)) as u128,
+                    EvictionPolicy::VolatileTtl => match e.expire_at {
+                        Some(t) => u128::MAX - t.saturating_duration_since(now).as_nanos(),
+                        None => 0,
+                    },
+                    // random score, so samples of all databases have the
+                    // same chance
+                    EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom
+                        | EvictionPolicy::NoEviction => next_random(&mut self.rng) as u128,
+                };
+                if best.map(|(_, _, s)| score > s).unwrap_or(true) {
+                    best = Some((db, idx, score));
+                }
+            }
+        }
+        let (db, idx, _) = best?;
+        self.dbs[db].keyval.get_index(idx).map(|(k, _)| (db, k.clone()))
+    }
+
+    // remove expired keys among a few sampled ones with time to live in
+    // the database, (database index, key) of removed keys are added to
+    // `expired`. Returns number of keys sampled and how many were removed.
+    fn expire_sampled(&mut self, db:usize, now:Instant, expired:&mut Vec<(usize, String)>)
+        -> (usize, usize)
+    {
+        let (mut sampled, mut removed, mut tries) = (0, 0, 0);
+        // give up early if only few keys have time to live
+        while sampled < ACTIVE_EXPIRE_SAMPLES && tries < ACTIVE_EXPIRE_SAMPLES * 10 {
+            tries += 1;
+            let ks = &self.dbs[db];
+            if ks.num_expires == 0 {
+                break;
+            }
+            let idx = (next_random(&mut self.rng) % ks.keyval.len() as u64) as usize;
+            let (k, is_expired) = match self.dbs[db].keyval.get_index(idx) {
+                Some((_, e)) if e.expire_at.is_none() => continue,
+                Some((k, e)) => (k.clone(), e.is_expired(now)),
+                None => break,
+            };
+            sampled += 1;
+            if is_expired {
+                self.dbs[db].remove(&k);
+                self.log.push_back((db, del_frame(&k)));
+                expired.push((db, k));
+                removed += 1;
+            }
+        }
+        (sampled, removed)
+    }
+
+    // remove keys until memory usage is under the limit, returns false if
+    // it cannot, e.g. the policy is `noeviction`. (database index, key) of
+    // removed keys are added to `evicted`.
+    fn evict(&mut self, stats:&ServerStats, evicted:&mut Vec<(usize, String)>) -> bool {
+        if self.maxmemory == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        while self.used_memory() > self.maxmemory {
+            if self.policy == EvictionPolicy::NoEviction {
+                return false;
+            }
+            match self.pick_victim(now) {
+                Some((db, k)) => {
+                    self.dbs[db].remove(&k);
+                    self.log.push_back((db, del_frame(&k)));
+                    stats.incr_evicted_keys();
+                    evicted.push((db, k));
+                },
+                None => return false,
+            }
+        }
+        true
+    }
+
+    // all the keys except expired ones, ordered by database index
+    fn dump(&self) -> Vec<DumpedEntry> {
+        let (now, wall_now) = (Instant::now(), SystemTime::now());
+        self.dbs.iter().enumerate().flat_map(|(db, ks)| {
+            ks.keyval.iter().filter(move |(_, v)| !v.is_expired(now)).map(move |(k, v)| {
+                let expire_at = v.expire_at.map(|t| wall_now + (t - now));
+                DumpedEntry{db, key:k.clone(), value:v.value.clone(), expire_at}
+            })
+        }).collect()
+    }
+}
+
+// string encodings reported by `OBJECT ENCODING`, decided the same way
+// as Redis though values are always stored as plain bytes here
+fn encoding_of(value:&[u8]) -> &'static str {
+    let is_int = value.len() <= 20 && std::str::from_utf8(value).ok()
+        .and_then(|s| s.parse::<i64>().ok()).is_some();
+    if is_int {
+        "int"
+    } else if value.len() <= 44 {
+        "embstr"
+    } else {
+        "raw"
+    }
+}
+
+// what `OBJECT` and `MEMORY USAGE` report about a key
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectInfo {
+    pub idle: Duration,
+    // logarithmic access counter, see `LfuCounter`
+    pub freq: u8,
+    pub encoding: &'static str,
+    // bytes taken by key, value and overhead
+    pub size: usize,
+}
+
+// size of each database reported by `INFO`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyspaceInfo {
+    pub keys: usize,
+    // keys with time to live
+    pub expires: usize,
+    pub used_memory: usize,
+}
+
+// key-value pair exported to / imported from persistent storage, the
+// expiry is absolute wall-clock time so it is still valid after restart.
+pub(crate) struct DumpedEntry {
+    pub(crate) db: usize,
+    pub(crate) key: String,
+    pub(crate) value: Vec<u8>,
+    pub(crate) expire_at: Option<SystemTime>,
+}
+
+// what the server does to a subscriber which cannot keep up with the
+// publishers, once messages were overwritten in its broadcast channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    // keep streaming, send an out-of-band frame with number of dropped messages
+    Notify,
+    // report the dropped messages then close the subscriber connection
+    Disconnect,
+}
+
+impl std::str::FromStr for LagPolicy {
+    type Err = IoError;
+    fn from_str(s:&str) -> IoResult<Self> {
+        match s.to_lowercase().as_str() {
+            "notify" => Ok(Self::Notify),
+            "disconnect" => Ok(Self::Disconnect),
+            _others => Err(IoError::new(ErrorKind::InvalidInput,
+                                        format!("invalid pub/sub lag policy '{}'", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for LagPolicy {
+    fn fmt(&self, f:&mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Notify => "notify",
+            Self::Disconnect => "disconnect",
+        };
+        write!(f, "{}", s)
+    }
 }
 
 pub struct FakeDatabase {
     shared : Arc<Mutex<InnerDataStore>>,
+    // database the handle reads and writes, each connection switches its
+    // own handle by `SELECT`, see `select()`
+    index: usize,
+    // channels are managed apart from the keyspace, publishing messages
+    // never waits for the lock of `shared`
+    broker: PubSubBroker,
+    lag_policy: LagPolicy,
+    snapshotter: Arc<Snapshotter>,
+    aof: Arc<AppendOnlyFile>,
+    replication: Arc<Replication>,
+    cluster: Arc<Cluster>,
+    config: Arc<Config>,
+    stats: Arc<ServerStats>,
+    acl: Arc<Acl>,
+    slowlog: Arc<SlowLog>,
+    latency: Arc<LatencyMonitor>,
+    monitors: Arc<MonitorHub>,
+    clients: Arc<ClientRegistry>,
+    notifier: Arc<KeyspaceNotifier>,
+    // keys read by clients with `CLIENT TRACKING` enabled
+    tracking: Arc<TrackingTable>,
+    // logged for the next mutation through this handle, see `log_next()`
+    log_frame: Mutex<Option<Frame>>,
+    // (client id, `CLIENT CACHING`) of the next read, see `track_next()`
+    reader: Mutex<Option<(u64, Option<bool>)>>,
+    // held while draining the write log, so batches taken by different
+    // connections are written in order
+    log_writer: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl Clone for FakeDatabase {
     fn clone(&self) -> Self {
         let shr_state = Arc::clone(&self.shared);
-        Self{ shared: shr_state }
+        Self{ shared: shr_state, index: self.index, broker: self.broker.clone(),
+              lag_policy: self.lag_policy,
+              snapshotter: Arc::clone(&self.snapshotter),
+              aof: Arc::clone(&self.aof),
+              replication: Arc::clone(&self.replication),
+              cluster: Arc::clone(&self.cluster),
+              config: Arc::clone(&self.config),
+              stats: Arc::clone(&self.stats),
+              acl: Arc::clone(&self.acl),
+              slowlog: Arc::clone(&self.slowlog),
+              latency: Arc::clone(&self.latency),
+              monitors: Arc::clone(&self.monitors),
+              clients: Arc::clone(&self.clients),
+              notifier: Arc::clone(&self.notifier),
+              tracking: Arc::clone(&self.tracking),
+              log_frame: Mutex::new(None), reader: Mutex::new(None),
+              log_writer: Arc::clone(&self.log_writer) }
     }
 }
 impl Drop for FakeDatabase {
@@ -24,84 +386,596 @@ impl Drop for FakeDatabase {
         let num_refs = Arc::strong_count(&self.shared);
         if num_refs == 1 {
             let mut fdb = self.shared.lock().unwrap();
-            fdb.keyval.clear();
-            fdb.keyval.shrink_to_fit();
+            fdb.clear();
         }
-        println!("[db][drop] shared, ref count:{}, {}",
-            num_refs,  Arc::weak_count(&self.shared) );
+        trace!(num_refs, num_weak_refs = Arc::weak_count(&self.shared), "drop database handle");
     }
 }
 
+impl Default for FakeDatabase {
+    fn default() -> Self { Self::new() }
+}
+
 impl FakeDatabase {
     pub fn new() -> Self {
-        let _inner_store = InnerDataStore{ keyval:HashMap::new(),
-            pubsub:HashMap::new() };
+        Self::with_pubsub_config(DEFAULT_CHANNEL_CAPACITY, LagPolicy::Notify)
+    }
+    // `chn_capacity` is the number of messages buffered for the slowest
+    // subscriber of a channel, older messages are dropped when it is full.
+    // It can be called outside Tokio runtime, the fan-out tasks of pub/sub
+    // are spawned once they are needed, see `PubSubBroker::new()`
+    pub fn with_pubsub_config(chn_capacity:usize, lag_policy:LagPolicy) -> Self {
+        let seed = RandomState::new().build_hasher().finish() | 1;
+        let dbs = (0 .. DEFAULT_DATABASES).map(|_| Keyspace::default()).collect();
+        let _inner_store = InnerDataStore{ dbs, maxmemory:0,
+            policy:EvictionPolicy::NoEviction, samples:DEFAULT_MAXMEMORY_SAMPLES, rng:seed,
+            log:VecDeque::new() };
         let shr_state = Arc::new(Mutex::new(_inner_store));
-        Self{ shared: shr_state }
+        let broker = PubSubBroker::new(DEFAULT_NUM_SHARDS, chn_capacity);
+        let snapshotter = Arc::new(Snapshotter::default());
+        let aof = Arc::new(AppendOnlyFile::default());
+        let replication = Arc::new(Replication::default());
+        let cluster = Arc::new(Cluster::default());
+        let config = Arc::new(Config::default());
+        let stats = Arc::new(ServerStats::default());
+        let acl = Arc::new(Acl::default());
+        let slowlog = Arc::new(SlowLog::default());
+        let latency = Arc::new(LatencyMonitor::default());
+        let monitors = Arc::new(MonitorHub::default());
+        let clients = Arc::new(ClientRegistry::default());
+        let notifier = Arc::new(KeyspaceNotifier::default());
+        let tracking = Arc::new(TrackingTable::default());
+        Self{ shared: shr_state, index: 0, broker, lag_policy, snapshotter, aof, replication,
+              cluster, config, stats, acl, slowlog, latency, monitors, clients, notifier,
+              tracking, log_frame: Mutex::new(None), reader: Mutex::new(None),
+              log_writer: Arc::new(tokio::sync::Mutex::new(())) }
     }
-    pub fn set(&self, k:&str, v:Vec<u8>) -> IoResult<()>
+    // settings read at startup, note the append-only file is not opened
+    // here, see `AppendOnlyFile::enable()`
+    pub fn with_config(cfg:ServerConfig) -> Self {
+        let mut db = Self::with_pubsub_config(cfg.pubsub_channel_capacity,
+                                              cfg.pubsub_lag_policy);
+        // no other handle yet, the lock cannot be poisoned
+        if let Ok(mut fdb) = db.shared.lock() {
+            fdb.dbs.resize_with(cfg.databases.max(1), Keyspace::default);
+        }
+        db.snapshotter.set_path(&cfg.dbfilename);
+        db.replication = Arc::new(Replication::new(cfg.repl_backlog_size));
+        db.replication.set_listening_port(cfg.port);
+        // the lock was just created, it cannot be poisoned
+        let _ = db.acl.set_default_password(cfg.requirepass.as_deref());
+        db.slowlog.set_threshold(cfg.slowlog_log_slower_than);
+        let _ = db.slowlog.set_max_len(cfg.slowlog_max_len);
+        db.latency.set_threshold(cfg.latency_monitor_threshold);
+        db.notifier.set_flags(cfg.notify_keyspace_events);
+        let _ = db.set_maxmemory(cfg.maxmemory, cfg.maxmemory_policy, cfg.maxmemory_samples);
+        db.config = Arc::new(Config::new(cfg));
+        db
+    }
+    // another handle of the same store, on the database at `index`, the
+    // caller checks it is below `num_dbs()`
+    pub fn select(&self, index:usize) -> Self {
+        let mut db = self.clone();
+        db.index = index;
+        db
+    }
+    pub fn index(&self) -> usize { self.index }
+    pub fn num_dbs(&self) -> IoResult<usize>
     {
-        if let Ok(mut fdb) = self.shared.lock() {
+        if let Ok(fdb) = self.shared.lock() {
+            Ok(fdb.dbs.len())
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            Err(e)
+        }
+    }
+    pub fn lag_policy(&self) -> LagPolicy { self.lag_policy }
+    pub fn snapshotter(&self) -> &Snapshotter { &self.snapshotter }
+    pub fn aof(&self) -> &Arc<AppendOnlyFile> { &self.aof }
+    pub fn replication(&self) -> &Replication { &self.replication }
+    pub fn cluster(&self) -> &Cluster { &self.cluster }
+    pub fn config(&self) -> &Config { &self.config }
+    pub fn stats(&self) -> &Arc<ServerStats> { &self.stats }
+    pub fn acl(&self) -> &Acl { &self.acl }
+    pub fn slowlog(&self) -> &SlowLog { &self.slowlog }
+    pub fn latency(&self) -> &LatencyMonitor { &self.latency }
+    pub fn monitors(&self) -> &MonitorHub { &self.monitors }
+    pub fn clients(&self) -> &Arc<ClientRegistry> { &self.clients }
+    pub fn notifier(&self) -> &KeyspaceNotifier { &self.notifier }
+    pub fn tracking(&self) -> &Arc<TrackingTable> { &self.tracking }
+
+    // publish keyspace notification of the key, if `notify-keyspace-events`
+    // enables the class of the event. Subscribers get it through ordinary
+    // channels, call it after releasing the db lock.
+    fn notify(&self, class:NotifyFlags, event:&str, db:usize, key:&str)
+    {
+        for (chn, msg) in self.notifier.messages(class, event, db, key) {
+            self.broker.publish_now(ChannelKind::Global, &chn, msg);
+        }
+    }
+
+    // the write command about to run through this handle, it is queued to
+    // the write log by the mutation it makes, under the same lock. Nothing
+    // is logged if the command doesn't change the store.
+    pub fn log_next(&self, frm:Frame)
+    {
+        if let Ok(mut pending) = self.log_frame.lock() {
+            *pending = Some(frm);
+        }
+    }
+
+    // the client about to read keys through this handle, the keys are
+    // remembered for `CLIENT TRACKING` by the read itself under the store
+    // lock, so any write applied after the read invalidates them
+    pub fn track_next(&self, id:u64, caching:Option<bool>)
+    {
+        if let Ok(mut reader) = self.reader.lock() {
+            *reader = Some((id, caching));
+        }
+    }
+
+    // called by reads while holding the store lock
+    fn remember_read(&self, k:&str)
+    {
+        let reader = self.reader.lock().ok().and_then(|mut r| r.take());
+        if let Some((id, caching)) = reader {
+            let _ = self.tracking.remember(id, &[k], caching);
+        }
+    }
+
+    // called by mutations while holding the store lock
+    fn push_log(&self, fdb:&mut InnerDataStore)
+    {
+        let pending = self.log_frame.lock().ok().and_then(|mut f| f.take());
+        if let Some(frm) = pending {
+            fdb.log.push_back((self.index, frm));
+        }
+    }
+
+    // Write mutations queued so far to the append-only file and to the
+    // replicas, in the order they were applied. It may also write those of
+    // other connections, whoever drains the log first writes them.
+    pub async fn propagate(&self) -> IoResult<()>
+    {
+        if let Ok(mut pending) = self.log_frame.lock() {
+            pending.take(); // the command didn't change anything
+        }
+        let is_empty = |db:&Self| db.shared.lock().map(|fdb| fdb.log.is_empty())
+            .map_err(|_| IoError::new(ErrorKind::ResourceBusy, "failed to acquire db lock"));
+        // most commands change nothing, they don't wait for other writers
+        if is_empty(self)? {
+            return Ok(());
+        }
+        let _writer = self.log_writer.lock().await;
+        let entries = match self.shared.lock() {
+            Ok(mut fdb) => std::mem::take(&mut fdb.log),
+            Err(_) => return Err(IoError::new(ErrorKind::ResourceBusy,
+                                              "failed to acquire db lock")),
+        };
+        self.write_log(entries).await
+    }
+
+    // the whole store at a point in the log, commands logged before it are
+    // written to the append-only file and the replicas first. Those logged
+    // after it are not written until the returned guard is dropped, e.g.
+    // once the append-only file buffers them for its rewrite.
+    pub(crate) async fn dump_at_log_cut(&self)
+        -> IoResult<(Vec<DumpedEntry>, OwnedMutexGuard<()>)>
+    {
+        let writer = Arc::clone(&self.log_writer).lock_owned().await;
+        let (dumped, entries) = match self.shared.lock() {
+            Ok(mut fdb) => (fdb.dump(), std::mem::take(&mut fdb.log)),
+            Err(_) => return Err(IoError::new(ErrorKind::ResourceBusy,
+                                              "failed to acquire db lock")),
+        };
+        self.write_log(entries).await?;
+        Ok((dumped, writer))
+    }
+
+    // a replica writes the stream of its primary by itself, it holds the
+    // guard while applying and writing each command, see `dump_at_log_cut()`
+    pub(crate) async fn lock_log(&self) -> OwnedMutexGuard<()>
+    {
+        Arc::clone(&self.log_writer).lock_owned().await
+    }
+
+    // called while holding `log_writer`
+    async fn write_log(&self, entries:VecDeque<(usize, Frame)>) -> IoResult<()>
+    {
+        if entries.is_empty() { // taken by another connection meanwhile
+            return Ok(());
+        }
+        let started = Instant::now();
+        let mut result = Ok(());
+        for (db, frm) in entries {
+            // keep the replicas in sync even if the file cannot be written
+            let appended = self.aof.append(db, &frm).await;
+            let fed = self.replication.feed(db, &frm);
+            if result.is_ok() {
+                result = appended.and(fed);
+            }
+        }
+        self.latency.record(EVENT_AOF_WRITE, started.elapsed());
+        result
+    }
+
+    #[instrument(level = "trace", skip(self, v), fields(len = v.len()))]
+    pub fn set(&self, k:&str, v:Vec<u8>, expire:Option<Duration>) -> IoResult<()>
+    {
+        let is_new = if let Ok(mut fdb) = self.shared.lock() {
             // the hashmap object also needs to be owner of the
             // key / value stored in frame without moving them.
             let key = k.to_string();
-            let value = v;
-            fdb.keyval.insert(key, value); // may return previously inserted value
-            Ok(())
+            let expire_at = expire.map(|d| Instant::now() + d);
+            let value = Entry::new(v, expire_at);
+            self.push_log(&mut fdb);
+            fdb.dbs[self.index].insert(key, value) // replaces previously inserted value
         } else {
             let e = IoError::new( ErrorKind::ResourceBusy,
                                   "failed to acquire db lock");
-            Err(e)
+            return Err(e)
+        };
+        if is_new {
+            self.notify(NotifyFlags::NEW, "new", self.index, k);
         }
+        self.notify(NotifyFlags::STRING, "set", self.index, k);
+        self.tracking.invalidate(k);
+        Ok(())
     }
+    #[instrument(level = "trace", skip(self))]
     pub fn get(&self, k:&str) -> IoResult<Option<Vec<u8>>>
+    {
+        // a replica keeps expired keys until the primary sends `DEL`, a
+        // `DEL` of its own would put its offset ahead of the primary
+        let is_replica = self.replication.is_replica();
+        if let Ok(mut guard) = self.shared.lock() {
+            let fdb = &mut *guard;
+            let now = Instant::now();
+            self.remember_read(k);
+            match fdb.dbs[self.index].keyval.get(k) {
+                Some(v) if v.is_expired(now) && is_replica => Ok(None),
+                Some(v) if v.is_expired(now) => {
+                    fdb.dbs[self.index].remove(k);
+                    fdb.log.push_back((self.index, del_frame(k)));
+                    drop(guard);
+                    self.stats.incr_expired_keys();
+                    self.notify(NotifyFlags::EXPIRED, "expired", self.index, k);
+                    self.tracking.invalidate(k);
+                    Ok(None)
+                },
+                Some(_) => {
+                    let rand = fdb.random_f64();
+                    let v = fdb.dbs[self.index].keyval.get_mut(k).unwrap();
+                    v.last_access = now;
+                    v.lfu.touch(now, rand);
+                    Ok(Some(v.value.clone()))
+                },
+                None => Ok(None),
+            }
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            Err(e)
+        }
+    }
+    // `DEL`, returns number of keys removed, expired ones are not counted
+    pub fn del(&self, keys:&[String]) -> IoResult<usize>
+    {
+        let (mut removed, mut expired) = (Vec::new(), Vec::new());
+        if let Ok(mut fdb) = self.shared.lock() {
+            let now = Instant::now();
+            for k in keys {
+                match fdb.dbs[self.index].remove(k) {
+                    Some(v) if v.is_expired(now) => expired.push(k),
+                    Some(_) => removed.push(k),
+                    None => {},
+                }
+            }
+            if !removed.is_empty() || !expired.is_empty() {
+                self.push_log(&mut fdb);
+            }
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            return Err(e)
+        }
+        for k in removed.iter() {
+            self.notify(NotifyFlags::GENERIC, "del", self.index, k);
+            self.tracking.invalidate(k);
+        }
+        for k in expired {
+            self.stats.incr_expired_keys();
+            self.notify(NotifyFlags::EXPIRED, "expired", self.index, k);
+            self.tracking.invalidate(k);
+        }
+        Ok(removed.len())
+    }
+    pub fn exists(&self, k:&str) -> IoResult<bool>
     {
         if let Ok(fdb) = self.shared.lock() {
-            if let Some(v) = fdb.keyval.get(k) {
-                Ok(Some(v.clone()))
-            } else {
-                Ok(None)
+            let found = fdb.dbs[self.index].keyval.get(k)
+                .map(|v| !v.is_expired(Instant::now()));
+            Ok(found.unwrap_or(false))
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            Err(e)
+        }
+    }
+    // `OBJECT` / `MEMORY USAGE`, the key is not touched
+    pub fn object_info(&self, k:&str) -> IoResult<Option<ObjectInfo>>
+    {
+        if let Ok(fdb) = self.shared.lock() {
+            let now = Instant::now();
+            let info = fdb.dbs[self.index].keyval.get(k).filter(|v| !v.is_expired(now)).map(|v| ObjectInfo{
+                idle: now.saturating_duration_since(v.last_access), freq: v.lfu.value(now),
+                encoding: encoding_of(&v.value), size: entry_size(k, v) });
+            Ok(info)
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            Err(e)
+        }
+    }
+    // `maxmemory` is in bytes, 0 means no limit
+    pub fn set_maxmemory(&self, maxmemory:usize, policy:EvictionPolicy, samples:usize)
+        -> IoResult<()>
+    {
+        if let Ok(mut fdb) = self.shared.lock() {
+            fdb.maxmemory = maxmemory;
+            fdb.policy = policy;
+            fdb.samples = samples.max(1);
+            Ok(())
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            Err(e)
+        }
+    }
+    // called before each write command, returns false if memory is still
+    // over `maxmemory` so the command should be refused
+    pub fn evict_if_needed(&self) -> IoResult<bool>
+    {
+        let started = Instant::now();
+        let mut evicted = Vec::new();
+        let fits = if let Ok(mut fdb) = self.shared.lock() {
+            if fdb.maxmemory == 0 || fdb.used_memory() <= fdb.maxmemory {
+                return Ok(true);
+            }
+            fdb.evict(&self.stats, &mut evicted)
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            return Err(e)
+        };
+        self.latency.record(EVENT_EVICTION, started.elapsed());
+        for (db, k) in evicted {
+            self.notify(NotifyFlags::EVICTED, "evicted", db, &k);
+            self.tracking.invalidate(&k);
+        }
+        Ok(fits)
+    }
+    // One cycle of active expiry over all databases, for keys nobody reads
+    // anymore. Removed keys are handled as if they expired on access, and
+    // logged as `DEL`. Returns number of removed keys.
+    pub async fn active_expire_cycle(&self) -> IoResult<usize>
+    {
+        let started = Instant::now();
+        let mut expired = Vec::new();
+        for db in 0 .. self.num_dbs()? {
+            loop {
+                let (sampled, removed) = match self.shared.lock() {
+                    Ok(mut fdb) => fdb.expire_sampled(db, Instant::now(), &mut expired),
+                    Err(_) => return Err(IoError::new(ErrorKind::ResourceBusy,
+                                                      "failed to acquire db lock")),
+                };
+                if removed * 4 <= sampled || started.elapsed() > ACTIVE_EXPIRE_BUDGET {
+                    break;
+                }
             }
+        }
+        self.latency.record(EVENT_EXPIRE_CYCLE, started.elapsed());
+        for (db, k) in expired.iter() {
+            self.stats.incr_expired_keys();
+            self.notify(NotifyFlags::EXPIRED, "expired", *db, k);
+            self.tracking.invalidate(k);
+        }
+        self.propagate().await?;
+        Ok(expired.len())
+    }
+    // all databases, in order of their index
+    pub fn keyspace_info(&self) -> IoResult<Vec<KeyspaceInfo>>
+    {
+        if let Ok(fdb) = self.shared.lock() {
+            Ok(fdb.dbs.iter().map(|ks| KeyspaceInfo{ keys: ks.keyval.len(),
+                expires: ks.num_expires, used_memory: ks.used_memory }).collect())
         } else {
             let e = IoError::new( ErrorKind::ResourceBusy,
                                   "failed to acquire db lock");
             Err(e)
         }
     }
-    pub(crate) fn publish(&self, chn:&str, msg:&Bytes) -> IoResult<usize> {
+    // `DBSIZE`, expired keys not removed yet are counted
+    pub fn dbsize(&self) -> IoResult<usize>
+    {
         if let Ok(fdb) = self.shared.lock() {
-            if let Some(sender) = fdb.pubsub.get(chn) {
-                let msg = msg.clone();
-                let num_subs = sender.send(msg).unwrap_or(0);
-                Ok(num_subs)
+            Ok(fdb.dbs[self.index].keyval.len())
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            Err(e)
+        }
+    }
+    // `MOVE`, the key keeps its value, time to live and access history,
+    // nothing moves if the key already exists in the database `dst`
+    pub fn move_key(&self, k:&str, dst:usize) -> IoResult<bool>
+    {
+        // on a replica the `MOVE` comes from the primary, which still has
+        // the key, see `get()`
+        let is_replica = self.replication.is_replica();
+        let moved = if let Ok(mut fdb) = self.shared.lock() {
+            let now = Instant::now();
+            let taken = fdb.dbs[dst].keyval.get(k).map(|v| !v.is_expired(now));
+            if taken.unwrap_or(false) {
+                return Ok(false);
+            }
+            match fdb.dbs[self.index].remove(k) {
+                Some(v) if v.is_expired(now) && !is_replica => {
+                    fdb.log.push_back((self.index, del_frame(k)));
+                    Some(false)
+                },
+                Some(v) => {
+                    fdb.dbs[dst].insert(k.to_string(), v);
+                    self.push_log(&mut fdb);
+                    Some(true)
+                },
+                None => None,
+            }
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            return Err(e)
+        };
+        match moved {
+            Some(true) => {
+                self.notify(NotifyFlags::GENERIC, "move_from", self.index, k);
+                self.notify(NotifyFlags::GENERIC, "move_to", dst, k);
+                self.tracking.invalidate(k);
+                Ok(true)
+            },
+            Some(false) => {
+                self.stats.incr_expired_keys();
+                self.notify(NotifyFlags::EXPIRED, "expired", self.index, k);
+                self.tracking.invalidate(k);
+                Ok(false)
+            },
+            None => Ok(false),
+        }
+    }
+    // `SWAPDB`, connections on either database see the data of the other
+    // one immediately
+    pub fn swap_db(&self, a:usize, b:usize) -> IoResult<()>
+    {
+        if let Ok(mut fdb) = self.shared.lock() {
+            fdb.dbs.swap(a, b);
+            self.push_log(&mut fdb);
+            drop(fdb);
+            self.tracking.invalidate_all();
+            Ok(())
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            Err(e)
+        }
+    }
+    // `FLUSHDB` on the selected database, or `FLUSHALL` if `all` is set.
+    // The keys are gone as soon as it returns, with `lazy` the memory is
+    // released in a blocking thread so a huge database doesn't stall
+    // other connections, it has to be called within Tokio runtime then.
+    pub fn flush(&self, all:bool, lazy:bool) -> IoResult<()>
+    {
+        let dropped:Vec<Keyspace> = if let Ok(mut fdb) = self.shared.lock() {
+            self.push_log(&mut fdb);
+            if all {
+                fdb.dbs.iter_mut().map(std::mem::take).collect()
             } else {
-                let e = IoError::new( ErrorKind::NotFound,
-                                      "channel not exists");
-                Err(e)
+                vec![std::mem::take(&mut fdb.dbs[self.index])]
             }
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire db lock");
+            return Err(e)
+        };
+        self.tracking.invalidate_all();
+        if lazy {
+            tokio::task::spawn_blocking(move || drop(dropped));
+        }
+        Ok(())
+    }
+    // remove all keys, e.g. before a replica loads snapshot of its primary
+    #[instrument(level = "trace", skip(self))]
+    pub(crate) fn clear(&self) -> IoResult<()>
+    {
+        if let Ok(mut fdb) = self.shared.lock() {
+            fdb.clear();
+            drop(fdb);
+            self.tracking.invalidate_all();
+            Ok(())
         } else {
             let e = IoError::new( ErrorKind::ResourceBusy,
                                   "failed to acquire db lock");
             Err(e)
         }
     }
-    pub(crate) fn subscribe(&self, chn:String) -> IoResult<broadcast::Receiver<Bytes>>
+    // (database index, key) of all keys currently in the store, used for
+    // dumping the store in several small slices
+    // export all the keys, expired ones are skipped, entries are ordered
+    // by database index
+    pub(crate) fn dump(&self) -> IoResult<Vec<DumpedEntry>>
+    {
+        match self.shared.lock() {
+            Ok(fdb) => Ok(fdb.dump()),
+            Err(_) => {
+                let e = IoError::new( ErrorKind::ResourceBusy,
+                                      "failed to acquire db lock");
+                Err(e)
+            },
+        }
+    }
+    // import entries loaded from persistent storage, already expired
+    // entries are discarded
+    #[instrument(level = "trace", skip_all, fields(num_entries = entries.len()))]
+    pub(crate) fn restore(&self, entries:Vec<DumpedEntry>) -> IoResult<usize>
     {
         if let Ok(mut fdb) = self.shared.lock() {
-            let recver = match fdb.pubsub.entry(chn) {
-                hash_map::Entry::Occupied(e) => e.get().subscribe(),
-                hash_map::Entry::Vacant(e) => {
-                    let (_sender, _recver) = broadcast::channel(30);
-                    e.insert(_sender);
-                    _recver
-                },
-            };
-            Ok(recver)
+            let (now, wall_now) = (Instant::now(), SystemTime::now());
+            let mut num_restored = 0;
+            for item in entries {
+                if item.db >= fdb.dbs.len() {
+                    let e = IoError::new( ErrorKind::InvalidData,
+                        format!("database index {} is out of range", item.db));
+                    return Err(e)
+                }
+                let expire_at = match item.expire_at {
+                    Some(t) => match t.duration_since(wall_now) {
+                        Ok(remain) => Some(now + remain),
+                        Err(_) => continue,
+                    },
+                    None => None,
+                };
+                fdb.dbs[item.db].insert(item.key, Entry::new(item.value, expire_at));
+                num_restored += 1;
+            }
+            Ok(num_restored)
         } else {
             let e = IoError::new( ErrorKind::ResourceBusy,
                                   "failed to acquire db lock");
             Err(e)
         }
     }
+    pub(crate) async fn publish(&self, kind:ChannelKind, chn:&str, msg:&Bytes)
+        -> IoResult<usize>
+    {
+        self.broker.publish(kind, chn, msg).await
+    }
+    pub fn num_channels(&self, kind:ChannelKind) -> usize
+    {
+        self.broker.num_channels(kind)
+    }
+    pub(crate) fn subscribe(&self, kind:ChannelKind, chn:String)
+        -> IoResult<broadcast::Receiver<Bytes>>
+    {
+        self.broker.subscribe(kind, chn)
+    }
+    pub(crate) fn unsubscribe(&self, kind:ChannelKind, chn:&str)
+    {
+        self.broker.unsubscribe(kind, chn)
+    }
+    pub(crate) fn psubscribe(&self, pattern:String)
+        -> IoResult<broadcast::Receiver<PatternMessage>>
+    {
+        self.broker.psubscribe(pattern)
+    }
 } // end of FakeDatabase
 
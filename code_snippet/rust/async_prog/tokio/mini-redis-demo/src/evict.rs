@@ -0,0 +1,139 @@
+use std::fmt;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::str::FromStr;
+use std::time::Instant;
+
+// keys compared each time one of them is evicted, more samples give
+// better approximation of true LRU / LFU but take more time
+pub const DEFAULT_MAXMEMORY_SAMPLES:usize = 5;
+
+// which keys are removed once `maxmemory` is reached, same names as Redis.
+// `volatile-*` policies consider only keys with time to live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    // refuse write commands instead
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    // the key closest to its expiry
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    pub fn is_volatile(&self) -> bool {
+        matches!(self, Self::VolatileLru | Self::VolatileLfu | Self::VolatileRandom
+                 | Self::VolatileTtl)
+    }
+    pub fn is_lfu(&self) -> bool {
+        matches!(self, Self::AllKeysLfu | Self::VolatileLfu)
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = IoError;
+    fn from_str(s:&str) -> IoResult<Self> {
+        match s.to_lowercase().as_str() {
+            "noeviction" => Ok(Self::NoEviction),
+            "allkeys-lru" => Ok(Self::AllKeysLru),
+            "allkeys-lfu" => Ok(Self::AllKeysLfu),
+            "allkeys-random" => Ok(Self::AllKeysRandom),
+            "volatile-lru" => Ok(Self::VolatileLru),
+            "volatile-lfu" => Ok(Self::VolatileLfu),
+            "volatile-random" => Ok(Self::VolatileRandom),
+            "volatile-ttl" => Ok(Self::VolatileTtl),
+            _others => Err(IoError::new(ErrorKind::InvalidInput,
+                                        format!("invalid maxmemory policy '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileLru => "volatile-lru",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Access frequency of a key as in Redis, a logarithmic counter in one
+// byte, which halves its chance to grow as it gets bigger, and decays by
+// one for every minute the key is not accessed.
+#[derive(Debug, Clone, Copy)]
+pub struct LfuCounter {
+    counter: u8,
+    decayed_at: Instant,
+}
+
+// new keys start above zero, so they are not evicted before they have a
+// chance to be accessed
+const LFU_INIT_VAL:u8 = 5;
+const LFU_LOG_FACTOR:f64 = 10.0;
+const LFU_DECAY_SECS:u64 = 60;
+
+impl LfuCounter {
+    pub fn new(now:Instant) -> Self {
+        Self{ counter: LFU_INIT_VAL, decayed_at: now }
+    }
+
+    // current value after decay, without touching the key
+    pub fn value(&self, now:Instant) -> u8 {
+        let periods = now.saturating_duration_since(self.decayed_at).as_secs() / LFU_DECAY_SECS;
+        self.counter.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    // `rand` is uniformly distributed in [0, 1)
+    pub fn touch(&mut self, now:Instant, rand:f64) {
+        let counter = self.value(now);
+        if counter != self.counter {
+            self.counter = counter;
+            self.decayed_at = now;
+        }
+        if self.counter == u8::MAX {
+            return;
+        }
+        let base = self.counter.saturating_sub(LFU_INIT_VAL) as f64;
+        if rand < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            self.counter += 1;
+        }
+    }
+}
+
+// xorshift, good enough to pick samples
+pub(crate) fn next_random(state:&mut u64) -> u64 {
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+// sizes in `redis.conf` style, e.g. `100mb`, `1gb`, `4096`
+pub(crate) fn parse_memory(s:&str) -> Option<usize> {
+    let lower = s.trim().to_lowercase();
+    let split = lower.find(|c:char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (num, unit) = lower.split_at(split);
+    let mul:usize = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _others => return None,
+    };
+    num.parse::<usize>().ok()?.checked_mul(mul)
+}
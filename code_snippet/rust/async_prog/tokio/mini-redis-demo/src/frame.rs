@@ -167,6 +167,39 @@ impl Frame {
         }
     }
 
+    /// Serializes the frame to the Redis protocol and appends the result to
+    /// `dst`, this is the same format `Connection::write_frame` writes to the
+    /// socket.
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        match self {
+            Frame::Simple(val) => {
+                dst.push(b'+');
+                dst.extend_from_slice(val.as_bytes());
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Error(val) => {
+                dst.push(b'-');
+                dst.extend_from_slice(val.as_bytes());
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Integer(val) => {
+                dst.extend_from_slice(format!(":{}\r\n", val).as_bytes());
+            }
+            Frame::Bulk(val) => {
+                dst.extend_from_slice(format!("${}\r\n", val.len()).as_bytes());
+                dst.extend_from_slice(val);
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Null => dst.extend_from_slice(b"$-1\r\n"),
+            Frame::Array(parts) => {
+                dst.extend_from_slice(format!("*{}\r\n", parts.len()).as_bytes());
+                for part in parts {
+                    part.encode(dst);
+                }
+            }
+        }
+    }
+
     /// Converts the frame to an "unexpected frame" error
     pub(crate) fn to_error(&self) -> crate::AsyncError {
         format!("unexpected frame: {}", self).into()
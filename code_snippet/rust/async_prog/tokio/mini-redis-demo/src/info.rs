@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::Result as IoResult;
+
+use crate::db::{FakeDatabase, KeyspaceInfo};
+use crate::pubsub::ChannelKind;
+use crate::replication::{LinkState, RoleInfo};
+use crate::stats::{CommandStats, LATENCY_BUCKETS_USEC};
+use crate::evict::EvictionPolicy;
+
+// sections shown by `INFO` without argument, or `INFO default`
+pub const DEFAULT_SECTIONS:[&str; 8] = [
+    "server", "clients", "memory", "persistence", "stats", "replication",
+    "cluster", "keyspace",
+];
+// shown only by `INFO all`, or when requested explicitly
+const EXTRA_SECTIONS:[&str; 2] = ["commandstats", "latencystats"];
+
+// state of the server at a moment, both `INFO` and metrics endpoint
+// render the same snapshot
+struct Snapshot {
+    port: u16,
+    tls_port: u16,
+    maxclients: usize,
+    uptime_secs: u64,
+    connected_clients: usize,
+    total_connections_received: u64,
+    rejected_connections: u64,
+    accept_errors: u64,
+    total_commands_processed: u64,
+    expired_keys: u64,
+    evicted_keys: u64,
+    sync_full: u64,
+    sync_partial_ok: u64,
+    pubsub_channels: usize,
+    pubsub_patterns: usize,
+    pubsubshard_channels: usize,
+    // client-side caching, see `tracking::TrackingTable`
+    tracking_clients: usize,
+    tracking_total_keys: usize,
+    // all databases in order of their index
+    keyspace: Vec<KeyspaceInfo>,
+    used_memory: usize,
+    maxmemory: usize,
+    maxmemory_policy: EvictionPolicy,
+    aof_enabled: bool,
+    bgsave_in_progress: bool,
+    last_save: u64,
+    role: RoleInfo,
+    cluster_enabled: bool,
+    commands: BTreeMap<String, CommandStats>,
+}
+
+impl Snapshot {
+    async fn collect(db:&FakeDatabase) -> IoResult<Self> {
+        let cfg = db.config().current()?;
+        let stats = db.stats();
+        let keyspace = db.keyspace_info()?;
+        Ok(Self{
+            port: cfg.port, tls_port: cfg.tls_port, maxclients: cfg.maxclients,
+            uptime_secs: stats.uptime().as_secs(),
+            connected_clients: stats.connected_clients(),
+            total_connections_received: stats.total_connections_received(),
+            rejected_connections: stats.rejected_connections(),
+            accept_errors: stats.accept_errors(),
+            total_commands_processed: stats.total_commands_processed(),
+            expired_keys: stats.expired_keys(),
+            evicted_keys: stats.evicted_keys(),
+            sync_full: stats.sync_full(),
+            sync_partial_ok: stats.sync_partial_ok(),
+            pubsub_channels: db.num_channels(ChannelKind::Global),
+            pubsub_patterns: db.num_channels(ChannelKind::Pattern),
+            pubsubshard_channels: db.num_channels(ChannelKind::Sharded),
+            tracking_clients: db.tracking().num_clients()?,
+            tracking_total_keys: db.tracking().num_keys()?,
+            used_memory: keyspace.iter().map(|ks| ks.used_memory).sum(),
+            keyspace,
+            maxmemory: cfg.maxmemory, maxmemory_policy: cfg.maxmemory_policy,
+            aof_enabled: db.aof().is_enabled().await,
+            bgsave_in_progress: db.snapshotter().is_bgsave_running(),
+            last_save: db.snapshotter().last_save(),
+            role: db.replication().role()?,
+            cluster_enabled: db.cluster().is_enabled(),
+            commands: stats.commands(),
+        })
+    }
+
+    fn section(&self, name:&str) -> Option<Vec<(String, String)>> {
+        let mut out:Vec<(String, String)> = Vec::new();
+        let mut add = |k:&str, v:String| out.push((k.to_string(), v));
+        match name {
+            "server" => {
+                add("redis_version", env!("CARGO_PKG_VERSION").to_string());
+                let mode = if self.cluster_enabled {"cluster"} else {"standalone"};
+                add("redis_mode", mode.to_string());
+                add("process_id", std::process::id().to_string());
+                add("tcp_port", self.port.to_string());
+                add("tls_port", self.tls_port.to_string());
+                add("uptime_in_seconds", self.uptime_secs.to_string());
+                add("uptime_in_days", (self.uptime_secs / 86400).to_string());
+            },
+            "clients" => {
+                add("connected_clients", self.connected_clients.to_string());
+                add("maxclients", self.maxclients.to_string());
+                add("tracking_clients", self.tracking_clients.to_string());
+            },
+            "memory" => {
+                add("used_memory", self.used_memory.to_string());
+                add("used_memory_human", human_bytes(self.used_memory));
+                add("maxmemory", self.maxmemory.to_string());
+                add("maxmemory_human", human_bytes(self.maxmemory));
+                add("maxmemory_policy", self.maxmemory_policy.to_string());
+            },
+            "persistence" => {
+                add("rdb_bgsave_in_progress", (self.bgsave_in_progress as u8).to_string());
+                add("rdb_last_save_time", self.last_save.to_string());
+                add("aof_enabled", (self.aof_enabled as u8).to_string());
+            },
+            "stats" => {
+                add("total_connections_received", self.total_connections_received.to_string());
+                add("total_commands_processed", self.total_commands_processed.to_string());
+                add("rejected_connections", self.rejected_connections.to_string());
+                add("accept_errors", self.accept_errors.to_string());
+                add("expired_keys", self.expired_keys.to_string());
+                add("evicted_keys", self.evicted_keys.to_string());
+                add("sync_full", self.sync_full.to_string());
+                add("sync_partial_ok", self.sync_partial_ok.to_string());
+                add("pubsub_channels", self.pubsub_channels.to_string());
+                add("pubsub_patterns", self.pubsub_patterns.to_string());
+                add("pubsubshard_channels", self.pubsubshard_channels.to_string());
+                add("tracking_total_keys", self.tracking_total_keys.to_string());
+            },
+            "replication" => match &self.role {
+                RoleInfo::Primary{offset, replicas} => {
+                    add("role", "master".to_string());
+                    add("connected_slaves", replicas.len().to_string());
+                    for (idx, (ip, port, ack)) in replicas.iter().enumerate() {
+                        add(&format!("slave{}", idx),
+                            format!("ip={},port={},state=online,offset={}", ip, port, ack));
+                    }
+                    add("master_repl_offset", offset.to_string());
+                },
+                RoleInfo::Replica{host, port, state, offset} => {
+                    add("role", "slave".to_string());
+                    add("master_host", host.clone());
+                    add("master_port", port.to_string());
+                    let status = if matches!(state, LinkState::Connected) {"up"} else {"down"};
+                    add("master_link_status", status.to_string());
+                    add("master_repl_offset", offset.to_string());
+                },
+            },
+            "cluster" => {
+                add("cluster_enabled", (self.cluster_enabled as u8).to_string());
+            },
+            "keyspace" => {
+                for (idx, ks) in self.keyspace.iter().enumerate().filter(|(_, ks)| ks.keys > 0) {
+                    add(&format!("db{}", idx), format!("keys={},expires={}", ks.keys,
+                                                       ks.expires));
+                }
+            },
+            "commandstats" => {
+                for (cmd, s) in self.commands.iter() {
+                    let timed = s.timed_calls();
+                    let per_call = if timed > 0 { s.usec as f64 / timed as f64 } else { 0.0 };
+                    add(&format!("cmdstat_{}", cmd),
+                        format!("calls={},usec={},usec_per_call={:.2},rejected_calls={}",
+                                s.calls, s.usec, per_call, s.rejected_calls));
+                }
+            },
+            "latencystats" => {
+                for (cmd, s) in self.commands.iter().filter(|(_, s)| s.timed_calls() > 0) {
+                    let p = |pct:f64| s.percentile_usec(pct).map(|v| v.to_string())
+                        .unwrap_or_else(|| "+inf".to_string());
+                    add(&format!("latency_percentiles_usec_{}", cmd),
+                        format!("p50={},p99={},p99.9={}", p(50.0), p(99.0), p(99.9)));
+                }
+            },
+            _others => return None,
+        }
+        Some(out)
+    } // end of section
+} // end of Snapshot
+
+fn human_bytes(n:usize) -> String {
+    match n {
+        n if n >= 1 << 30 => format!("{:.2}G", n as f64 / (1u64 << 30) as f64),
+        n if n >= 1 << 20 => format!("{:.2}M", n as f64 / (1u64 << 20) as f64),
+        n if n >= 1 << 10 => format!("{:.2}K", n as f64 / (1u64 << 10) as f64),
+        n => format!("{}B", n),
+    }
+}
+
+// Text of `INFO [section ...]` in Redis format, `# Section` header then
+// `field:value` lines. No section means the default ones, `all` or
+// `everything` means all of them, unknown sections are ignored.
+pub async fn render_info(db:&FakeDatabase, requested:&[String]) -> IoResult<String> {
+    let snapshot = Snapshot::collect(db).await?;
+    let mut names:Vec<&str> = Vec::new();
+    if requested.is_empty() {
+        names.extend(DEFAULT_SECTIONS);
+    }
+    for r in requested {
+        match r.to_lowercase().as_str() {
+            "default" => names.extend(DEFAULT_SECTIONS),
+            "all" | "everything" => names.extend(DEFAULT_SECTIONS.iter().chain(EXTRA_SECTIONS.iter())),
+            other => if let Some(n) = DEFAULT_SECTIONS.iter().chain(EXTRA_SECTIONS.iter())
+                .find(|n| **n == other) {
+                names.push(n);
+            },
+        }
+    }
+    let mut out = String::new();
+    let mut seen = Vec::new();
+    for name in names {
+        if seen.contains(&name) {
+            continue;
+        }
+        seen.push(name);
+        let fields = snapshot.section(name).unwrap_or_default();
+        if !out.is_empty() {
+            out.push_str("\r\n");
+        }
+        let mut title = name.to_string();
+        title[..1].make_ascii_uppercase();
+        let _ = write!(out, "# {}\r\n", title);
+        for (k, v) in fields {
+            let _ = write!(out, "{}:{}\r\n", k, v);
+        }
+    }
+    Ok(out)
+}
+
+// `labels` is either empty or in the form `{name="value",...}`
+fn metric(out:&mut String, name:&str, labels:&str, kind:&str, help:&str,
+          value:impl std::fmt::Display) {
+    let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name}{labels} {value}\n");
+}
+
+// text exposition format of Prometheus, names prefixed with `redis_`
+pub async fn render_metrics(db:&FakeDatabase) -> IoResult<String> {
+    let s = Snapshot::collect(db).await?;
+    let mut out = String::new();
+    let gauges:[(&str, &str, u64); 9] = [
+        ("uptime_in_seconds", "Seconds since the server started", s.uptime_secs),
+        ("connected_clients", "Number of client connections", s.connected_clients as u64),
+        ("maxclients", "Maximum number of client connections", s.maxclients as u64),
+        ("used_memory", "Estimated bytes taken by the keyspace", s.used_memory as u64),
+        ("maxmemory", "Limit of used memory, 0 if unlimited", s.maxmemory as u64),
+        ("pubsub_channels", "Channels with subscribers", s.pubsub_channels as u64),
+        ("pubsub_patterns", "Patterns with subscribers", s.pubsub_patterns as u64),
+        ("pubsubshard_channels", "Shard channels with subscribers", s.pubsubshard_channels as u64),
+        ("master_repl_offset", "Replication offset", match &s.role {
+            RoleInfo::Primary{offset, ..} | RoleInfo::Replica{offset, ..} => *offset,
+        }),
+    ];
+    for (name, help, value) in gauges {
+        metric(&mut out, &format!("redis_{}", name), "", "gauge", help, value);
+    }
+    let counters:[(&str, &str, u64); 6] = [
+        ("connections_received_total", "Connections accepted", s.total_connections_received),
+        ("rejected_connections_total", "Connections refused by maxclients", s.rejected_connections),
+        ("accept_errors_total", "Failures of accepting connections", s.accept_errors),
+        ("commands_processed_total", "Commands run", s.total_commands_processed),
+        ("expired_keys_total", "Keys removed after their time to live", s.expired_keys),
+        ("evicted_keys_total", "Keys removed to free memory", s.evicted_keys),
+    ];
+    for (name, help, value) in counters {
+        metric(&mut out, &format!("redis_{}", name), "", "counter", help, value);
+    }
+    // only databases with keys, as `INFO keyspace`
+    out.push_str("# HELP redis_db_keys Keys in the database\n");
+    out.push_str("# TYPE redis_db_keys gauge\n");
+    for (idx, ks) in s.keyspace.iter().enumerate().filter(|(_, ks)| ks.keys > 0) {
+        let _ = writeln!(out, "redis_db_keys{{db=\"db{}\"}} {}", idx, ks.keys);
+    }
+    out.push_str("# HELP redis_db_keys_expiring Keys with time to live\n");
+    out.push_str("# TYPE redis_db_keys_expiring gauge\n");
+    for (idx, ks) in s.keyspace.iter().enumerate().filter(|(_, ks)| ks.keys > 0) {
+        let _ = writeln!(out, "redis_db_keys_expiring{{db=\"db{}\"}} {}", idx, ks.expires);
+    }
+
+    out.push_str("# HELP redis_command_calls_total Calls of each command\n");
+    out.push_str("# TYPE redis_command_calls_total counter\n");
+    for (cmd, c) in s.commands.iter() {
+        let _ = writeln!(out, "redis_command_calls_total{{cmd=\"{}\"}} {}", cmd, c.calls);
+    }
+    out.push_str("# HELP redis_command_rejected_calls_total Calls refused before running\n");
+    out.push_str("# TYPE redis_command_rejected_calls_total counter\n");
+    for (cmd, c) in s.commands.iter() {
+        let _ = writeln!(out, "redis_command_rejected_calls_total{{cmd=\"{}\"}} {}",
+                         cmd, c.rejected_calls);
+    }
+    out.push_str("# HELP redis_command_duration_seconds Latency of each command\n");
+    out.push_str("# TYPE redis_command_duration_seconds histogram\n");
+    for (cmd, c) in s.commands.iter().filter(|(_, c)| c.timed_calls() > 0) {
+        let mut cumulative = 0;
+        for (idx, n) in c.latency_buckets.iter().enumerate() {
+            cumulative += n;
+            let le = match LATENCY_BUCKETS_USEC.get(idx) {
+                Some(usec) => (*usec as f64 / 1e6).to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(out, "redis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}",
+                             cmd, le, cumulative);
+        }
+        let _ = writeln!(out, "redis_command_duration_seconds_sum{{cmd=\"{}\"}} {}",
+                         cmd, c.usec as f64 / 1e6);
+        let _ = writeln!(out, "redis_command_duration_seconds_count{{cmd=\"{}\"}} {}",
+                         cmd, c.timed_calls());
+    }
+    Ok(out)
+} // end of render_metrics
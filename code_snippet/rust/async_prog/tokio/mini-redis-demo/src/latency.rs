@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::slowlog::unix_time;
+
+// samples kept for each event, as Redis does
+const HISTORY_LEN:usize = 160;
+
+// event names recorded by the server
+pub const EVENT_COMMAND:&str = "command";
+pub const EVENT_AOF_WRITE:&str = "aof-write";
+pub const EVENT_EVICTION:&str = "eviction-cycle";
+pub const EVENT_EXPIRE_CYCLE:&str = "expire-cycle";
+
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySample {
+    // unix time in seconds
+    pub timestamp: u64,
+    pub latency_ms: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LatencyEvent {
+    // oldest first, at most one sample per second
+    pub history: VecDeque<LatencySample>,
+    // the highest latency ever seen, survives samples dropped from history
+    pub max_ms: u64,
+}
+
+// Spikes of named events over `latency-monitor-threshold` milliseconds,
+// disabled when the threshold is 0
+#[derive(Default)]
+pub struct LatencyMonitor {
+    threshold_ms: AtomicU64,
+    events: Mutex<BTreeMap<String, LatencyEvent>>,
+}
+
+impl LatencyMonitor {
+    fn lock(&self) -> IoResult<MutexGuard<'_, BTreeMap<String, LatencyEvent>>> {
+        self.events.lock().map_err(|_| {
+            IoError::new(ErrorKind::ResourceBusy, "failed to acquire latency monitor lock")
+        })
+    }
+
+    pub fn set_threshold(&self, ms:u64) {
+        self.threshold_ms.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, event:&str, elapsed:Duration) {
+        let threshold = self.threshold_ms.load(Ordering::Relaxed);
+        let latency_ms = elapsed.as_millis() as u64;
+        if threshold == 0 || latency_ms < threshold {
+            return;
+        }
+        let timestamp = unix_time();
+        if let Ok(mut events) = self.lock() {
+            let ev = events.entry(event.to_string()).or_default();
+            ev.max_ms = ev.max_ms.max(latency_ms);
+            // several spikes in the same second are merged into the worst one
+            match ev.history.back_mut() {
+                Some(last) if last.timestamp == timestamp => {
+                    last.latency_ms = last.latency_ms.max(latency_ms);
+                },
+                _others => {
+                    if ev.history.len() == HISTORY_LEN {
+                        ev.history.pop_front();
+                    }
+                    ev.history.push_back(LatencySample{ timestamp, latency_ms });
+                },
+            }
+        }
+    }
+
+    // `LATENCY LATEST`, (event, latest sample, max latency) of every event
+    pub fn latest(&self) -> IoResult<Vec<(String, LatencySample, u64)>> {
+        let events = self.lock()?;
+        let out = events.iter()
+            .filter_map(|(name, ev)| ev.history.back().map(|s| (name.clone(), *s, ev.max_ms)))
+            .collect();
+        Ok(out)
+    }
+
+    // `LATENCY HISTORY`, empty if the event never happened
+    pub fn history(&self, event:&str) -> IoResult<Vec<LatencySample>> {
+        let events = self.lock()?;
+        Ok(events.get(event).map(|ev| ev.history.iter().copied().collect())
+            .unwrap_or_default())
+    }
+
+    // `LATENCY RESET`, all events if `names` is empty, returns number of
+    // events removed
+    pub fn reset(&self, names:&[String]) -> IoResult<usize> {
+        let mut events = self.lock()?;
+        if names.is_empty() {
+            let n = events.len();
+            events.clear();
+            return Ok(n);
+        }
+        Ok(names.iter().filter(|n| events.remove(n.as_str()).is_some()).count())
+    }
+} // end of LatencyMonitor
@@ -1,7 +1,5 @@
-#![feature(io_error_more)]
-
 mod connection;
-pub use connection::Connection;
+pub use connection::{Connection, Session, AsyncStream, BoxedStream};
 
 mod parse; // not public module / types
 use parse::{Parse, ParseError};
@@ -13,14 +11,32 @@ pub mod clients;
 pub use clients::{Client}; 
 
 mod shutdown;
-pub use shutdown::{SingleRequestShutdown}; 
+pub use shutdown::{SingleRequestShutdown, ShutdownCoordinator, SaveMode};
 
 pub mod db;
+pub mod pubsub;
+pub mod persist;
+pub mod replication;
+pub mod cluster;
+pub mod config;
+pub mod stats;
+pub mod acl;
+pub mod tls;
+pub mod logging;
+pub mod info;
+pub mod slowlog;
+pub mod latency;
+pub mod monitor;
+pub mod registry;
+pub mod evict;
+pub mod notify;
+pub mod tracking;
 pub mod cmd;
 
 
 pub const DEFAULT_PORT:u16 = 6379;
 pub const MAX_CONNECTIONS:u16 = 10;
+pub const DEFAULT_CHANNEL_CAPACITY:usize = 30;
 
 // the AsyncError could be used as return type of any async function.
 // why adding `Send` trait ?
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use tracing_subscriber::{fmt as tfmt, reload, EnvFilter, Layer, Registry};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+// how each event is written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // human readable, one line per event
+    Text,
+    // one JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = IoError;
+    fn from_str(s:&str) -> IoResult<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _others => Err(IoError::new(ErrorKind::InvalidInput,
+                                        format!("invalid log format '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// the filter can be replaced at runtime, e.g. by `CONFIG SET loglevel`
+static FILTER_HANDLE:OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// Redis log levels to `tracing` levels. Events on hot paths (every frame
+// or command) are at `debug` / `trace` level, so they are hidden with
+// default level `notice`.
+fn filter_of(loglevel:&str) -> IoResult<EnvFilter> {
+    let directive = match loglevel.to_lowercase().as_str() {
+        "debug" => "trace",
+        "verbose" => "debug",
+        "notice" => "info",
+        "warning" => "warn",
+        "nothing" => "off",
+        _others => return Err(IoError::new(ErrorKind::InvalidInput,
+                                           format!("invalid log level '{}'", loglevel))),
+    };
+    Ok(EnvFilter::new(directive))
+}
+
+// install global subscriber, environment variable `RUST_LOG` takes
+// precedence over `loglevel` if it is set. Calling it more than once
+// does nothing.
+pub fn init(loglevel:&str, format:LogFormat) -> IoResult<()> {
+    if FILTER_HANDLE.get().is_some() {
+        return Ok(());
+    }
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(f) => f,
+        Err(_) => filter_of(loglevel)?,
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    let output = match format {
+        LogFormat::Text => tfmt::layer().boxed(),
+        LogFormat::Json => tfmt::layer().json().boxed(),
+    };
+    tracing_subscriber::registry().with(filter).with(output).try_init()
+        .map_err(|e| IoError::new(ErrorKind::AlreadyExists, e))?;
+    let _ = FILTER_HANDLE.set(handle);
+    Ok(())
+}
+
+pub fn set_level(loglevel:&str) -> IoResult<()> {
+    let filter = filter_of(loglevel)?;
+    match FILTER_HANDLE.get() {
+        Some(handle) => handle.reload(filter)
+            .map_err(|e| IoError::new(ErrorKind::ResourceBusy, e)),
+        // logging was never set up, nothing to change
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use crate::slowlog::redact;
+
+// lines buffered for the slowest monitor, older lines are dropped
+const MONITOR_CAPACITY:usize = 1024;
+
+// Fan-out of executed commands to clients running `MONITOR`. Connections
+// check `is_active()` before formatting anything, so the server pays
+// nothing for it while no monitor is attached.
+pub struct MonitorHub {
+    sender: broadcast::Sender<String>,
+}
+
+impl Default for MonitorHub {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(MONITOR_CAPACITY);
+        Self{ sender }
+    }
+}
+
+impl MonitorHub {
+    pub fn is_active(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    // the monitor is detached once the receiver is dropped
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.sender.subscribe()
+    }
+
+    // `argv` is the full command including its name, `client_addr` is
+    // `ip:port` or `unix` for Unix domain socket. Commands carrying
+    // passwords are hidden as in the slow log.
+    pub fn feed(&self, db_index:usize, client_addr:&str, argv:&[Bytes]) {
+        if !self.is_active() {
+            return;
+        }
+        let mut argv = argv.to_vec();
+        if !redact(&mut argv) {
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut line = format!("{}.{:06} [{} {}]", now.as_secs(), now.subsec_micros(),
+                               db_index, client_addr);
+        for arg in argv.iter() {
+            line.push(' ');
+            quote_into(&mut line, arg);
+        }
+        // no receiver left in the meantime, nothing to do
+        let _ = self.sender.send(line);
+    }
+} // end of MonitorHub
+
+// double-quoted with escapes, same as `redis-cli` shows binary-safe strings
+fn quote_into(out:&mut String, arg:&[u8]) {
+    out.push('"');
+    for b in arg {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            0x20 ..= 0x7e => out.push(*b as char),
+            _others => { let _ = write!(out, "\\x{:02x}", b); },
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use bytes::Bytes;
+
+// Which keyspace notifications are published, `notify-keyspace-events`
+// in the same letters as Redis. Nothing is published unless at least one
+// of `K` / `E` and one class of events are set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NotifyFlags(u16);
+
+impl NotifyFlags {
+    // `__keyspace@<db>__:<key>` channels, the message is the event
+    pub const KEYSPACE:Self = Self(1 << 0);
+    // `__keyevent@<db>__:<event>` channels, the message is the key
+    pub const KEYEVENT:Self = Self(1 << 1);
+    pub const GENERIC:Self = Self(1 << 2);
+    pub const STRING:Self = Self(1 << 3);
+    pub const LIST:Self = Self(1 << 4);
+    pub const SET:Self = Self(1 << 5);
+    pub const HASH:Self = Self(1 << 6);
+    pub const ZSET:Self = Self(1 << 7);
+    pub const EXPIRED:Self = Self(1 << 8);
+    pub const EVICTED:Self = Self(1 << 9);
+    pub const STREAM:Self = Self(1 << 10);
+    pub const KEY_MISS:Self = Self(1 << 11);
+    pub const MODULE:Self = Self(1 << 12);
+    pub const NEW:Self = Self(1 << 13);
+    // `A`, note key miss and new key events are excluded as in Redis
+    pub const ALL:Self = Self(Self::GENERIC.0 | Self::STRING.0 | Self::LIST.0 | Self::SET.0
+        | Self::HASH.0 | Self::ZSET.0 | Self::EXPIRED.0 | Self::EVICTED.0 | Self::STREAM.0
+        | Self::MODULE.0);
+
+    // letters of the event classes, in the order Redis prints them
+    const CLASSES:[(char, Self); 12] = [
+        ('g', Self::GENERIC), ('$', Self::STRING), ('l', Self::LIST), ('s', Self::SET),
+        ('h', Self::HASH), ('z', Self::ZSET), ('x', Self::EXPIRED), ('e', Self::EVICTED),
+        ('t', Self::STREAM), ('m', Self::KEY_MISS), ('d', Self::MODULE), ('n', Self::NEW),
+    ];
+
+    pub fn contains(&self, other:Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for NotifyFlags {
+    type Output = Self;
+    fn bitor(self, rhs:Self) -> Self { Self(self.0 | rhs.0) }
+}
+
+impl FromStr for NotifyFlags {
+    type Err = IoError;
+    fn from_str(s:&str) -> IoResult<Self> {
+        let mut flags = Self::default();
+        for c in s.chars() {
+            flags = flags | match c {
+                'K' => Self::KEYSPACE,
+                'E' => Self::KEYEVENT,
+                'A' => Self::ALL,
+                other => match Self::CLASSES.iter().find(|(l, _)| *l == other) {
+                    Some((_, class)) => *class,
+                    None => return Err(IoError::new(ErrorKind::InvalidInput,
+                        format!("invalid keyspace event class '{}'", other))),
+                },
+            };
+        }
+        Ok(flags)
+    }
+}
+
+impl fmt::Display for NotifyFlags {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        let rest = if self.contains(Self::ALL) {
+            out.push('A');
+            Self(self.0 & !Self::ALL.0)
+        } else {
+            *self
+        };
+        for (letter, class) in Self::CLASSES.iter() {
+            if rest.contains(*class) {
+                out.push(*letter);
+            }
+        }
+        if self.contains(Self::KEYSPACE) {
+            out.push('K');
+        }
+        if self.contains(Self::KEYEVENT) {
+            out.push('E');
+        }
+        write!(f, "{}", out)
+    }
+}
+
+// current flags shared by all connections, changed by `CONFIG SET`
+#[derive(Default)]
+pub struct KeyspaceNotifier {
+    flags: AtomicU16,
+}
+
+impl KeyspaceNotifier {
+    pub fn set_flags(&self, flags:NotifyFlags) {
+        self.flags.store(flags.0, Ordering::Relaxed);
+    }
+
+    pub fn flags(&self) -> NotifyFlags {
+        NotifyFlags(self.flags.load(Ordering::Relaxed))
+    }
+
+    // (channel, message) pairs to publish for the event of the `class`,
+    // empty if the class or both kinds of channels are disabled
+    pub fn messages(&self, class:NotifyFlags, event:&str, db:usize, key:&str)
+        -> Vec<(String, Bytes)>
+    {
+        let flags = self.flags();
+        let mut out = Vec::new();
+        if !flags.contains(class) {
+            return out;
+        }
+        if flags.contains(NotifyFlags::KEYSPACE) {
+            out.push((format!("__keyspace@{}__:{}", db, key),
+                      Bytes::from(event.to_string().into_bytes())));
+        }
+        if flags.contains(NotifyFlags::KEYEVENT) {
+            out.push((format!("__keyevent@{}__:{}", db, event),
+                      Bytes::from(key.to_string().into_bytes())));
+        }
+        out
+    }
+} // end of KeyspaceNotifier
@@ -0,0 +1,304 @@
+use std::io::{Cursor, Result as IoResult, Error as IoError, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+use crate::{cmd, Frame, AsyncResult};
+use crate::cmd::Select;
+use crate::cmd::private_part::Command as PrivCommand;
+use crate::db::FakeDatabase;
+use crate::frame;
+
+pub const DEFAULT_AOF_PATH:&str = "appendonly.aof";
+
+// bytes of the dumped keys encoded before written to the new file
+const REWRITE_BUF_SIZE:usize = 64 * 1024;
+
+// when data written to the append-only file is flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    // after every write command, safest and slowest
+    Always,
+    // once per second in background task, at most 1 second of writes lost
+    EverySec,
+    // leave it to the operating system
+    No,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = IoError;
+    fn from_str(s:&str) -> IoResult<Self> {
+        match s.to_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySec),
+            "no" => Ok(Self::No),
+            _others => Err(IoError::new(ErrorKind::InvalidInput,
+                                        format!("invalid fsync policy: {}", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        };
+        label.fmt(f)
+    }
+}
+
+struct AofState {
+    // `None` when append-only file is disabled
+    file: Option<File>,
+    path: PathBuf,
+    policy: FsyncPolicy,
+    // data written since last fsync
+    dirty: bool,
+    // while rewriting the file, commands appended in the meantime are
+    // also kept here, then appended to the new file at the end.
+    rewrite_buf: Option<Vec<u8>>,
+    // database of the latest command appended, `SELECT` is written before
+    // commands on another database. `None` forces it for the next command.
+    selected_db: Option<usize>,
+}
+
+pub struct AppendOnlyFile {
+    state: Mutex<AofState>,
+    rewrite_running: AtomicBool,
+}
+
+impl Default for AppendOnlyFile {
+    fn default() -> Self {
+        let state = AofState{ file: None, path: PathBuf::from(DEFAULT_AOF_PATH),
+            policy: FsyncPolicy::EverySec, dirty: false, rewrite_buf: None,
+            selected_db: None };
+        Self{ state: Mutex::new(state), rewrite_running: AtomicBool::new(false) }
+    }
+}
+
+impl AppendOnlyFile {
+    // open (or create) the file for appending commands, a background task
+    // is spawned for `everysec` policy, it ends once the instance is dropped.
+    pub async fn enable(self: &Arc<Self>, path:impl AsRef<Path>, policy:FsyncPolicy)
+        -> IoResult<()>
+    {
+        let mut state = self.state.lock().await;
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path).await?;
+        let spawn_fsync_task = state.file.is_none();
+        state.file = Some(file);
+        state.path = path;
+        state.policy = policy;
+        // whatever the existing file selected last
+        state.selected_db = None;
+        if spawn_fsync_task {
+            tokio::spawn(everysec_fsync(Arc::downgrade(self)));
+        }
+        Ok(())
+    }
+
+    pub async fn disable(&self) -> IoResult<()> {
+        let mut state = self.state.lock().await;
+        if let Some(mut file) = state.file.take() {
+            file.flush().await?;
+            file.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn is_enabled(&self) -> bool {
+        self.state.lock().await.file.is_some()
+    }
+
+    pub async fn path(&self) -> PathBuf {
+        self.state.lock().await.path.clone()
+    }
+
+    pub async fn set_policy(&self, policy:FsyncPolicy) {
+        self.state.lock().await.policy = policy;
+    }
+
+    pub async fn policy(&self) -> FsyncPolicy {
+        self.state.lock().await.policy
+    }
+
+    // `db` is the database the command was applied to
+    pub async fn append(&self, db:usize, frm:&Frame) -> IoResult<()>
+    {
+        let mut state = self.state.lock().await;
+        let policy = state.policy;
+        let mut buf = Vec::new();
+        if state.selected_db != Some(db) {
+            Select::new(db).into_frame().encode(&mut buf);
+            state.selected_db = Some(db);
+        }
+        frm.encode(&mut buf);
+        if let Some(rb) = state.rewrite_buf.as_mut() {
+            rb.extend_from_slice(&buf);
+        }
+        if let Some(file) = state.file.as_mut() {
+            // hand over the data to the operating system, regardless of the policy
+            file.write_all(&buf).await?;
+            file.flush().await?;
+            if policy == FsyncPolicy::Always {
+                file.sync_data().await?;
+            } else {
+                state.dirty = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn fsync_if_dirty(&self) -> IoResult<()> {
+        let mut state = self.state.lock().await;
+        if !state.dirty || state.policy != FsyncPolicy::EverySec {
+            return Ok(());
+        }
+        if let Some(file) = state.file.as_mut() {
+            file.sync_data().await?;
+        }
+        state.dirty = false;
+        Ok(())
+    }
+
+    // Replay commands in the file at server startup. A record which is cut
+    // off at the end of the file, e.g. the server crashed in the middle of
+    // writing, is discarded and the file is truncated to the last complete
+    // record. Any other malformed record is reported as error.
+    pub async fn load(path:impl AsRef<Path>, db:&FakeDatabase) -> AsyncResult<usize>
+    {
+        let content = match tokio::fs::read(path.as_ref()).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut cursor = Cursor::new(&content[..]);
+        let mut num_replayed = 0;
+        // switched by `SELECT` in the file
+        let mut db = db.select(0);
+        while (cursor.position() as usize) < content.len() {
+            let start = cursor.position();
+            match Frame::check(&mut cursor) {
+                Ok(_) => {},
+                Err(frame::Error::Incomplete) => {
+                    warn!(offset = start, "truncated record in append-only file, discarded");
+                    let file = OpenOptions::new().write(true).open(path.as_ref()).await?;
+                    file.set_len(start).await?;
+                    break;
+                },
+                Err(e) => return Err(e.into()),
+            }
+            cursor.set_position(start);
+            let frm = Frame::parse(&mut cursor)?;
+            let cmdobj = cmd::from_frame(frm)?;
+            cmdobj.replay(&mut db)?;
+            num_replayed += 1;
+        }
+        Ok(num_replayed)
+    } // end of load
+
+    // `BGREWRITEAOF`, write the current state of the store as a minimal
+    // sequence of commands to a new file, which replaces the current file.
+    pub fn bgrewrite(self: &Arc<Self>, db:&FakeDatabase) -> IoResult<()>
+    {
+        let already = self.rewrite_running.swap(true, Ordering::AcqRel);
+        if already {
+            let e = IoError::new(ErrorKind::AlreadyExists,
+                                 "Background append only file rewriting already in progress");
+            return Err(e);
+        }
+        let (aof, db) = (Arc::clone(self), db.clone());
+        tokio::spawn(async move {
+            if let Err(e) = aof.rewrite(&db).await {
+                error!("append-only file rewrite failed, {}", e);
+                aof.state.lock().await.rewrite_buf = None;
+            }
+            aof.rewrite_running.store(false, Ordering::Release);
+        });
+        Ok(())
+    }
+
+    async fn rewrite(&self, db:&FakeDatabase) -> IoResult<()>
+    {
+        // the store is dumped at once, commands applied after the dump are
+        // buffered from the first one, replaying commands like `MOVE` or
+        // `SWAPDB` is only correct on top of the exact state they followed.
+        let (entries, writer) = db.dump_at_log_cut().await?;
+        let path = {
+            let mut state = self.state.lock().await;
+            state.rewrite_buf = Some(Vec::new());
+            // the new file ends with unknown database selected
+            state.selected_db = None;
+            state.path.clone()
+        };
+        drop(writer);
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".rewrite");
+        let mut tmpfile = File::create(&tmp_path).await?;
+        let (mut selected, mut buf) = (0, Vec::new());
+        for item in entries {
+            if item.db != selected {
+                Select::new(item.db).into_frame().encode(&mut buf);
+                selected = item.db;
+            }
+            let mut frm = Frame::array();
+            frm.push_bulk(Bytes::from("set".as_bytes()));
+            frm.push_bulk(Bytes::from(item.key.into_bytes()));
+            frm.push_bulk(Bytes::from(item.value));
+            if let Some(t) = item.expire_at {
+                let ms = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+                frm.push_bulk(Bytes::from("pxat".as_bytes()));
+                frm.push_int(ms as u64);
+            }
+            frm.encode(&mut buf);
+            if buf.len() >= REWRITE_BUF_SIZE {
+                tmpfile.write_all(&buf).await?;
+                buf.clear();
+            }
+        }
+        tmpfile.write_all(&buf).await?;
+        // commands appended during the rewrite are added to the new file, no
+        // more command can be appended until the new file takes place.
+        let mut state = self.state.lock().await;
+        if let Some(rb) = state.rewrite_buf.take() {
+            tmpfile.write_all(&rb).await?;
+        }
+        tmpfile.sync_all().await?;
+        drop(tmpfile);
+        tokio::fs::rename(&tmp_path, &path).await?;
+        if state.file.is_some() {
+            let file = OpenOptions::new().append(true).open(&path).await?;
+            state.file = Some(file);
+            state.dirty = false;
+        }
+        Ok(())
+    } // end of rewrite
+} // end of impl AppendOnlyFile
+
+async fn everysec_fsync(aof:Weak<AppendOnlyFile>)
+{
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let aof = match aof.upgrade() {
+            Some(v) => v,
+            None => break,
+        };
+        if !aof.is_enabled().await {
+            break;
+        }
+        if let Err(e) = aof.fsync_if_dirty().await {
+            error!("append-only file fsync failed, {}", e);
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod snapshot;
+pub use snapshot::{Snapshotter, DEFAULT_SNAPSHOT_PATH};
+
+pub mod aof;
+pub use aof::{AppendOnlyFile, FsyncPolicy, DEFAULT_AOF_PATH};
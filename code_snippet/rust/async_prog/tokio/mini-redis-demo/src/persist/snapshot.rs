@@ -0,0 +1,258 @@
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut};
+use tracing::error;
+
+use crate::db::{FakeDatabase, DumpedEntry};
+
+pub const DEFAULT_SNAPSHOT_PATH:&str = "dump.rdb";
+
+// Snapshot file layout, all integers are little-endian :
+//
+// ```text
+// "MINIRDB" version:u8
+// { [OP_SELECTDB index:u32] [OP_EXPIRE_MS unix-time-ms:u64] value-type:u8 key value }*
+// OP_EOF
+// checksum:u32
+// ```
+//
+// where `key` and `value` are `length:u32` followed by raw bytes, the
+// checksum is CRC32 of all preceding bytes in the file. Entries belong to
+// database 0 until the first `OP_SELECTDB`, version 1 files have no
+// `OP_SELECTDB` at all.
+const MAGIC:&[u8] = b"MINIRDB";
+const VERSION:u8 = 2;
+const OP_SELECTDB:u8 = 0xfe;
+const OP_EXPIRE_MS:u8 = 0xfc;
+const OP_EOF:u8 = 0xff;
+const TYPE_STRING:u8 = 0;
+
+pub struct Snapshotter {
+    path: Mutex<PathBuf>,
+    bgsave_running: AtomicBool,
+    // unix time in seconds of last successful save
+    last_save: AtomicU64,
+}
+
+impl Default for Snapshotter {
+    fn default() -> Self {
+        Self{ path: Mutex::new(PathBuf::from(DEFAULT_SNAPSHOT_PATH)),
+              bgsave_running: AtomicBool::new(false),
+              last_save: AtomicU64::new(0) }
+    }
+}
+
+impl Snapshotter {
+    pub fn path(&self) -> PathBuf {
+        self.path.lock().map(|p| p.clone())
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_SNAPSHOT_PATH))
+    }
+    pub fn set_path(&self, p:impl AsRef<Path>) {
+        if let Ok(mut path) = self.path.lock() {
+            *path = p.as_ref().to_path_buf();
+        }
+    }
+    pub fn last_save(&self) -> u64 { self.last_save.load(Ordering::Relaxed) }
+    pub fn is_bgsave_running(&self) -> bool { self.bgsave_running.load(Ordering::Relaxed) }
+
+    // dump the whole store while holding the db lock, so the snapshot is
+    // consistent at a single point in time, this is what `SAVE` does.
+    pub async fn save(&self, db:&FakeDatabase) -> IoResult<usize>
+    {
+        let entries = db.dump()?;
+        let num_saved = entries.len();
+        let mut wr = SnapshotWriter::new();
+        wr.append(entries);
+        self.write_file(wr).await?;
+        Ok(num_saved)
+    }
+
+    // `BGSAVE` dumps the store as `SAVE` does, then the file is written in
+    // a separate task so other connections can still access the store in
+    // the meantime.
+    pub fn bgsave(&self, db:&FakeDatabase) -> IoResult<()>
+    {
+        let already = self.bgsave_running.swap(true, Ordering::AcqRel);
+        if already {
+            let e = IoError::new(ErrorKind::AlreadyExists,
+                                 "Background save already in progress");
+            return Err(e);
+        }
+        let entries = match db.dump() {
+            Ok(v) => v,
+            Err(e) => {
+                self.bgsave_running.store(false, Ordering::Release);
+                return Err(e);
+            },
+        };
+        let db = db.clone();
+        tokio::spawn(async move {
+            let snapshotter = db.snapshotter();
+            let mut wr = SnapshotWriter::new();
+            wr.append(entries);
+            if let Err(e) = snapshotter.write_file(wr).await {
+                error!("background save failed, {}", e);
+            }
+            snapshotter.bgsave_running.store(false, Ordering::Release);
+        });
+        Ok(())
+    }
+
+    // the content is written to temporary file first, then renamed, a
+    // crash in the middle of writing never corrupts the previous snapshot.
+    async fn write_file(&self, wr:SnapshotWriter) -> IoResult<()>
+    {
+        let path = self.path();
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, wr.finish()).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .unwrap_or_default().as_secs();
+        self.last_save.store(now, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // load the snapshot at server startup, it is fine if the file does not
+    // exist yet, however a corrupted file is reported as error.
+    pub async fn load(&self, db:&FakeDatabase) -> IoResult<usize>
+    {
+        let content = match tokio::fs::read(self.path()).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let entries = decode(&content)?;
+        db.restore(entries)
+    }
+} // end of impl Snapshotter
+
+struct SnapshotWriter {
+    buf: Vec<u8>,
+    // database of the latest entry written
+    db: usize,
+}
+
+impl SnapshotWriter {
+    fn new() -> Self {
+        let mut buf = Vec::with_capacity(1usize << 12);
+        buf.put_slice(MAGIC);
+        buf.put_u8(VERSION);
+        Self{buf, db: 0}
+    }
+    fn append(&mut self, entries:Vec<DumpedEntry>) {
+        for item in entries {
+            if item.db != self.db {
+                self.buf.put_u8(OP_SELECTDB);
+                self.buf.put_u32_le(item.db as u32);
+                self.db = item.db;
+            }
+            if let Some(t) = item.expire_at {
+                let ms = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+                self.buf.put_u8(OP_EXPIRE_MS);
+                self.buf.put_u64_le(ms as u64);
+            }
+            self.buf.put_u8(TYPE_STRING);
+            put_blob(&mut self.buf, item.key.as_bytes());
+            put_blob(&mut self.buf, &item.value);
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        self.buf.put_u8(OP_EOF);
+        let checksum = crc32fast::hash(&self.buf);
+        self.buf.put_u32_le(checksum);
+        self.buf
+    }
+}
+
+// whole snapshot in memory, e.g. sent to replicas on full synchronization
+pub(crate) fn encode(entries:Vec<DumpedEntry>) -> Vec<u8> {
+    let mut wr = SnapshotWriter::new();
+    wr.append(entries);
+    wr.finish()
+}
+
+fn put_blob(buf:&mut Vec<u8>, data:&[u8]) {
+    buf.put_u32_le(data.len() as u32);
+    buf.put_slice(data);
+}
+
+fn corrupted(detail:&str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, format!("corrupted snapshot, {}", detail))
+}
+
+pub(crate) fn decode(content:&[u8]) -> IoResult<Vec<DumpedEntry>>
+{
+    let header_sz = MAGIC.len() + 1;
+    if content.len() < header_sz + 1 + 4 {
+        return Err(corrupted("file too short"));
+    }
+    let (body, checksum) = content.split_at(content.len() - 4);
+    let expect = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if crc32fast::hash(body) != expect {
+        return Err(corrupted("checksum mismatch"));
+    }
+    if &body[..MAGIC.len()] != MAGIC {
+        return Err(corrupted("invalid magic"));
+    }
+    if !(1 ..= VERSION).contains(&body[MAGIC.len()]) {
+        return Err(corrupted("unsupported version"));
+    }
+    let mut src = &body[header_sz..];
+    let mut out = Vec::new();
+    let (mut db, mut expire_at) = (0, None);
+    loop {
+        match get_u8(&mut src)? {
+            OP_EOF => break,
+            OP_SELECTDB => {
+                if src.remaining() < 4 {
+                    return Err(corrupted("truncated database index"));
+                }
+                db = src.get_u32_le() as usize;
+            },
+            OP_EXPIRE_MS => {
+                if src.remaining() < 8 {
+                    return Err(corrupted("truncated expiry"));
+                }
+                let ms = src.get_u64_le();
+                expire_at = Some(UNIX_EPOCH + Duration::from_millis(ms));
+            },
+            TYPE_STRING => {
+                let key = String::from_utf8(get_blob(&mut src)?)
+                    .map_err(|_| corrupted("key is not valid UTF-8"))?;
+                let value = get_blob(&mut src)?;
+                out.push(DumpedEntry{db, key, value, expire_at: expire_at.take()});
+            },
+            other => return Err(corrupted(&format!("unknown opcode {}", other))),
+        }
+    }
+    if src.has_remaining() {
+        return Err(corrupted("unexpected data after end of file marker"));
+    }
+    Ok(out)
+} // end of decode
+
+fn get_u8(src:&mut &[u8]) -> IoResult<u8> {
+    if src.has_remaining() {
+        Ok(src.get_u8())
+    } else {
+        Err(corrupted("missing end of file marker"))
+    }
+}
+
+fn get_blob(src:&mut &[u8]) -> IoResult<Vec<u8>> {
+    if src.remaining() < 4 {
+        return Err(corrupted("truncated length"));
+    }
+    let len = src.get_u32_le() as usize;
+    if src.remaining() < len {
+        return Err(corrupted("truncated data"));
+    }
+    let data = src[..len].to_vec();
+    src.advance(len);
+    Ok(data)
+}
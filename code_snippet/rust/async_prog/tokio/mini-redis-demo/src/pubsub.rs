@@ -0,0 +1,298 @@
+use std::collections::{HashMap, hash_map};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use bytes::Bytes;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::config::glob_match;
+
+pub const DEFAULT_NUM_SHARDS:usize = 16;
+
+// Redis keeps channels of `SUBSCRIBE` / `PUBLISH` and shard channels of
+// `SSUBSCRIBE` / `SPUBLISH` in separate namespaces, the same name can be
+// used in both kinds without interfering each other.
+// Patterns of `PSUBSCRIBE` form one more namespace, they match names of
+// global channels only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    Global,
+    Sharded,
+    Pattern,
+}
+
+impl ChannelKind {
+    // prefix of command names and reply frames, e.g. `subscribe`,
+    // `ssubscribe` and `psubscribe`, `message`, `smessage` and `pmessage`
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Self::Global => "",
+            Self::Sharded => "s",
+            Self::Pattern => "p",
+        }
+    }
+}
+
+type ChannelRegistry = HashMap<(ChannelKind, String), broadcast::Sender<Bytes>>;
+
+// name of the channel the message was published to, and the message
+pub type PatternMessage = (String, Bytes);
+
+// all patterns in one registry, every message published to a global
+// channel is matched against each of them
+type PatternRegistry = HashMap<String, broadcast::Sender<PatternMessage>>;
+
+struct FanoutRequest {
+    kind: ChannelKind,
+    channel: String,
+    message: Bytes,
+    // number of subscribers which will receive the message
+    resp: oneshot::Sender<IoResult<usize>>,
+}
+
+struct Shard {
+    // each shard has its own lock, subscribing or publishing a channel
+    // never blocks channels in other shards, nor the keyspace.
+    registry: Arc<Mutex<ChannelRegistry>>,
+    // the fan-out task is spawned on the first publish, see `fanout()`
+    fanout: OnceLock<mpsc::Sender<FanoutRequest>>,
+}
+
+// The broker runs one fan-out task per shard, the publisher only forwards
+// its message to the task owning the channel, then waits for the number of
+// subscribers asynchronously.
+pub struct PubSubBroker {
+    shards: Arc<Vec<Shard>>,
+    patterns: Arc<Mutex<PatternRegistry>>,
+    chn_capacity: usize,
+}
+
+impl Clone for PubSubBroker {
+    fn clone(&self) -> Self {
+        Self{ shards: Arc::clone(&self.shards), patterns: Arc::clone(&self.patterns),
+              chn_capacity: self.chn_capacity }
+    }
+}
+
+impl PubSubBroker {
+    // The fan-out tasks are spawned lazily by `publish()`, so the broker can
+    // be created outside Tokio runtime. The tasks end once all clones of
+    // the broker are dropped.
+    pub fn new(num_shards:usize, chn_capacity:usize) -> Self {
+        let shards = (0 .. num_shards.max(1)).map(|_| {
+            Shard{registry: Arc::new(Mutex::new(HashMap::new())), fanout: OnceLock::new()}
+        }).collect();
+        // `broadcast::channel()` panics on zero capacity
+        let chn_capacity = chn_capacity.max(1);
+        Self{ shards: Arc::new(shards), patterns: Arc::new(Mutex::new(HashMap::new())),
+              chn_capacity }
+    }
+
+    // sender to the fan-out task of the shard, called only within Tokio
+    // runtime
+    fn fanout(&self, shard:&Shard) -> mpsc::Sender<FanoutRequest> {
+        shard.fanout.get_or_init(|| {
+            let (tx, rx) = mpsc::channel(128);
+            tokio::spawn(fanout_task(Arc::clone(&shard.registry),
+                                     Arc::clone(&self.patterns), rx));
+            tx
+        }).clone()
+    }
+
+    fn shard(&self, chn:&str) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        chn.hash(&mut hasher);
+        let idx = (hasher.finish() as usize) % self.shards.len();
+        &self.shards[idx]
+    }
+
+    pub fn subscribe(&self, kind:ChannelKind, chn:String)
+        -> IoResult<broadcast::Receiver<Bytes>>
+    {
+        if kind == ChannelKind::Pattern {
+            let e = IoError::new(ErrorKind::InvalidInput, "subscribe patterns by psubscribe()");
+            return Err(e);
+        }
+        let shard = self.shard(chn.as_str());
+        if let Ok(mut registry) = shard.registry.lock() {
+            let recver = match registry.entry((kind, chn)) {
+                hash_map::Entry::Occupied(e) => e.get().subscribe(),
+                hash_map::Entry::Vacant(e) => {
+                    let (_sender, _recver) = broadcast::channel(self.chn_capacity);
+                    e.insert(_sender);
+                    _recver
+                },
+            };
+            Ok(recver)
+        } else {
+            let e = IoError::new( ErrorKind::ResourceBusy,
+                                  "failed to acquire pubsub lock");
+            Err(e)
+        }
+    }
+
+    pub fn psubscribe(&self, pattern:String)
+        -> IoResult<broadcast::Receiver<PatternMessage>>
+    {
+        let mut patterns = self.patterns.lock().map_err(|_| IoError::new(
+            ErrorKind::ResourceBusy, "failed to acquire pubsub lock"))?;
+        let recver = match patterns.entry(pattern) {
+            hash_map::Entry::Occupied(e) => e.get().subscribe(),
+            hash_map::Entry::Vacant(e) => {
+                let (_sender, _recver) = broadcast::channel(self.chn_capacity);
+                e.insert(_sender);
+                _recver
+            },
+        };
+        Ok(recver)
+    }
+
+    // forget the channel or pattern once its last subscriber left, called
+    // after the subscriber dropped its receiver
+    pub fn unsubscribe(&self, kind:ChannelKind, chn:&str) {
+        if kind == ChannelKind::Pattern {
+            if let Ok(mut patterns) = self.patterns.lock() {
+                if patterns.get(chn).is_some_and(|s| s.receiver_count() == 0) {
+                    patterns.remove(chn);
+                }
+            }
+        } else if let Ok(mut registry) = self.shard(chn).registry.lock() {
+            let key = (kind, chn.to_string());
+            if registry.get(&key).is_some_and(|s| s.receiver_count() == 0) {
+                registry.remove(&key);
+            }
+        }
+    }
+
+    pub async fn publish(&self, kind:ChannelKind, chn:&str, msg:&Bytes) -> IoResult<usize>
+    {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = FanoutRequest{ kind, channel: chn.to_string(),
+                                 message: msg.clone(), resp: resp_tx };
+        let fanout = self.fanout(self.shard(chn));
+        if fanout.send(req).await.is_err() {
+            let e = IoError::new(ErrorKind::BrokenPipe, "fan-out task terminated");
+            return Err(e);
+        }
+        resp_rx.await.unwrap_or_else(|_| {
+            Err(IoError::new(ErrorKind::BrokenPipe, "fan-out task terminated"))
+        })
+    }
+
+    // publish in the caller instead of the fan-out task, for messages the
+    // server generates where it cannot wait, e.g. keyspace notifications.
+    // Returns number of subscribers which will receive the message.
+    pub fn publish_now(&self, kind:ChannelKind, chn:&str, msg:Bytes) -> usize
+    {
+        let shard = self.shard(chn);
+        let num_recvs = shard.registry.lock().ok()
+            .and_then(|registry| registry.get(&(kind, chn.to_string()))
+                      .map(|sender| sender.send(msg.clone()).unwrap_or(0)))
+            .unwrap_or(0);
+        num_recvs + publish_to_patterns(&self.patterns, kind, chn, &msg)
+    }
+
+    // number of channels which still have subscribers
+    pub fn num_channels(&self, kind:ChannelKind) -> usize {
+        if kind == ChannelKind::Pattern {
+            return self.patterns.lock().map(|patterns| {
+                patterns.values().filter(|s| s.receiver_count() > 0).count()
+            }).unwrap_or(0);
+        }
+        self.shards.iter().map(|shard| {
+            // a subscriber which failed to unsubscribe, e.g. its connection
+            // was lost, leaves the entry until the next publish
+            shard.registry.lock().map(|registry| {
+                registry.iter().filter(|((k, _), s)| *k == kind && s.receiver_count() > 0)
+                    .count()
+            }).unwrap_or(0)
+        }).sum()
+    }
+} // end of PubSubBroker
+
+// Send the message of a global channel to subscribers of the matching
+// patterns, returns number of them. Patterns nobody subscribes any more
+// are dropped along the way.
+fn publish_to_patterns(patterns:&Mutex<PatternRegistry>, kind:ChannelKind,
+                       chn:&str, msg:&Bytes) -> usize
+{
+    if kind != ChannelKind::Global {
+        return 0;
+    }
+    let mut patterns = match patterns.lock() {
+        Ok(p) => p,
+        Err(_) => return 0,
+    };
+    patterns.retain(|_, sender| sender.receiver_count() > 0);
+    patterns.iter()
+        .filter(|(p, _)| glob_match(p.as_bytes(), chn.as_bytes()))
+        .map(|(_, sender)| sender.send((chn.to_string(), msg.clone())).unwrap_or(0))
+        .sum()
+}
+
+async fn fanout_task(registry:Arc<Mutex<ChannelRegistry>>,
+                     patterns:Arc<Mutex<PatternRegistry>>,
+                     mut rx:mpsc::Receiver<FanoutRequest>)
+{
+    while let Some(req) = rx.recv().await {
+        let result = if let Ok(mut registry) = registry.lock() {
+            let key = (req.kind, req.channel.clone());
+            match registry.get(&key) {
+                Some(sender) if sender.receiver_count() > 0 => {
+                    Ok(sender.send(req.message.clone()).unwrap_or(0))
+                },
+                Some(_) => { // all subscribers left, forget the channel
+                    registry.remove(&key);
+                    Err(IoError::new(ErrorKind::NotFound, "channel not exists"))
+                },
+                None => Err(IoError::new(ErrorKind::NotFound, "channel not exists")),
+            }
+        } else {
+            Err(IoError::new(ErrorKind::ResourceBusy, "failed to acquire pubsub lock"))
+        };
+        // the channel may have no subscriber while some patterns match it
+        let num_matched = publish_to_patterns(&patterns, req.kind, &req.channel, &req.message);
+        let result = match result {
+            Ok(n) => Ok(n + num_matched),
+            Err(e) if e.kind() == ErrorKind::NotFound && num_matched > 0 => Ok(num_matched),
+            Err(e) => Err(e),
+        };
+        let _ = req.resp.send(result);
+    }
+} // end of fanout_task
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_outside_runtime() {
+        let _db = crate::db::FakeDatabase::new();
+        let broker = PubSubBroker::new(DEFAULT_NUM_SHARDS, 16);
+        let _rx = broker.subscribe(ChannelKind::Global, "news".to_string()).unwrap();
+        assert_eq!(broker.num_channels(ChannelKind::Global), 1);
+    }
+
+    #[tokio::test]
+    async fn forget_channel_after_last_unsubscribe() {
+        let broker = PubSubBroker::new(DEFAULT_NUM_SHARDS, 16);
+        let rx1 = broker.subscribe(ChannelKind::Global, "news".to_string()).unwrap();
+        let rx2 = broker.subscribe(ChannelKind::Global, "news".to_string()).unwrap();
+        let prx = broker.psubscribe("n*".to_string()).unwrap();
+        let num_recvs = broker.publish(ChannelKind::Global, "news", &Bytes::from("hi")).await;
+        assert_eq!(num_recvs.unwrap(), 3);
+        drop(rx1);
+        broker.unsubscribe(ChannelKind::Global, "news");
+        assert_eq!(broker.num_channels(ChannelKind::Global), 1);
+        drop(rx2);
+        broker.unsubscribe(ChannelKind::Global, "news");
+        assert_eq!(broker.num_channels(ChannelKind::Global), 0);
+        assert!(broker.shard("news").registry.lock().unwrap().is_empty());
+        drop(prx);
+        assert_eq!(broker.num_channels(ChannelKind::Pattern), 0);
+        broker.unsubscribe(ChannelKind::Pattern, "n*");
+        assert!(broker.patterns.lock().unwrap().is_empty());
+    }
+}
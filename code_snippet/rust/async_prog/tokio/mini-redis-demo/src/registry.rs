@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{watch, Notify};
+
+// which commands are held by `CLIENT PAUSE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    // only commands modifying the store, e.g. during failover
+    Write,
+    All,
+}
+
+impl FromStr for PauseMode {
+    type Err = IoError;
+    fn from_str(s:&str) -> IoResult<Self> {
+        match s.to_lowercase().as_str() {
+            "write" => Ok(Self::Write),
+            "all" => Ok(Self::All),
+            _others => Err(IoError::new(ErrorKind::InvalidInput,
+                                        format!("invalid pause mode '{}'", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pause {
+    until: Instant,
+    mode: PauseMode,
+}
+
+// what `CLIENT LIST` reports for a connection, updated by the connection
+// itself before each command
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub id: u64,
+    // `ip:port`, or `unix` for Unix domain socket
+    pub addr: String,
+    // set by `CLIENT SETNAME`, empty by default
+    pub name: String,
+    pub user: String,
+    pub connected_at: Instant,
+    pub last_active: Instant,
+    // the latest command, still running if the client is streaming
+    pub cmd: String,
+    // number of subscribed channels, shard channels and patterns
+    pub sub: usize,
+    pub ssub: usize,
+    pub psub: usize,
+    pub monitor: bool,
+    // set by `SELECT`
+    pub db: usize,
+    // bytes received but not parsed yet, and free space of read buffer
+    pub qbuf: usize,
+    pub qbuf_free: usize,
+    // bytes of replies not flushed yet
+    pub obl: usize,
+}
+
+impl ClientInfo {
+    // one line of `CLIENT LIST`, fields have the same names as Redis
+    pub fn describe(&self) -> String {
+        let flags = if self.monitor {
+            "O"
+        } else if self.sub + self.ssub + self.psub > 0 {
+            "P"
+        } else {
+            "N"
+        };
+        format!("id={} addr={} name={} age={} idle={} flags={} db={} sub={} psub={} ssub={} \
+                 qbuf={} qbuf-free={} obl={} user={} cmd={}",
+                self.id, self.addr, self.name, self.connected_at.elapsed().as_secs(),
+                self.last_active.elapsed().as_secs(), flags, self.db, self.sub, self.psub, self.ssub,
+                self.qbuf, self.qbuf_free, self.obl, self.user, self.cmd)
+    }
+}
+
+// conditions of `CLIENT KILL`, clients matching all of them are closed
+#[derive(Debug, Clone, Default)]
+pub struct KillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    // the client running the command, `None` if it can be killed as well
+    pub skip: Option<u64>,
+}
+
+impl KillFilter {
+    fn matches(&self, info:&ClientInfo) -> bool {
+        self.id.map(|id| id == info.id).unwrap_or(true)
+            && self.addr.as_ref().map(|a| *a == info.addr).unwrap_or(true)
+            && self.skip.map(|id| id != info.id).unwrap_or(true)
+    }
+}
+
+struct ClientEntry {
+    info: ClientInfo,
+    // wakes the connection up to close itself, see `SingleRequestShutdown`
+    kill: Arc<Notify>,
+}
+
+// all connected clients, each of them is added by the task serving the
+// connection and removed once the task ends
+pub struct ClientRegistry {
+    clients: Mutex<BTreeMap<u64, ClientEntry>>,
+    pause: watch::Sender<Option<Pause>>,
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        let (pause, _) = watch::channel(None);
+        Self{ clients: Mutex::new(BTreeMap::new()), pause }
+    }
+}
+
+impl ClientRegistry {
+    fn lock(&self) -> IoResult<MutexGuard<'_, BTreeMap<u64, ClientEntry>>> {
+        self.clients.lock().map_err(|_| {
+            IoError::new(ErrorKind::ResourceBusy, "failed to acquire client registry lock")
+        })
+    }
+
+    // the client is removed when the returned value is dropped
+    pub fn register(self:&Arc<Self>, id:u64, addr:String, user:String)
+        -> IoResult<ClientRegistration>
+    {
+        let now = Instant::now();
+        let info = ClientInfo{ id, addr, name: String::new(), user, connected_at: now,
+            last_active: now, cmd: "NULL".to_string(), sub: 0, ssub: 0, psub: 0, monitor: false, db: 0,
+            qbuf: 0, qbuf_free: 0, obl: 0 };
+        let kill = Arc::new(Notify::new());
+        self.lock()?.insert(id, ClientEntry{ info, kill: Arc::clone(&kill) });
+        Ok(ClientRegistration{ id, registry: Arc::clone(self), kill })
+    }
+
+    // change the state of a client, nothing happens if it is already gone
+    pub fn update<F>(&self, id:u64, f:F) where F: FnOnce(&mut ClientInfo) {
+        if let Ok(mut clients) = self.lock() {
+            if let Some(entry) = clients.get_mut(&id) {
+                f(&mut entry.info);
+            }
+        }
+    }
+
+    // `CLIENT LIST`, in order of id, `ids` selects part of them
+    pub fn list(&self, ids:Option<&[u64]>) -> IoResult<Vec<ClientInfo>> {
+        let clients = self.lock()?;
+        let out = clients.values()
+            .filter(|e| ids.map(|ids| ids.contains(&e.info.id)).unwrap_or(true))
+            .map(|e| e.info.clone()).collect();
+        Ok(out)
+    }
+
+    // `CLIENT KILL`, returns number of clients asked to close, they are
+    // closed once they finish current command
+    pub fn kill(&self, filter:&KillFilter) -> IoResult<usize> {
+        let clients = self.lock()?;
+        let killed = clients.values().filter(|e| filter.matches(&e.info))
+            .map(|e| e.kill.notify_one()).count();
+        Ok(killed)
+    }
+
+    // `CLIENT PAUSE`, a new pause replaces the current one
+    pub fn pause(&self, timeout:Duration, mode:PauseMode) {
+        let until = Instant::now() + timeout;
+        self.pause.send_replace(Some(Pause{ until, mode }));
+    }
+
+    pub fn unpause(&self) {
+        self.pause.send_replace(None);
+    }
+
+    // cheap check for every command before waiting on `wait_unpaused()`
+    pub fn is_paused(&self) -> bool {
+        self.pause.borrow().map(|p| p.until > Instant::now()).unwrap_or(false)
+    }
+
+    // resolves once a command of the kind is allowed to run, i.e. the
+    // pause expires or `CLIENT UNPAUSE` is received
+    pub async fn wait_unpaused(&self, is_write:bool) {
+        let mut rx = self.pause.subscribe();
+        loop {
+            let pause = *rx.borrow_and_update();
+            let until = match pause {
+                Some(p) if p.mode == PauseMode::All || is_write => p.until,
+                _others => return,
+            };
+            if until <= Instant::now() {
+                return;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep_until(until.into()) => return,
+                // pause changed or cancelled, check again
+                _ = rx.changed() => {},
+            }
+        }
+    }
+} // end of ClientRegistry
+
+pub struct ClientRegistration {
+    id: u64,
+    registry: Arc<ClientRegistry>,
+    kill: Arc<Notify>,
+}
+
+impl ClientRegistration {
+    // given to the connection's `SingleRequestShutdown`
+    pub fn kill_switch(&self) -> Arc<Notify> {
+        Arc::clone(&self.kill)
+    }
+}
+
+impl Drop for ClientRegistration {
+    fn drop(&mut self) {
+        if let Ok(mut clients) = self.registry.lock() {
+            clients.remove(&self.id);
+        }
+    }
+}
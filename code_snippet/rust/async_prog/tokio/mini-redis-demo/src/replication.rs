@@ -0,0 +1,463 @@
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{cmd, Connection, Frame, AsyncResult, DEFAULT_PORT};
+use crate::cmd::Select;
+use crate::cmd::private_part::Command as PrivCommand;
+use crate::db::FakeDatabase;
+use crate::persist::snapshot;
+
+// number of bytes of the latest write commands kept for partial
+// synchronization, a replica which falls behind further than this has to
+// run full synchronization again.
+pub const DEFAULT_BACKLOG_SIZE:usize = 1usize << 20;
+
+// chunks of command stream buffered for the slowest replica, the replica
+// is disconnected once it lags behind, then it resumes from the backlog.
+const FEED_CHANNEL_CAPACITY:usize = 1024;
+
+const ACK_INTERVAL:Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY:Duration = Duration::from_secs(5);
+
+// state of the link from a replica to its primary, reported by `ROLE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Connect,
+    Connecting,
+    // receiving the snapshot
+    Sync,
+    Connected,
+}
+
+impl LinkState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Connecting => "connecting",
+            Self::Sync => "sync",
+            Self::Connected => "connected",
+        }
+    }
+}
+
+// snapshot of replication state, see `Replication::role()`
+#[derive(Debug, Clone)]
+pub enum RoleInfo {
+    Primary {
+        offset: u64,
+        // address, listening port and acknowledged offset of each replica
+        replicas: Vec<(String, u16, u64)>,
+    },
+    Replica {
+        host: String,
+        port: u16,
+        state: LinkState,
+        offset: u64,
+    },
+}
+
+// what the primary sends to a replica right after `PSYNC`
+pub(crate) enum SyncPlan {
+    // the replica resumes from the backlog
+    Continue { replid: String, backlog: Vec<u8> },
+    // the replica discards its data and loads the snapshot
+    Full { replid: String, offset: u64, snapshot: Vec<u8> },
+}
+
+// circular buffer of the latest bytes in the command stream
+struct Backlog {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    // replication offset of the first byte in `buf`
+    start: u64,
+}
+
+impl Backlog {
+    fn new(capacity:usize, start:u64) -> Self {
+        Self{ buf: VecDeque::new(), capacity, start }
+    }
+    fn append(&mut self, data:&[u8]) {
+        self.buf.extend(data);
+        if self.buf.len() > self.capacity {
+            let excess = self.buf.len() - self.capacity;
+            self.buf.drain(.. excess);
+            self.start += excess as u64;
+        }
+    }
+    // bytes from `offset` to the end, `None` if they are no longer kept
+    fn since(&self, offset:u64) -> Option<Vec<u8>> {
+        let end = self.start + self.buf.len() as u64;
+        if offset < self.start || offset > end {
+            return None
+        }
+        let skip = (offset - self.start) as usize;
+        Some(self.buf.range(skip ..).copied().collect())
+    }
+    fn reset(&mut self, start:u64) {
+        self.buf.clear();
+        self.start = start;
+    }
+}
+
+struct PrimaryLink {
+    host: String,
+    port: u16,
+    state: LinkState,
+    task: JoinHandle<()>,
+}
+
+struct ReplicaInfo {
+    addr: Option<SocketAddr>,
+    listening_port: Option<u16>,
+    ack_offset: u64,
+}
+
+struct ReplState {
+    // identifies the history of the data set, together with `offset` a
+    // replica can tell whether it can resume from where it stopped.
+    replid: String,
+    // number of bytes in the command stream since the history began
+    offset: u64,
+    backlog: Backlog,
+    // `None` when the server is a primary
+    primary: Option<PrimaryLink>,
+    replicas: HashMap<u64, ReplicaInfo>,
+    next_replica_id: u64,
+    // port this server is listening on, sent to the primary
+    listening_port: u16,
+    // database of the latest command fed, `None` forces `SELECT` before
+    // the next command
+    selected_db: Option<usize>,
+}
+
+// Every write command applied on the primary is encoded once, appended to
+// the backlog and forwarded to all connected replicas, see `feed()`. The
+// replica applies the same stream, so its offset tells how far it is.
+pub struct Replication {
+    state: Mutex<ReplState>,
+    feed: broadcast::Sender<Bytes>,
+}
+
+impl Default for Replication {
+    fn default() -> Self { Self::new(DEFAULT_BACKLOG_SIZE) }
+}
+
+impl Replication {
+    pub fn new(backlog_size:usize) -> Self {
+        let state = ReplState{ replid: new_replid(), offset: 0,
+            backlog: Backlog::new(backlog_size.max(1), 0), primary: None,
+            replicas: HashMap::new(), next_replica_id: 0,
+            listening_port: DEFAULT_PORT, selected_db: None };
+        let (feed, _) = broadcast::channel(FEED_CHANNEL_CAPACITY);
+        Self{ state: Mutex::new(state), feed }
+    }
+
+    fn lock(&self) -> IoResult<MutexGuard<'_, ReplState>> {
+        self.state.lock().map_err(|_| {
+            IoError::new(ErrorKind::ResourceBusy, "failed to acquire replication lock")
+        })
+    }
+
+    pub fn set_listening_port(&self, port:u16) {
+        if let Ok(mut state) = self.lock() {
+            state.listening_port = port;
+        }
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.lock().map(|s| s.primary.is_some()).unwrap_or(false)
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.lock().map(|s| s.offset).unwrap_or(0)
+    }
+
+    pub fn role(&self) -> IoResult<RoleInfo> {
+        let state = self.lock()?;
+        let info = match &state.primary {
+            Some(link) => RoleInfo::Replica{ host: link.host.clone(), port: link.port,
+                                             state: link.state, offset: state.offset },
+            None => {
+                let replicas = state.replicas.values().map(|r| {
+                    let ip = r.addr.map(|a| a.ip().to_string()).unwrap_or_default();
+                    (ip, r.listening_port.unwrap_or(0), r.ack_offset)
+                }).collect();
+                RoleInfo::Primary{ offset: state.offset, replicas }
+            },
+        };
+        Ok(info)
+    }
+
+    // append a write command to the stream, the command has to be applied
+    // to the store already, on the database `db`.
+    pub(crate) fn feed(&self, db:usize, frm:&Frame) -> IoResult<()> {
+        let mut buf = Vec::new();
+        let mut state = self.lock()?;
+        if state.selected_db != Some(db) {
+            Select::new(db).into_frame().encode(&mut buf);
+            state.selected_db = Some(db);
+        }
+        frm.encode(&mut buf);
+        state.backlog.append(&buf);
+        state.offset += buf.len() as u64;
+        // sent while holding the lock, so the stream of each receiver
+        // starts exactly at the offset recorded in `prepare_sync()`
+        let _ = self.feed.send(Bytes::from(buf));
+        Ok(())
+    }
+
+    // a replica counts the bytes of the stream applied from its primary,
+    // which are the same as those counted by the primary. It serves no
+    // replica of its own, so nothing is kept for them.
+    pub(crate) fn advance(&self, frm:&Frame) -> IoResult<()> {
+        let mut buf = Vec::new();
+        frm.encode(&mut buf);
+        self.lock()?.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    // `PSYNC`, the replica reports the history id and offset it has, the
+    // replica is able to continue if it is still in the backlog.
+    pub(crate) fn prepare_sync(&self, replid:&str, offset:Option<u64>, db:&FakeDatabase)
+        -> IoResult<(SyncPlan, broadcast::Receiver<Bytes>)>
+    {
+        let mut state = self.lock()?;
+        let rx = self.feed.subscribe();
+        // the replica starts on database 0 after loading the snapshot
+        state.selected_db = None;
+        let backlog = match offset {
+            Some(off) if replid == state.replid => state.backlog.since(off),
+            _others => None,
+        };
+        let plan = match backlog {
+            Some(backlog) => SyncPlan::Continue{ replid: state.replid.clone(), backlog },
+            None => {
+                let snapshot = snapshot::encode(db.dump()?);
+                SyncPlan::Full{ replid: state.replid.clone(), offset: state.offset, snapshot }
+            },
+        };
+        Ok((plan, rx))
+    }
+
+    pub(crate) fn register_replica(&self, addr:Option<SocketAddr>,
+                                   listening_port:Option<u16>) -> IoResult<u64>
+    {
+        let mut state = self.lock()?;
+        let id = state.next_replica_id;
+        state.next_replica_id += 1;
+        state.replicas.insert(id, ReplicaInfo{ addr, listening_port, ack_offset: 0 });
+        Ok(id)
+    }
+
+    pub(crate) fn update_ack(&self, id:u64, offset:u64) {
+        if let Ok(mut state) = self.lock() {
+            if let Some(r) = state.replicas.get_mut(&id) {
+                r.ack_offset = offset;
+            }
+        }
+    }
+
+    pub(crate) fn unregister_replica(&self, id:u64) {
+        if let Ok(mut state) = self.lock() {
+            state.replicas.remove(&id);
+        }
+    }
+
+    // `REPLICAOF host port`, the link runs in background task and keeps
+    // reconnecting until the server is promoted or follows another primary.
+    // Returns `false` if it is already following the same primary.
+    pub fn replicate_of(&self, host:&str, port:u16, db:&FakeDatabase) -> IoResult<bool>
+    {
+        let mut state = self.lock()?;
+        if let Some(link) = &state.primary {
+            if link.host == host && link.port == port {
+                return Ok(false)
+            }
+        }
+        if let Some(link) = state.primary.take() {
+            link.task.abort();
+        }
+        let task = tokio::spawn(primary_link_task(db.clone(), host.to_string(), port));
+        state.primary = Some(PrimaryLink{ host: host.to_string(), port,
+                                          state: LinkState::Connect, task });
+        Ok(true)
+    }
+
+    // `REPLICAOF NO ONE`, the data set is kept and starts new history,
+    // replicas of this server will run full synchronization.
+    pub fn promote(&self) -> IoResult<()>
+    {
+        let mut state = self.lock()?;
+        if let Some(link) = state.primary.take() {
+            link.task.abort();
+            // the stream of the old primary may have selected any database
+            state.selected_db = None;
+            state.replid = new_replid();
+            let offset = state.offset;
+            state.backlog.reset(offset);
+        }
+        Ok(())
+    }
+
+    fn set_link_state(&self, link_state:LinkState) {
+        if let Ok(mut state) = self.lock() {
+            if let Some(link) = state.primary.as_mut() {
+                link.state = link_state;
+            }
+        }
+    }
+
+    // replid / offset sent in `PSYNC`
+    fn sync_position(&self) -> IoResult<(String, u64, u16)> {
+        let state = self.lock()?;
+        Ok((state.replid.clone(), state.offset, state.listening_port))
+    }
+
+    // after full synchronization, the replica continues the history of its
+    // primary from the offset the snapshot was taken.
+    fn reset_history(&self, replid:String, offset:u64) {
+        if let Ok(mut state) = self.lock() {
+            state.replid = replid;
+            state.offset = offset;
+            state.backlog.reset(offset);
+        }
+    }
+} // end of Replication
+
+// random enough to tell different histories apart, not for security
+fn new_replid() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .unwrap_or_default().as_nanos();
+    (0 .. 3).map(|i| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        hasher.write_u8(i);
+        format!("{:016x}", hasher.finish())
+    }).collect::<String>()[.. 40].to_string()
+}
+
+async fn primary_link_task(db:FakeDatabase, host:String, port:u16)
+{
+    let repl = db.replication();
+    let mut delay = Duration::from_millis(100);
+    // switched by `SELECT` in the stream, kept across reconnections since
+    // partial synchronization continues the same stream
+    let mut selected = db.select(0);
+    loop {
+        repl.set_link_state(LinkState::Connecting);
+        match sync_with_primary(&db, &mut selected, &host, port).await {
+            Ok(_) => info!(%host, port, "primary closed the link"),
+            Err(e) => warn!(%host, port, "link to primary broken, {}", e),
+        }
+        repl.set_link_state(LinkState::Connect);
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn sync_with_primary(db:&FakeDatabase, selected:&mut FakeDatabase, host:&str,
+                           port:u16) -> AsyncResult<()>
+{
+    let repl = db.replication();
+    let socket = TcpStream::connect((host, port)).await?;
+    let mut conn = Connection::new(socket);
+    let (replid, offset, listening_port) = repl.sync_position()?;
+
+    let cfg = db.config().current()?;
+    let mut handshake = Vec::new();
+    if let Some(password) = cfg.masterauth {
+        let mut auth = vec!["auth".to_string()];
+        auth.extend(cfg.masteruser);
+        auth.push(password);
+        handshake.push(auth);
+    }
+    let num_auth = handshake.len();
+    handshake.push(vec!["replconf".to_string(), "listening-port".to_string(),
+                        listening_port.to_string()]);
+    handshake.push(vec!["psync".to_string(), replid, offset.to_string()]);
+    for args in handshake.iter() {
+        let mut frm = Frame::array();
+        for arg in args {
+            frm.push_bulk(Bytes::from(arg.clone().into_bytes()));
+        }
+        conn.write_frame(&frm).await?;
+    }
+    for name in ["AUTH"].iter().take(num_auth).chain(["REPLCONF"].iter()) {
+        match conn.read_frame().await? {
+            Some(Frame::Simple(s)) if s == "OK" => {},
+            Some(frm) => return Err(format!("unexpected reply to {}, {:?}", name, frm).into()),
+            None => return Ok(()),
+        }
+    }
+    let reply = match conn.read_frame().await? {
+        Some(Frame::Simple(s)) => s,
+        Some(frm) => return Err(format!("unexpected reply to PSYNC, {:?}", frm).into()),
+        None => return Ok(()),
+    };
+    let mut words = reply.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("FULLRESYNC"), Some(replid), Some(offset)) => {
+            repl.set_link_state(LinkState::Sync);
+            let offset:u64 = offset.parse()?;
+            let content = match conn.read_frame().await? {
+                Some(Frame::Bulk(b)) => b,
+                Some(frm) => return Err(format!("unexpected snapshot, {:?}", frm).into()),
+                None => return Ok(()),
+            };
+            let entries = snapshot::decode(&content)?;
+            db.clear()?;
+            let num_loaded = db.restore(entries)?;
+            *selected = db.select(0);
+            repl.reset_history(replid.to_string(), offset);
+            info!(%host, port, num_loaded, "full sync with primary");
+            // commands logged before are about the old data set
+            if db.aof().is_enabled().await {
+                let _ = db.aof().bgrewrite(db);
+            }
+        },
+        (Some("CONTINUE"), _, _) => {
+            info!(%host, port, offset, "partial sync with primary");
+        },
+        _others => return Err(format!("unexpected reply to PSYNC, {}", reply).into()),
+    }
+    repl.set_link_state(LinkState::Connected);
+
+    let mut ack_timer = tokio::time::interval(ACK_INTERVAL);
+    loop {
+        tokio::select! {
+            result = conn.read_frame() => {
+                let frm = match result? {
+                    Some(f) => f,
+                    None => break,
+                };
+                let cmdobj = cmd::from_frame(frm.clone())?;
+                // a rewrite of the append-only file cannot start in between
+                let _writer = db.lock_log().await;
+                cmdobj.replay(selected)?;
+                if cmd::command_name(&frm).as_deref() != Some("select") {
+                    db.aof().append(selected.index(), &frm).await?;
+                }
+                repl.advance(&frm)?;
+            },
+            _ = ack_timer.tick() => {
+                let mut frm = Frame::array();
+                frm.push_bulk(Bytes::from_static(b"replconf"));
+                frm.push_bulk(Bytes::from_static(b"ack"));
+                frm.push_bulk(Bytes::from(repl.offset().to_string().into_bytes()));
+                conn.write_frame(&frm).await?;
+            },
+        }
+    }
+    Ok(())
+} // end of sync_with_primary
@@ -1,22 +1,167 @@
-use tokio::sync::broadcast;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::JoinSet;
+use tracing::warn;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+// what to do with the snapshot when the server stops, see `SHUTDOWN`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveMode {
+    // save the snapshot only if the append-only file is disabled
+    Default,
+    Save,
+    NoSave,
+}
 
 pub struct SingleRequestShutdown {
     is_terminating: bool,
-    notification: broadcast::Receiver<()>
+    notification: broadcast::Receiver<()>,
+    // asks the whole server to stop, `None` if no coordinator
+    server_stop: Option<mpsc::Sender<SaveMode>>,
+    // closes only this connection, e.g. by `CLIENT KILL`
+    kill: Option<Arc<Notify>>,
 }
 
 impl SingleRequestShutdown {
     pub fn new(notify_rx: broadcast::Receiver<()>) -> Self
     {
-        Self{notification:notify_rx, is_terminating:false}
+        Self{notification:notify_rx, is_terminating:false, server_stop:None, kill:None}
+    }
+
+    // `recv()` also resolves once the switch is notified
+    pub fn set_kill_switch(&mut self, kill:Arc<Notify>) {
+        self.kill = Some(kill);
     }
 
     pub fn is_shutdown(&self) -> bool { self.is_terminating }
 
+    // close only the connection owning this instance, e.g. when a command
+    // decides the client should be disconnected
+    pub fn terminate(&mut self) { self.is_terminating = true; }
+
+    // stop the whole server, this connection is closed as well. Returns
+    // `false` if the server cannot be stopped this way.
+    pub fn stop_server(&mut self, mode:SaveMode) -> bool {
+        let sent = match self.server_stop.as_ref() {
+            Some(tx) => tx.try_send(mode).is_ok(),
+            None => false,
+        };
+        if sent {
+            self.is_terminating = true;
+        }
+        sent
+    }
+
     pub async fn recv(&mut self) {
         if !self.is_terminating {
-            let _ = self.notification.recv().await;
+            match self.kill.as_ref() {
+                Some(kill) => tokio::select! {
+                    _ = self.notification.recv() => {},
+                    _ = kill.notified() => {},
+                },
+                None => { let _ = self.notification.recv().await; },
+            }
             self.is_terminating = true;
         }
     }
 } // end of SingleRequestShutdown
+
+// Keeps track of all connection tasks, then on shutdown :
+// - the caller stops accepting new connections
+// - every connection is notified through its `SingleRequestShutdown`
+// - connections still running after the deadline are aborted
+pub struct ShutdownCoordinator {
+    notify: broadcast::Sender<()>,
+    stop_tx: mpsc::Sender<SaveMode>,
+    stop_rx: mpsc::Receiver<SaveMode>,
+    tasks: JoinSet<()>,
+    // registered once, so signals received while the caller is not
+    // waiting are not lost
+    #[cfg(unix)]
+    signals: Option<(Signal, Signal)>,
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self { Self::new() }
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        let (notify, _) = broadcast::channel(1);
+        let (stop_tx, stop_rx) = mpsc::channel(1);
+        Self{ notify, stop_tx, stop_rx, tasks: JoinSet::new(),
+              #[cfg(unix)]
+              signals: None }
+    }
+
+    // handle given to each connection
+    pub fn subscribe(&self) -> SingleRequestShutdown {
+        let mut s = SingleRequestShutdown::new(self.notify.subscribe());
+        s.server_stop = Some(self.stop_tx.clone());
+        s
+    }
+
+    // spawn a connection task which will be waited on shutdown
+    pub fn spawn<F>(&mut self, task:F)
+        where F: Future<Output = ()> + Send + 'static
+    {
+        // forget the tasks already finished, so the set does not grow
+        // with every connection ever accepted
+        while self.tasks.try_join_next().is_some() {}
+        self.tasks.spawn(task);
+    }
+
+    pub fn num_running(&mut self) -> usize {
+        while self.tasks.try_join_next().is_some() {}
+        self.tasks.len()
+    }
+
+    // resolves when `SHUTDOWN` is received, or the process gets ctrl-c
+    // (SIGINT) or SIGTERM. Note it has to be called within Tokio runtime.
+    #[cfg(unix)]
+    pub async fn wait_for_stop(&mut self) -> SaveMode {
+        if self.signals.is_none() {
+            match (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) {
+                (Ok(i), Ok(t)) => { self.signals = Some((i, t)); },
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("failed to register signal handlers, {}", e);
+                },
+            }
+        }
+        match self.signals.as_mut() {
+            Some((interrupt, terminate)) => tokio::select! {
+                mode = self.stop_rx.recv() => mode.unwrap_or(SaveMode::Default),
+                _ = interrupt.recv() => SaveMode::Default,
+                _ = terminate.recv() => SaveMode::Default,
+            },
+            None => self.stop_rx.recv().await.unwrap_or(SaveMode::Default),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub async fn wait_for_stop(&mut self) -> SaveMode {
+        tokio::select! {
+            mode = self.stop_rx.recv() => mode.unwrap_or(SaveMode::Default),
+            _ = tokio::signal::ctrl_c() => SaveMode::Default,
+        }
+    }
+
+    // notify all connections then wait for them, returns number of
+    // connections aborted after the deadline
+    pub async fn drain(&mut self, deadline:Duration) -> usize {
+        let _ = self.notify.send(());
+        let all_done = async {
+            while self.tasks.join_next().await.is_some() {}
+        };
+        if tokio::time::timeout(deadline, all_done).await.is_ok() {
+            return 0;
+        }
+        let num_aborted = self.tasks.len();
+        self.tasks.abort_all();
+        while self.tasks.join_next().await.is_some() {}
+        num_aborted
+    }
+} // end of ShutdownCoordinator
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+pub const DEFAULT_SLOWLOG_THRESHOLD_USEC:i64 = 10_000;
+pub const DEFAULT_SLOWLOG_MAX_LEN:usize = 128;
+
+// same limits as Redis, long commands are cut down so the log does not
+// keep large values alive
+const ENTRY_MAX_ARGC:usize = 32;
+const ENTRY_MAX_STRING:usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    // unix time in seconds when the command was logged
+    pub timestamp: u64,
+    pub duration_usec: u64,
+    pub argv: Vec<Bytes>,
+    // `ip:port` of the client, `unix` for Unix domain socket
+    pub client_addr: String,
+    // set by `CLIENT SETNAME`, empty by default
+    pub client_name: String,
+}
+
+// Commands whose `apply` takes longer than the threshold, newest first.
+// The threshold and maximum length follow `slowlog-log-slower-than` and
+// `slowlog-max-len`, kept here so the hot path never locks the config.
+pub struct SlowLog {
+    // microseconds, negative disables the log, 0 logs every command
+    threshold_usec: AtomicI64,
+    max_len: AtomicUsize,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self{ threshold_usec: AtomicI64::new(DEFAULT_SLOWLOG_THRESHOLD_USEC),
+              max_len: AtomicUsize::new(DEFAULT_SLOWLOG_MAX_LEN),
+              next_id: AtomicU64::new(0), entries: Mutex::new(VecDeque::new()) }
+    }
+}
+
+impl SlowLog {
+    fn lock(&self) -> IoResult<MutexGuard<'_, VecDeque<SlowLogEntry>>> {
+        self.entries.lock().map_err(|_| {
+            IoError::new(ErrorKind::ResourceBusy, "failed to acquire slowlog lock")
+        })
+    }
+
+    pub fn set_threshold(&self, usec:i64) {
+        self.threshold_usec.store(usec, Ordering::Relaxed);
+    }
+
+    // older entries beyond the new length are dropped
+    pub fn set_max_len(&self, max_len:usize) -> IoResult<()> {
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.lock()?.truncate(max_len);
+        Ok(())
+    }
+
+    // arguments of commands are collected only if it is true
+    pub fn is_enabled(&self) -> bool {
+        self.threshold_usec.load(Ordering::Relaxed) >= 0
+    }
+
+    // whether a command running for `elapsed` should be logged
+    pub fn is_slow(&self, elapsed:Duration) -> bool {
+        let threshold = self.threshold_usec.load(Ordering::Relaxed);
+        threshold >= 0 && elapsed.as_micros() as u64 >= threshold as u64
+    }
+
+    // log the command if it ran for at least the threshold, `argv` is the
+    // full command including its name
+    pub fn record(&self, mut argv:Vec<Bytes>, elapsed:Duration, client_addr:String,
+                  client_name:String)
+    {
+        if !self.is_slow(elapsed) {
+            return;
+        }
+        if !redact(&mut argv) {
+            return;
+        }
+        let max_len = self.max_len.load(Ordering::Relaxed);
+        let entry = SlowLogEntry{ id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: unix_time(), duration_usec: elapsed.as_micros() as u64,
+            argv: truncate(argv), client_addr, client_name };
+        if let Ok(mut entries) = self.lock() {
+            entries.push_front(entry);
+            entries.truncate(max_len);
+        }
+    }
+
+    // `SLOWLOG GET`, `None` returns all entries
+    pub fn get(&self, count:Option<usize>) -> IoResult<Vec<SlowLogEntry>> {
+        let entries = self.lock()?;
+        let count = count.unwrap_or(entries.len());
+        Ok(entries.iter().take(count).cloned().collect())
+    }
+
+    pub fn len(&self) -> IoResult<usize> {
+        Ok(self.lock()?.len())
+    }
+
+    pub fn is_empty(&self) -> IoResult<bool> {
+        Ok(self.lock()?.is_empty())
+    }
+
+    pub fn reset(&self) -> IoResult<()> {
+        self.lock()?.clear();
+        Ok(())
+    }
+} // end of SlowLog
+
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Hide secrets from the log, returns false if the command should not be
+// logged at all. Passwords appear in `AUTH`, in rules of `ACL SETUSER`
+// and in values of a few parameters of `CONFIG SET`.
+pub(crate) fn redact(argv:&mut [Bytes]) -> bool {
+    let lower = |b:&Bytes| String::from_utf8_lossy(b).to_lowercase();
+    let name = argv.first().map(lower).unwrap_or_default();
+    let sub = argv.get(1).map(lower).unwrap_or_default();
+    let redacted = Bytes::from_static(b"(redacted)");
+    match (name.as_str(), sub.as_str()) {
+        ("auth", _) => return false,
+        ("acl", "setuser") => {
+            argv.iter_mut().skip(3).for_each(|a| *a = redacted.clone());
+        },
+        ("config", "set") => {
+            for idx in (2 .. argv.len()).step_by(2) {
+                let param = lower(&argv[idx]);
+                if param == "requirepass" || param == "masterauth" {
+                    if let Some(v) = argv.get_mut(idx + 1) {
+                        *v = redacted.clone();
+                    }
+                }
+            }
+        },
+        _others => {},
+    }
+    true
+}
+
+fn truncate(mut argv:Vec<Bytes>) -> Vec<Bytes> {
+    if argv.len() > ENTRY_MAX_ARGC {
+        let more = argv.len() - ENTRY_MAX_ARGC + 1;
+        argv.truncate(ENTRY_MAX_ARGC - 1);
+        argv.push(Bytes::from(format!("... ({} more arguments)", more)));
+    }
+    argv.into_iter().map(|a| {
+        if a.len() > ENTRY_MAX_STRING {
+            let mut s = a.slice(.. ENTRY_MAX_STRING).to_vec();
+            s.extend_from_slice(format!("... ({} more bytes)", a.len() - ENTRY_MAX_STRING).as_bytes());
+            Bytes::from(s)
+        } else {
+            a
+        }
+    }).collect()
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// upper bounds (microseconds) of buckets in latency histogram of each
+// command, the last bucket has no upper bound
+pub const LATENCY_BUCKETS_USEC:[u64; 12] = [
+    10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000, 100_000,
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandStats {
+    pub calls: u64,
+    // total time spent in the command
+    pub usec: u64,
+    // refused before the command runs, e.g. by ACL or cluster redirection
+    pub rejected_calls: u64,
+    // number of calls in each bucket of `LATENCY_BUCKETS_USEC`, plus the
+    // last one for anything slower
+    pub latency_buckets: [u64; LATENCY_BUCKETS_USEC.len() + 1],
+}
+
+impl CommandStats {
+    // calls with their duration recorded, streaming commands are not timed
+    pub fn timed_calls(&self) -> u64 {
+        self.latency_buckets.iter().sum()
+    }
+
+    // estimate of the latency below which `percentile` of calls are, as
+    // upper bound of the bucket, `None` if it falls in the last bucket
+    pub fn percentile_usec(&self, percentile:f64) -> Option<u64> {
+        let target = (self.timed_calls() as f64 * percentile / 100.0).ceil() as u64;
+        let mut seen = 0;
+        for (idx, n) in self.latency_buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target.max(1) {
+                return LATENCY_BUCKETS_USEC.get(idx).copied();
+            }
+        }
+        None
+    }
+}
+
+// counters of the whole server, names follow the `INFO` fields of Redis
+#[derive(Debug)]
+pub struct ServerStats {
+    started_at: Instant,
+    connected_clients: AtomicUsize,
+    total_connections_received: AtomicU64,
+    // accepted then closed immediately because of `maxclients`
+    rejected_connections: AtomicU64,
+    // failures of `accept()` itself, e.g. too many open files
+    accept_errors: AtomicU64,
+    // the last id given to a connection
+    last_client_id: AtomicU64,
+    total_commands_processed: AtomicU64,
+    // keys removed because their time to live passed
+    expired_keys: AtomicU64,
+    // keys removed to free memory
+    evicted_keys: AtomicU64,
+    // replicas served with a snapshot, or from the backlog by `PSYNC`
+    sync_full: AtomicU64,
+    sync_partial_ok: AtomicU64,
+    commands: Mutex<BTreeMap<String, CommandStats>>,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self{ started_at: Instant::now(), connected_clients: AtomicUsize::new(0),
+              total_connections_received: AtomicU64::new(0),
+              rejected_connections: AtomicU64::new(0), accept_errors: AtomicU64::new(0),
+              last_client_id: AtomicU64::new(0), total_commands_processed: AtomicU64::new(0),
+              expired_keys: AtomicU64::new(0), evicted_keys: AtomicU64::new(0),
+              sync_full: AtomicU64::new(0), sync_partial_ok: AtomicU64::new(0),
+              commands: Mutex::new(BTreeMap::new()) }
+    }
+}
+
+impl ServerStats {
+    pub fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+    pub fn total_connections_received(&self) -> u64 {
+        self.total_connections_received.load(Ordering::Relaxed)
+    }
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+    pub fn accept_errors(&self) -> u64 {
+        self.accept_errors.load(Ordering::Relaxed)
+    }
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+    pub fn total_commands_processed(&self) -> u64 {
+        self.total_commands_processed.load(Ordering::Relaxed)
+    }
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+    pub fn sync_full(&self) -> u64 {
+        self.sync_full.load(Ordering::Relaxed)
+    }
+    pub fn sync_partial_ok(&self) -> u64 {
+        self.sync_partial_ok.load(Ordering::Relaxed)
+    }
+    // copy of statistics of all commands called so far, by name
+    pub fn commands(&self) -> BTreeMap<String, CommandStats> {
+        self.commands.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    pub fn incr_expired_keys(&self) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn incr_evicted_keys(&self) {
+        self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn incr_sync(&self, partial:bool) {
+        let counter = if partial { &self.sync_partial_ok } else { &self.sync_full };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // the command `name` ran for `elapsed`, `None` if it is not timed
+    pub fn record_command(&self, name:&str, elapsed:Option<Duration>) {
+        self.total_commands_processed.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut commands) = self.commands.lock() {
+            let stats = commands.entry(name.to_string()).or_default();
+            stats.calls += 1;
+            if let Some(elapsed) = elapsed {
+                let usec = elapsed.as_micros() as u64;
+                let bucket = LATENCY_BUCKETS_USEC.iter().position(|b| usec <= *b)
+                    .unwrap_or(LATENCY_BUCKETS_USEC.len());
+                stats.usec += usec;
+                stats.latency_buckets[bucket] += 1;
+            }
+        }
+    }
+
+    pub fn record_rejected(&self, name:&str) {
+        if let Ok(mut commands) = self.commands.lock() {
+            commands.entry(name.to_string()).or_default().rejected_calls += 1;
+        }
+    }
+
+    // unique for the lifetime of the server, starting from 1
+    pub fn next_client_id(&self) -> u64 {
+        self.last_client_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn incr_accept_errors(&self) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // count a new connection, `None` if there are already `maxclients`
+    // connected clients, the slot is released when the returned value
+    // is dropped.
+    pub fn register_client(self:&Arc<Self>, maxclients:usize) -> Option<ClientSlot> {
+        self.total_connections_received.fetch_add(1, Ordering::Relaxed);
+        let result = self.connected_clients.fetch_update(
+            Ordering::AcqRel, Ordering::Acquire,
+            |n| if n < maxclients { Some(n + 1) } else { None });
+        match result {
+            Ok(_) => Some(ClientSlot{ stats: Arc::clone(self) }),
+            Err(_) => {
+                self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+} // end of ServerStats
+
+pub struct ClientSlot {
+    stats: Arc<ServerStats>,
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.stats.connected_clients.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io::{BufReader, Result as IoResult, Error as IoError, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::{self, RootCertStore, ClientConfig, ServerConfig};
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+pub use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+// whether the server asks TLS clients for their certificates, same values
+// as `tls-auth-clients` of Redis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    // clients without valid certificate are refused
+    Required,
+    // certificate is verified only if the client sends one
+    Optional,
+    No,
+}
+
+impl FromStr for ClientAuth {
+    type Err = IoError;
+    fn from_str(s:&str) -> IoResult<Self> {
+        match s.to_lowercase().as_str() {
+            "yes" => Ok(Self::Required),
+            "optional" => Ok(Self::Optional),
+            "no" => Ok(Self::No),
+            _others => Err(invalid(format!("invalid tls-auth-clients '{}'", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for ClientAuth {
+    fn fmt(&self, f:&mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Required => "yes",
+            Self::Optional => "optional",
+            Self::No => "no",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+fn invalid(detail:String) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, detail)
+}
+
+fn tls_error(e:rustls::Error) -> IoError {
+    IoError::new(ErrorKind::InvalidData, e)
+}
+
+// only `ring` is built in, avoid depending on the process-wide default
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+// all certificates in the PEM file, e.g. certificate chain of the server
+fn load_certs(path:&Path) -> IoResult<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<IoResult<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificate found in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+fn load_key(path:&Path) -> IoResult<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| invalid(format!("no private key found in {}", path.display())))
+}
+
+fn load_roots(path:&Path) -> IoResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(tls_error)?;
+    }
+    Ok(roots)
+}
+
+// `ca_file` is required unless client certificates are not verified
+pub fn acceptor(cert_file:&Path, key_file:&Path, ca_file:Option<&Path>,
+                client_auth:ClientAuth) -> IoResult<TlsAcceptor>
+{
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions().map_err(tls_error)?;
+    let builder = match (client_auth, ca_file) {
+        (ClientAuth::No, _) => builder.with_no_client_auth(),
+        (_, None) => return Err(invalid(
+            "tls-ca-cert-file is required to verify client certificates".to_string())),
+        (auth, Some(ca)) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(load_roots(ca)?), provider());
+            let verifier = if auth == ClientAuth::Optional {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            let verifier = verifier.build().map_err(|e| invalid(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+    };
+    let config = builder.with_single_cert(load_certs(cert_file)?, load_key(key_file)?)
+        .map_err(tls_error)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// the server certificate is verified against `ca_file`, `client_cert` is
+// pair of certificate / key files sent to server which requires it
+pub fn connector(ca_file:&Path, client_cert:Option<(&Path, &Path)>) -> IoResult<TlsConnector>
+{
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions().map_err(tls_error)?
+        .with_root_certificates(load_roots(ca_file)?);
+    let config = match client_cert {
+        Some((cert, key)) => builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(tls_error)?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Result as IoResult, Error as IoError, ErrorKind};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use crate::Frame;
+
+// channel of invalidation messages, a client receives them on its own
+// connection, or on another connection subscribed to the channel with
+// `CLIENT TRACKING ON REDIRECT <id>`
+pub const INVALIDATE_CHANNEL:&str = "__redis__:invalidate";
+
+// keys to drop from the client-side cache, `None` means all of them,
+// e.g. after `FLUSHALL`
+pub type Invalidation = Option<Vec<String>>;
+
+// The message looks like an ordinary pub/sub message, so a connection
+// in subscriber mode receives it as usual. The payload is an array of
+// keys, or null for all keys.
+// ```text
+// [ "message", "__redis__:invalidate", [key, ...] | null ]
+// ```
+pub fn make_invalidation_message(msg:Invalidation) -> Frame
+{
+    let keys = match msg {
+        Some(keys) => Frame::Array(keys.into_iter()
+            .map(|k| Frame::Bulk(Bytes::from(k.into_bytes()))).collect()),
+        None => Frame::Null,
+    };
+    Frame::Array(vec![ Frame::Bulk(Bytes::from_static(b"message")),
+        Frame::Bulk(Bytes::from_static(INVALIDATE_CHANNEL.as_bytes())), keys ])
+}
+
+// options of `CLIENT TRACKING ON`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackingOptions {
+    // id of the connection receiving the invalidation messages
+    pub redirect: Option<u64>,
+    // invalidate keys matching `prefixes` whether the client read them
+    // or not, nothing is remembered per key
+    pub bcast: bool,
+    pub prefixes: Vec<String>,
+    // keys are tracked only if `CLIENT CACHING yes` is sent right before
+    // the read command
+    pub optin: bool,
+    // keys are tracked unless `CLIENT CACHING no` is sent right before
+    // the read command
+    pub optout: bool,
+}
+
+impl TrackingOptions {
+    // error reply for the combination of options Redis refuses
+    pub fn validate(&self) -> Result<(), String> {
+        if self.optin && self.optout {
+            return Err("ERR You can't use OPTIN and OPTOUT at the same time".to_string());
+        }
+        if !self.prefixes.is_empty() && !self.bcast {
+            return Err("ERR PREFIX option requires BCAST mode to be enabled".to_string());
+        }
+        if self.bcast && (self.optin || self.optout) {
+            return Err("ERR OPTIN and OPTOUT are not compatible with BCAST".to_string());
+        }
+        Ok(())
+    }
+
+    fn matches_prefix(&self, key:&str) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p.as_str()))
+    }
+}
+
+#[derive(Default)]
+struct TrackingState {
+    // connections with tracking enabled
+    clients: HashMap<u64, TrackingOptions>,
+    // ids of the clients which read each key, the entry is dropped once
+    // the key is invalidated, clients read it again to be notified again
+    keys: HashMap<String, HashSet<u64>>,
+    // every connection, messages are sent inline if there is no redirect
+    inboxes: HashMap<u64, mpsc::UnboundedSender<Invalidation>>,
+    // connections subscribed to `INVALIDATE_CHANNEL`
+    redirects: HashMap<u64, mpsc::UnboundedSender<Invalidation>>,
+}
+
+impl TrackingState {
+    fn deliver(&mut self, id:u64, msg:Invalidation) {
+        let redirect = match self.clients.get(&id) {
+            Some(opts) => opts.redirect,
+            None => return,
+        };
+        // a redirect target which is not subscribed (any more) simply
+        // drops the message, as in Redis with RESP2
+        match redirect {
+            Some(target) => {
+                let sent = self.redirects.get(&target).map(|tx| tx.send(msg).is_ok());
+                if sent == Some(false) { // the target unsubscribed
+                    self.redirects.remove(&target);
+                }
+            },
+            None => if let Some(tx) = self.inboxes.get(&id) {
+                let _ = tx.send(msg);
+            },
+        }
+    }
+}
+
+// Server side of client-side caching. The table is shared by all the
+// databases, keys are tracked by name as in Redis.
+#[derive(Default)]
+pub struct TrackingTable {
+    state: Mutex<TrackingState>,
+}
+
+impl TrackingTable {
+    fn lock(&self) -> IoResult<MutexGuard<'_, TrackingState>> {
+        self.state.lock().map_err(|_| IoError::new(
+            ErrorKind::ResourceBusy, "failed to acquire tracking lock"))
+    }
+
+    // called once per connection, the inbox is polled along with the
+    // frames from the client, the connection is forgotten when the inbox
+    // is dropped
+    pub fn register(self:&Arc<Self>, id:u64) -> IoResult<TrackingInbox> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.lock()?.inboxes.insert(id, tx);
+        Ok(TrackingInbox{ id, table: Arc::clone(self), rx })
+    }
+
+    // `CLIENT TRACKING ON`, options of a client already tracking are
+    // replaced
+    pub fn enable(&self, id:u64, opts:TrackingOptions) -> IoResult<()> {
+        self.lock()?.clients.insert(id, opts);
+        Ok(())
+    }
+
+    // `CLIENT TRACKING OFF`, keys it read are removed lazily, on their
+    // next invalidation
+    pub fn disable(&self, id:u64) -> IoResult<()> {
+        self.lock()?.clients.remove(&id);
+        Ok(())
+    }
+
+    pub fn options(&self, id:u64) -> IoResult<Option<TrackingOptions>> {
+        Ok(self.lock()?.clients.get(&id).cloned())
+    }
+
+    // number of connections with tracking enabled
+    pub fn num_clients(&self) -> IoResult<usize> {
+        Ok(self.lock()?.clients.len())
+    }
+
+    // number of keys remembered for at least one client
+    pub fn num_keys(&self) -> IoResult<usize> {
+        Ok(self.lock()?.keys.len())
+    }
+
+    // Remember the keys read by the client, `caching` is set by `CLIENT
+    // CACHING` right before the read command. The store calls it while
+    // still holding its lock for the read, so a write applied after the
+    // read always finds the keys and invalidates them.
+    pub fn remember(&self, id:u64, keys:&[&str], caching:Option<bool>) -> IoResult<()> {
+        let mut state = self.lock()?;
+        let tracked = match state.clients.get(&id) {
+            Some(opts) if opts.bcast => false,
+            Some(opts) if opts.optin => caching == Some(true),
+            Some(opts) if opts.optout => caching != Some(false),
+            Some(_) => true,
+            None => false,
+        };
+        if tracked {
+            for k in keys {
+                state.keys.entry(k.to_string()).or_default().insert(id);
+            }
+        }
+        Ok(())
+    }
+
+    // the key was modified, expired or evicted in any of the databases
+    pub fn invalidate(&self, key:&str) {
+        let mut state = match self.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        if state.clients.is_empty() {
+            return;
+        }
+        let mut ids = state.keys.remove(key).unwrap_or_default();
+        ids.extend(state.clients.iter()
+                   .filter(|(_, opts)| opts.bcast && opts.matches_prefix(key))
+                   .map(|(id, _)| *id));
+        for id in ids {
+            state.deliver(id, Some(vec![key.to_string()]));
+        }
+    }
+
+    // the whole keyspace changed, e.g. `FLUSHALL` or `SWAPDB`
+    pub fn invalidate_all(&self) {
+        let mut state = match self.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        state.keys.clear();
+        let ids:Vec<u64> = state.clients.keys().copied().collect();
+        for id in ids {
+            state.deliver(id, None);
+        }
+    }
+
+    // the connection subscribed to `INVALIDATE_CHANNEL`, messages of the
+    // clients redirecting to it come from the returned receiver, until it
+    // is dropped
+    pub fn subscribe_redirect(&self, id:u64) -> IoResult<mpsc::UnboundedReceiver<Invalidation>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.lock()?.redirects.insert(id, tx);
+        Ok(rx)
+    }
+} // end of TrackingTable
+
+pub struct TrackingInbox {
+    id: u64,
+    table: Arc<TrackingTable>,
+    rx: mpsc::UnboundedReceiver<Invalidation>,
+}
+
+impl TrackingInbox {
+    // never returns `None` while the inbox is alive, the table keeps the
+    // sender until then
+    pub async fn recv(&mut self) -> Option<Invalidation> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for TrackingInbox {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.table.lock() {
+            state.clients.remove(&self.id);
+            state.inboxes.remove(&self.id);
+            state.redirects.remove(&self.id);
+        }
+    }
+}
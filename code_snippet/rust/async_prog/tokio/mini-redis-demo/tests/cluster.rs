@@ -0,0 +1,91 @@
+mod common;
+
+use bytes::Bytes;
+use tokio::net::TcpListener;
+
+use mini_redis_demo::{Connection, Frame};
+use mini_redis_demo::clients::ClusterClient;
+
+use common::{Server, free_port, request};
+
+// "bar" is in slot 5061 and "foo" in 12182
+async fn start_cluster() -> (Server, Server) {
+    let (port_a, port_b) = (free_port(), free_port());
+    // config is read at startup only, it is fine to be removed with `a`
+    let dir_a = std::env::temp_dir()
+        .join(format!("mini-redis-test-{}-{}", std::process::id(), port_a));
+    std::fs::create_dir_all(&dir_a).unwrap();
+    let config = dir_a.join("cluster.conf");
+    std::fs::write(&config, format!("slots 0-8191 127.0.0.1:{}\nslots 8192-16383 127.0.0.1:{}\n",
+                                    port_a, port_b)).unwrap();
+    let config = config.to_str().unwrap();
+    let a = Server::start_in(port_a, Some(dir_a.clone()), &["--cluster-config-file", config]).await;
+    // a wildcard bind needs the address other nodes know this one by
+    let b = Server::start_in(port_b, None, &["--cluster-config-file", config, "--bind", "0.0.0.0",
+                                             "--cluster-announce-ip", "127.0.0.1"]).await;
+    (a, b)
+}
+
+// a node missing the latest resharding, it reports all the slots are
+// served by `owner`
+async fn outdated_node(owner:u16) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut conn = Connection::new(socket);
+        while let Ok(Some(_)) = conn.read_frame().await {
+            let node = Frame::Array(vec![Frame::Bulk(Bytes::from("127.0.0.1")),
+                                         Frame::Integer(owner as u64)]);
+            let range = Frame::Array(vec![Frame::Integer(0), Frame::Integer(16383), node]);
+            conn.write_frame(&Frame::Array(vec![range])).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn nodes_redirect_with_moved() {
+    let (a, b) = start_cluster().await;
+    let (mut conn_a, mut conn_b) = (a.connect().await, b.connect().await);
+
+    assert_eq!(request(&mut conn_a, &["set", "bar", "1"]).await, "OK");
+    let moved = request(&mut conn_a, &["set", "foo", "1"]).await;
+    assert!(matches!(moved, Frame::Error(e) if e == format!("MOVED 12182 {}", b.addr())));
+    let moved = request(&mut conn_b, &["get", "bar"]).await;
+    assert!(matches!(moved, Frame::Error(e) if e == format!("MOVED 5061 {}", a.addr())));
+}
+
+#[tokio::test]
+async fn client_follows_moved() {
+    let (a, b) = start_cluster().await;
+
+    let seed = outdated_node(a.port).await;
+    let mut client = ClusterClient::connect(&seed).await.unwrap();
+    assert_eq!(client.node_of("foo"), Some(a.addr().as_str()));
+    // sent to `a` first, which redirects to `b`, then the map is reloaded
+    client.set("foo", Bytes::from("in b")).await.unwrap();
+    assert_eq!(client.node_of("foo"), Some(b.addr().as_str()));
+    assert_eq!(client.node_of("bar"), Some(a.addr().as_str()));
+    client.set("bar", Bytes::from("in a")).await.unwrap();
+    assert_eq!(client.get("foo").await.unwrap(), Some(Bytes::from("in b")));
+    assert_eq!(client.get("bar").await.unwrap(), Some(Bytes::from("in a")));
+
+    let mut conn_b = b.connect().await;
+    assert_eq!(request(&mut conn_b, &["get", "foo"]).await, "in b");
+}
+
+#[tokio::test]
+async fn sharded_channels_redirect_in_stream() {
+    let (a, b) = start_cluster().await;
+    let mut conn = a.connect().await;
+
+    let reply = request(&mut conn, &["ssubscribe", "bar"]).await;
+    assert!(matches!(reply, Frame::Array(items) if matches!(items[2], Frame::Integer(1))));
+    // the connection is streaming messages now, the channel owned by
+    // the other node is still refused
+    let moved = request(&mut conn, &["ssubscribe", "foo"]).await;
+    assert!(matches!(moved, Frame::Error(e) if e == format!("MOVED 12182 {}", b.addr())));
+    let reply = request(&mut conn, &["ssubscribe", "{bar}.more"]).await;
+    assert!(matches!(reply, Frame::Array(items) if matches!(items[2], Frame::Integer(2))));
+}
@@ -0,0 +1,136 @@
+// helpers shared by the integration tests, each test runs its own server
+// processes on free ports, in a temporary directory of each server.
+// Not every test uses all of them.
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+use mini_redis_demo::{Connection, Frame};
+
+// how long a test waits for the servers to reach the expected state
+pub const WAIT_TIMEOUT:Duration = Duration::from_secs(10);
+
+// the port is released before the server binds it, another process could
+// take it meanwhile but that is unlikely in tests
+pub fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+pub struct Server {
+    pub port: u16,
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Server {
+    // the binary `server` with `--port` given, followed by `args`
+    pub async fn start(args:&[&str]) -> Self {
+        Self::start_in(free_port(), None, args).await
+    }
+
+    // `dir` is created if it doesn't exist, a new one is used if `None`,
+    // either way it is removed once the server is dropped
+    pub async fn start_in(port:u16, dir:Option<PathBuf>, args:&[&str]) -> Self {
+        let dir = dir.unwrap_or_else(|| std::env::temp_dir()
+            .join(format!("mini-redis-test-{}-{}", std::process::id(), port)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .current_dir(&dir).arg("--port").arg(port.to_string()).args(args)
+            .stdout(Stdio::null()).stderr(Stdio::null())
+            .spawn().unwrap();
+        let server = Self{ port, child, dir };
+        wait_for_port(port).await;
+        server
+    }
+
+    pub fn addr(&self) -> String { format!("127.0.0.1:{}", self.port) }
+
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    pub async fn connect(&self) -> Connection<TcpStream> {
+        Connection::new(TcpStream::connect(self.addr()).await.unwrap())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub async fn wait_for_port(port:u16) {
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while TcpStream::connect(("127.0.0.1", port)).await.is_err() {
+        assert!(Instant::now() < deadline, "nothing listens on port {}", port);
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+pub fn args_frame(args:&[&str]) -> Frame {
+    Frame::Array(args.iter().map(|a| Frame::Bulk(Bytes::from(a.to_string()))).collect())
+}
+
+// send a command then return the reply as it is
+pub async fn request(conn:&mut Connection<TcpStream>, args:&[&str]) -> Frame {
+    conn.write_frame(&args_frame(args)).await.unwrap();
+    conn.read_frame().await.unwrap().expect("connection closed by server")
+}
+
+// value of the field in `INFO section`
+pub async fn info_field(conn:&mut Connection<TcpStream>, section:&str, name:&str)
+    -> Option<String>
+{
+    let reply = request(conn, &["info", section]).await.to_string();
+    reply.lines().find_map(|line| {
+        line.strip_prefix(name)?.strip_prefix(':').map(|v| v.to_string())
+    })
+}
+
+// poll `GET key` until it returns `expected`, false on timeout
+pub async fn wait_for_value(conn:&mut Connection<TcpStream>, key:&str, expected:&str) -> bool {
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while Instant::now() < deadline {
+        if request(conn, &["get", key]).await == expected {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+// poll `INFO section` until the field has the value, false on timeout
+pub async fn wait_for_info(conn:&mut Connection<TcpStream>, section:&str, name:&str,
+                           expected:&str) -> bool
+{
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while Instant::now() < deadline {
+        if info_field(conn, section, name).await.as_deref() == Some(expected) {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+// poll `DBSIZE` until it returns `expected`, expired keys are counted
+// until they are removed
+pub async fn wait_for_dbsize(conn:&mut Connection<TcpStream>, expected:u64) -> bool {
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while Instant::now() < deadline {
+        if matches!(request(conn, &["dbsize"]).await, Frame::Integer(n) if n == expected) {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
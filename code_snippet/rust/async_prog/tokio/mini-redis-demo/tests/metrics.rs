@@ -0,0 +1,76 @@
+mod common;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use mini_redis_demo::Frame;
+
+use common::{Server, free_port, request, wait_for_port};
+
+async fn start_with_metrics() -> (Server, u16) {
+    let metrics_port = free_port();
+    let server = Server::start(&["--metrics-port", &metrics_port.to_string()]).await;
+    wait_for_port(metrics_port).await;
+    (server, metrics_port)
+}
+
+// the whole response, the server closes the connection after it
+async fn http_request(port:u16, request_line:&str) -> String {
+    let mut socket = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let req = format!("{}\r\nHost: 127.0.0.1\r\n\r\n", request_line);
+    socket.write_all(req.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    socket.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn metrics_in_prometheus_format() {
+    let (server, metrics_port) = start_with_metrics().await;
+    let mut conn = server.connect().await;
+    assert_eq!(request(&mut conn, &["set", "k1", "v1"]).await, "OK");
+    assert_eq!(request(&mut conn, &["get", "k1"]).await, "v1");
+
+    let response = http_request(metrics_port, "GET /metrics HTTP/1.1").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    // the probes waiting for the port may not be closed on server side yet
+    let clients = body.lines().find_map(|l| l.strip_prefix("redis_connected_clients "))
+        .and_then(|v| v.parse::<u64>().ok());
+    assert!(clients.is_some_and(|n| n >= 1), "{}", body);
+    for cmd in ["set", "get"] {
+        let inf = format!("redis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} 1", cmd);
+        assert!(body.lines().any(|l| l == inf), "{}", body);
+        let count = format!("redis_command_duration_seconds_count{{cmd=\"{}\"}} 1", cmd);
+        assert!(body.lines().any(|l| l == count), "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn metrics_other_requests_refused() {
+    let (_server, metrics_port) = start_with_metrics().await;
+    let response = http_request(metrics_port, "GET /other HTTP/1.1").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+    let response = http_request(metrics_port, "POST /metrics HTTP/1.1").await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", response);
+}
+
+#[tokio::test]
+async fn streaming_commands_not_timed() {
+    let (server, metrics_port) = start_with_metrics().await;
+    let mut conn = server.connect().await;
+    request(&mut conn, &["config", "set", "slowlog-log-slower-than", "0"]).await;
+    request(&mut conn, &["subscribe", "news"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    // leaves subscriber mode with no channel
+    request(&mut conn, &["unsubscribe"]).await;
+
+    let response = http_request(metrics_port, "GET /metrics HTTP/1.1").await;
+    assert!(response.lines().any(|l| l == "redis_command_calls_total{cmd=\"subscribe\"} 1"),
+            "{}", response);
+    assert!(!response.contains("redis_command_duration_seconds_count{cmd=\"subscribe\"}"),
+            "{}", response);
+    // only `CONFIG SET` is logged
+    let slowlog_len = request(&mut conn, &["slowlog", "len"]).await;
+    assert!(matches!(slowlog_len, Frame::Integer(1)), "{}", slowlog_len);
+}
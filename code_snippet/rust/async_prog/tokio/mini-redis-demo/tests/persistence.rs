@@ -0,0 +1,54 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+use mini_redis_demo::{Connection, Frame};
+
+use common::{Server, free_port, request, wait_for_dbsize};
+
+const NUM_KEYS:usize = 3000;
+
+// poll `BGREWRITEAOF` until it can start, the previous one is done then
+async fn wait_for_rewrite(conn:&mut Connection<TcpStream>) {
+    for _ in 0 .. 200 {
+        if !matches!(request(conn, &["bgrewriteaof"]).await, Frame::Error(_)) {
+            return;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    panic!("append-only file rewrite not finished");
+}
+
+#[tokio::test]
+async fn keys_moved_during_aof_rewrite() {
+    let server = Server::start(&["--appendonly", "yes"]).await;
+    let mut conn = server.connect().await;
+    let value = "x".repeat(100);
+    for idx in 0 .. NUM_KEYS {
+        assert_eq!(request(&mut conn, &["set", &format!("key{}", idx), &value]).await, "OK");
+    }
+    request(&mut conn, &["bgrewriteaof"]).await;
+    // the last key inserted is dumped last
+    let last = format!("key{}", NUM_KEYS - 1);
+    assert!(matches!(request(&mut conn, &["move", &last, "1"]).await, Frame::Integer(1)));
+    assert_eq!(request(&mut conn, &["swapdb", "0", "2"]).await, "OK");
+    wait_for_rewrite(&mut conn).await;
+
+    // a new server loads a copy of the file
+    let port = free_port();
+    let dir = std::env::temp_dir()
+        .join(format!("mini-redis-test-{}-{}", std::process::id(), port));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy(server.dir().join("appendonly.aof"), dir.join("appendonly.aof")).unwrap();
+    let loaded = Server::start_in(port, Some(dir), &["--appendonly", "yes"]).await;
+    let mut conn = loaded.connect().await;
+    assert!(wait_for_dbsize(&mut conn, 0).await);
+    request(&mut conn, &["select", "1"]).await;
+    assert!(wait_for_dbsize(&mut conn, 1).await);
+    assert_eq!(request(&mut conn, &["get", &last]).await, value.as_str());
+    request(&mut conn, &["select", "2"]).await;
+    assert!(wait_for_dbsize(&mut conn, NUM_KEYS as u64 - 1).await);
+}
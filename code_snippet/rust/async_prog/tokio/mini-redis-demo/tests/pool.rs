@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+use mini_redis_demo::Connection;
+use mini_redis_demo::clients::{Pool, PoolConfig, PoolStatus};
+
+// a server which reads commands but never replies
+async fn silent_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut conn = Connection::new(socket);
+                while let Ok(Some(_)) = conn.read_frame().await {}
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn cancelled_request_closes_connection() {
+    let addr = silent_server().await;
+    let pool = Pool::new(&addr, PoolConfig::default()).await.unwrap();
+    assert_eq!(pool.status().unwrap(), PoolStatus{ idle: 1, in_use: 0 });
+
+    let mut conn = pool.checkout().await.unwrap();
+    assert!(timeout(Duration::from_millis(50), conn.ping(None)).await.is_err());
+    // its reply may still arrive, it would be taken by the next request
+    drop(conn);
+    assert_eq!(pool.status().unwrap(), PoolStatus{ idle: 0, in_use: 0 });
+}
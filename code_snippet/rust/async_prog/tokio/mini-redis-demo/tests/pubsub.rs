@@ -0,0 +1,30 @@
+mod common;
+
+use mini_redis_demo::Client;
+
+use common::{Server, request};
+
+#[tokio::test]
+async fn unsubscribe_all_with_patterns_left() {
+    let server = Server::start(&[]).await;
+    let mut client = Client::connect(server.addr()).await.unwrap();
+    let mut subscriber = client.psubscribe(vec!["news.*".to_string()]).await.unwrap();
+    // no channel to remove, the pattern is still counted in the reply
+    subscriber.unsubscribe(&[]).await.unwrap();
+    subscriber.subscribe(&["weather".to_string()]).await.unwrap();
+    subscriber.punsubscribe(&[]).await.unwrap();
+    assert_eq!(subscriber.get_subscribed(), ["weather".to_string()]);
+    subscriber.unsubscribe(&[]).await.unwrap();
+    assert!(subscriber.next_message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn reset_in_stream_selects_database_0() {
+    let server = Server::start(&[]).await;
+    let mut conn = server.connect().await;
+    assert_eq!(request(&mut conn, &["set", "k1", "in db0"]).await, "OK");
+    assert_eq!(request(&mut conn, &["select", "3"]).await, "OK");
+    request(&mut conn, &["subscribe", "news"]).await;
+    assert_eq!(request(&mut conn, &["reset"]).await, "RESET");
+    assert_eq!(request(&mut conn, &["get", "k1"]).await, "in db0");
+}
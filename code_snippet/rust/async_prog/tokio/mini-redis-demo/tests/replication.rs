@@ -0,0 +1,129 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::time::sleep;
+
+use mini_redis_demo::Frame;
+
+use common::{Server, request, info_field, wait_for_value, wait_for_info, wait_for_dbsize};
+
+#[tokio::test]
+async fn replica_follows_primary() {
+    let primary = Server::start(&[]).await;
+    let primary_port = primary.port.to_string();
+    let replica = Server::start(&["--replicaof", "127.0.0.1", &primary_port]).await;
+    let (mut p, mut r) = (primary.connect().await, replica.connect().await);
+
+    assert_eq!(request(&mut p, &["set", "k1", "v1"]).await, "OK");
+    assert!(wait_for_value(&mut r, "k1", "v1").await);
+    // the stream carries `SELECT` for other databases
+    request(&mut p, &["select", "3"]).await;
+    assert_eq!(request(&mut p, &["set", "k1", "in db3"]).await, "OK");
+    request(&mut r, &["select", "3"]).await;
+    assert!(wait_for_value(&mut r, "k1", "in db3").await);
+    assert_eq!(info_field(&mut r, "replication", "role").await.as_deref(), Some("slave"));
+    assert_eq!(info_field(&mut p, "stats", "sync_full").await.as_deref(), Some("1"));
+    // replicas refuse write commands
+    assert!(matches!(request(&mut r, &["set", "k2", "v2"]).await, Frame::Error(_)));
+    // and replicas of their own
+    assert!(matches!(request(&mut r, &["psync", "?", "-1"]).await, Frame::Error(_)));
+}
+
+#[tokio::test]
+async fn replica_continues_after_reconnect() {
+    let primary = Server::start(&[]).await;
+    let primary_port = primary.port.to_string();
+    let replica = Server::start(&["--replicaof", "127.0.0.1", &primary_port]).await;
+    let (mut p, mut r) = (primary.connect().await, replica.connect().await);
+
+    assert_eq!(request(&mut p, &["set", "k1", "v1"]).await, "OK");
+    assert!(wait_for_value(&mut r, "k1", "v1").await);
+
+    // close the link on the primary side, the replica reconnects with the
+    // history id and offset it reached
+    let clients = request(&mut p, &["client", "list"]).await.to_string();
+    let link_id = clients.lines().find(|l| l.contains("cmd=psync"))
+        .and_then(|l| l.strip_prefix("id="))
+        .and_then(|l| l.split_whitespace().next())
+        .expect("replica link in CLIENT LIST").to_string();
+    let killed = request(&mut p, &["client", "kill", "id", &link_id]).await;
+    assert!(matches!(killed, Frame::Integer(1)));
+    assert_eq!(request(&mut p, &["set", "k2", "v2"]).await, "OK");
+    assert_eq!(request(&mut p, &["set", "k1", "v1 again"]).await, "OK");
+
+    assert!(wait_for_value(&mut r, "k2", "v2").await);
+    assert!(wait_for_value(&mut r, "k1", "v1 again").await);
+    assert!(wait_for_info(&mut p, "stats", "sync_partial_ok", "1").await);
+    // no snapshot was sent the second time
+    assert_eq!(info_field(&mut p, "stats", "sync_full").await.as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn replica_drops_keys_removed_by_primary() {
+    let primary = Server::start(&[]).await;
+    let primary_port = primary.port.to_string();
+    let replica = Server::start(&["--replicaof", "127.0.0.1", &primary_port]).await;
+    let (mut p, mut r) = (primary.connect().await, replica.connect().await);
+
+    assert_eq!(request(&mut p, &["set", "k1", "v1"]).await, "OK");
+    assert_eq!(request(&mut p, &["set", "k2", "v2", "px", "100"]).await, "OK");
+    assert!(wait_for_dbsize(&mut r, 2).await);
+    assert!(matches!(request(&mut p, &["del", "k1", "k3"]).await, Frame::Integer(1)));
+    assert!(wait_for_dbsize(&mut r, 1).await);
+    // expired on access of the primary
+    sleep(Duration::from_millis(150)).await;
+    assert!(matches!(request(&mut p, &["get", "k2"]).await, Frame::Null));
+    assert!(wait_for_dbsize(&mut r, 0).await);
+
+    // evicted to make room for the others
+    request(&mut p, &["config", "set", "maxmemory-policy", "allkeys-random"]).await;
+    request(&mut p, &["config", "set", "maxmemory", "2000"]).await;
+    let value = "x".repeat(200);
+    for idx in 0 .. 30 {
+        assert_eq!(request(&mut p, &["set", &format!("key{}", idx), &value]).await, "OK");
+    }
+    let remaining = match request(&mut p, &["dbsize"]).await {
+        Frame::Integer(n) => n,
+        frm => panic!("unexpected reply {}", frm),
+    };
+    assert!(remaining < 30);
+    assert!(wait_for_dbsize(&mut r, remaining).await);
+}
+
+#[tokio::test]
+async fn expired_keys_removed_without_access() {
+    let primary = Server::start(&["--notify-keyspace-events", "Ex"]).await;
+    let primary_port = primary.port.to_string();
+    let replica = Server::start(&["--replicaof", "127.0.0.1", &primary_port]).await;
+    let (mut p, mut r) = (primary.connect().await, replica.connect().await);
+    let mut sub = primary.connect().await;
+    request(&mut sub, &["subscribe", "__keyevent@0__:expired"]).await;
+
+    assert_eq!(request(&mut p, &["set", "k1", "v1", "px", "100"]).await, "OK");
+    assert_eq!(request(&mut p, &["set", "k2", "v2"]).await, "OK");
+    assert!(wait_for_dbsize(&mut r, 2).await);
+    // nobody reads `k1` after it expires
+    assert!(wait_for_dbsize(&mut p, 1).await);
+    assert!(wait_for_dbsize(&mut r, 1).await);
+    assert_eq!(info_field(&mut p, "stats", "expired_keys").await.as_deref(), Some("1"));
+    let msg = sub.read_frame().await.unwrap().expect("expired event");
+    assert!(matches!(msg, Frame::Array(items) if items[2] == "k1"));
+}
+
+#[tokio::test]
+async fn replica_keeps_offset_on_expired_read() {
+    let primary = Server::start(&[]).await;
+    let primary_port = primary.port.to_string();
+    let replica = Server::start(&["--replicaof", "127.0.0.1", &primary_port]).await;
+    let (mut p, mut r) = (primary.connect().await, replica.connect().await);
+
+    assert_eq!(request(&mut p, &["set", "k1", "v1", "px", "100"]).await, "OK");
+    assert!(wait_for_dbsize(&mut r, 1).await);
+    sleep(Duration::from_millis(150)).await;
+    // expired on the replica too, though only the primary removes it
+    assert!(matches!(request(&mut r, &["get", "k1"]).await, Frame::Null));
+    assert!(wait_for_dbsize(&mut r, 0).await);
+    let offset = info_field(&mut p, "replication", "master_repl_offset").await.unwrap();
+    assert!(wait_for_info(&mut r, "replication", "master_repl_offset", &offset).await);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use bytes::Bytes;
+
+use mini_redis_demo::Client;
+use mini_redis_demo::tls::{self, TlsConnector};
+
+use common::{Server, free_port, wait_for_port};
+
+// server with `tls-auth-clients` set to `auth`, certificates are created
+// by `gen-test-certs.sh` in the directory of the server
+async fn start_tls_server(auth:&str) -> (Server, u16) {
+    let (port, tls_port) = (free_port(), free_port());
+    let dir = std::env::temp_dir()
+        .join(format!("mini-redis-test-{}-{}", std::process::id(), port));
+    let status = Command::new("sh")
+        .arg(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("gen-test-certs.sh"))
+        .arg(&dir).stdout(Stdio::null()).stderr(Stdio::null())
+        .status().expect("gen-test-certs.sh requires openssl");
+    assert!(status.success());
+    let tls_port_arg = tls_port.to_string();
+    let server = Server::start_in(port, Some(dir), &["--tls-port", &tls_port_arg,
+        "--tls-cert-file", "server.crt", "--tls-key-file", "server.key",
+        "--tls-ca-cert-file", "ca.crt", "--tls-auth-clients", auth]).await;
+    wait_for_port(tls_port).await;
+    (server, tls_port)
+}
+
+fn client_connector(server:&Server, with_cert:bool) -> TlsConnector {
+    let dir = server.dir();
+    let client_cert = (dir.join("client.crt"), dir.join("client.key"));
+    let client_cert = with_cert.then_some((client_cert.0.as_path(), client_cert.1.as_path()));
+    tls::connector(&dir.join("ca.crt"), client_cert).unwrap()
+}
+
+#[tokio::test]
+async fn client_with_certificate() {
+    let (server, tls_port) = start_tls_server("yes").await;
+    let connector = client_connector(&server, true);
+    let mut client = Client::connect_tls(("127.0.0.1", tls_port), "localhost", &connector)
+        .await.unwrap();
+    client.set("k1", Bytes::from("over tls")).await.unwrap();
+    assert_eq!(client.get("k1").await.unwrap(), Some(Bytes::from("over tls")));
+
+    // the plain port serves the same data
+    let mut client = Client::connect(server.addr()).await.unwrap();
+    assert_eq!(client.get("k1").await.unwrap(), Some(Bytes::from("over tls")));
+}
+
+#[tokio::test]
+async fn client_without_certificate_refused() {
+    let (server, tls_port) = start_tls_server("yes").await;
+    let connector = client_connector(&server, false);
+    // with TLS 1.3 the client may finish its handshake before the server
+    // checks the certificate, the failure shows up at the first request
+    let connected = Client::connect_tls(("127.0.0.1", tls_port), "localhost", &connector).await;
+    let result = match connected {
+        Ok(mut client) => client.ping(None).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn client_without_certificate_optional() {
+    let (server, tls_port) = start_tls_server("optional").await;
+    let connector = client_connector(&server, false);
+    let mut client = Client::connect_tls(("127.0.0.1", tls_port), "localhost", &connector)
+        .await.unwrap();
+    assert_eq!(client.ping(None).await.unwrap(), Bytes::from("PONG"));
+
+    // a certificate is still accepted
+    let connector = client_connector(&server, true);
+    let mut client = Client::connect_tls(("127.0.0.1", tls_port), "localhost", &connector)
+        .await.unwrap();
+    assert_eq!(client.ping(None).await.unwrap(), Bytes::from("PONG"));
+}